@@ -0,0 +1,85 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use dataman::model::database::Database;
+use rusqlite::{params, Connection, Result};
+
+const ROWS: u32 = 3_000_000;
+/// Rows per page, what the UI asks `Database::get` for.
+const PAGE: u32 = 100;
+
+fn setup_large_database(conn: &mut Connection, num_rows: u32) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name TEXT, age INTEGER)",
+        [],
+    )?;
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare("INSERT INTO test (name, age) VALUES (?, ?)")?;
+        for i in 0..num_rows {
+            // scattered, with a few ties, so sorting by name really reorders the rows
+            let name = format!("name {:07}", i.wrapping_mul(2_654_435_761) % 1_000_000);
+            stmt.execute(params![name, i % 90])?;
+        }
+    }
+    tx.commit()?;
+    // without an index every page, keyset or not, has to sort the whole table
+    conn.execute("CREATE INDEX test_name ON test (name)", [])?;
+    Ok(())
+}
+
+/// Pages through the table sorted by the text column `name` the way the UI does, through
+/// `Database::get`. Paging down or up from a page is a keyset query and costs the same at any
+/// depth, jumping to a row without a page next to it falls back to OFFSET, which grows with the
+/// depth.
+fn benchmark_pagination(c: &mut Criterion) {
+    let mut conn = Connection::open_in_memory().unwrap();
+    setup_large_database(&mut conn, ROWS).unwrap();
+    let mut database = Database::new(conn).unwrap();
+    database.sort_by("name");
+    let depths = [0, ROWS / 2, ROWS - 2 * PAGE];
+
+    let mut group = c.benchmark_group("Database::get sorted by name");
+    group.sample_size(10);
+    for depth in depths {
+        // the first page at this depth is read with OFFSET, after that we page from its keys
+        database.scroll_to(depth);
+        database.get(PAGE, depth, "test".to_string()).unwrap();
+        group.bench_with_input(
+            BenchmarkId::new("page down and up", depth),
+            &depth,
+            |b, &depth| {
+                b.iter(|| {
+                    database.scroll_to(depth + PAGE);
+                    database
+                        .get(PAGE, depth + PAGE, "test".to_string())
+                        .unwrap();
+                    database.scroll_to(depth);
+                    database.get(PAGE, depth, "test".to_string()).unwrap()
+                })
+            },
+        );
+        group.bench_with_input(BenchmarkId::new("jump", depth), &depth, |b, &depth| {
+            b.iter(|| {
+                // forgets the page keys, so nothing is near
+                database.invalidate_cache();
+                database.scroll_to(depth);
+                database.get(PAGE, depth, "test".to_string()).unwrap()
+            })
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("Database::count_rows");
+    group.sample_size(10);
+    group.bench_function("uncached", |b| {
+        b.iter(|| {
+            database.invalidate_cache();
+            database.count_rows()
+        })
+    });
+    database.count_rows();
+    group.bench_function("cached", |b| b.iter(|| database.count_rows()));
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_pagination);
+criterion_main!(benches);
@@ -1,8 +1,8 @@
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
-use dataman::model::regexping::custom_functions::add_custom_functions;
+use dataman::model::background::CustomFunctionAdder;
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::{Connection, Result};
+use rusqlite::Result;
 
 fn setup_large_database(pool: &Pool<SqliteConnectionManager>, num_rows: usize) -> Result<()> {
     let conn = pool.get().unwrap();
@@ -20,16 +20,6 @@ fn setup_large_database(pool: &Pool<SqliteConnectionManager>, num_rows: usize) -
     Ok(())
 }
 
-use r2d2::CustomizeConnection;
-#[derive(Debug)]
-struct CustomFunctionAdder;
-
-impl CustomizeConnection<Connection, rusqlite::Error> for CustomFunctionAdder {
-    fn on_acquire(&self, conn: &mut Connection) -> Result<(), rusqlite::Error> {
-        add_custom_functions(conn)
-    }
-}
-
 fn benchmark_regexp_transform_no_capture_group(c: &mut Criterion) {
     let manager = SqliteConnectionManager::memory();
 
@@ -1,6 +1,11 @@
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
+use std::path::Path;
+
+use crate::model::compression;
+
 use super::direction::Direction;
+use super::message::Severity;
 
 #[derive(Debug, Clone)]
 pub(crate) struct QueuedCommand {
@@ -16,6 +21,26 @@ impl QueuedCommand {
         }
     }
 }
+impl QueuedCommand {
+    /// Whether the command asks for another input after `input`. A regex transform asks for the
+    /// column to transform and then the pattern, saving a workbook or a SQL dump asks which
+    /// tables to save and exporting every table asks for the directory and then the format.
+    pub(crate) fn wants_another_input(&self, input: &str) -> bool {
+        match self.command {
+            Command::RegexTransform => self.inputs.is_empty(),
+            Command::Save => {
+                let path = compression::strip_extension(Path::new(input.trim()));
+                self.inputs.is_empty()
+                    && path
+                        .extension()
+                        .is_some_and(|extension| extension == "xlsx" || extension == "sql")
+            }
+            Command::ExportAll => self.inputs.is_empty(),
+            _ => false,
+        }
+    }
+}
+
 impl Default for QueuedCommand {
     fn default() -> Self {
         QueuedCommand::new(Command::None)
@@ -24,12 +49,51 @@ impl Default for QueuedCommand {
 #[derive(Debug, Clone)]
 pub(crate) struct PreviousCommand {
     pub(crate) command: Command,
-    pub(super) message: Option<String>,
+    pub(crate) message: Option<String>,
+    pub(crate) severity: Severity,
 }
 
 impl PreviousCommand {
+    /// The message of an illegal operation is an error, all others are info until `warn`.
     pub(crate) fn new(command: Command, message: Option<String>) -> Self {
-        Self { command, message }
+        let severity = match command {
+            Command::IllegalOperation => Severity::Error,
+            _ => Severity::Info,
+        };
+        Self {
+            command,
+            message,
+            severity,
+        }
+    }
+
+    /// Sets a message that the command didn't do what was asked, e.g. because it was cancelled.
+    pub(crate) fn warn(&mut self, message: impl Into<String>) {
+        self.message = Some(message.into());
+        self.severity = Severity::Warning;
+    }
+}
+
+impl fmt::Display for PreviousCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.message {
+            Some(message) => write!(f, "{}, {message}", self.command),
+            None => write!(f, "{}", self.command),
+        }
+    }
+}
+
+/// A destructive command that waits for a y or n in the confirmation dialog.
+#[derive(Debug, Clone)]
+pub(crate) struct Confirmation {
+    pub(crate) command: Command,
+    /// What will be lost, e.g. "Drop table data_filt (1,204 rows)?".
+    pub(crate) question: String,
+}
+
+impl Confirmation {
+    pub(crate) fn new(command: Command, question: String) -> Self {
+        Self { command, question }
     }
 }
 
@@ -44,19 +108,35 @@ pub enum Command {
     IllegalOperation,
     Quit,
     Sort,
+    AddSortColumn,
+    ClearSort,
+    CycleCollation,
     Save,
     Move(Direction),
     NextTable,
     PrevTable,
     ExactSearch,
+    FuzzySearch,
+    RegexSearch,
+    SearchNext,
+    SearchPrev,
+    ToggleSearchCase,
+    ToggleSearchScope,
     TextToInt,
     IntToText,
     DeleteColumn,
+    DeleteRow,
     DeleteTable,
     MathOperation,
     RenameTable,
     ToggleMetadataTable,
     RenameColumn,
+    Materialize,
+    ExportAll,
+    WriteBack,
+    /// Asks what to do with unsaved changes before quitting.
+    ConfirmQuit,
+    ToggleMessageHistory,
 }
 
 impl Command {
@@ -67,25 +147,167 @@ impl Command {
             | Command::Edit
             | Command::SqlQuery
             | Command::Sort
+            | Command::AddSortColumn
+            | Command::ClearSort
+            | Command::CycleCollation
             | Command::NextTable
             | Command::PrevTable
             | Command::TextToInt
             | Command::IntToText
             | Command::DeleteColumn
+            | Command::DeleteRow
             | Command::RenameTable
             | Command::RenameColumn
             | Command::ExactSearch
+            | Command::FuzzySearch
+            | Command::RegexSearch
+            | Command::SearchNext
+            | Command::SearchPrev
             | Command::ToggleMetadataTable
             | Command::DeleteTable
             | Command::MathOperation
+            | Command::Materialize
             | Command::RegexFilter => true,
             Command::None
             | Command::IllegalOperation
             | Command::Save
+            | Command::ExportAll
+            | Command::WriteBack
+            | Command::ConfirmQuit
+            | Command::ToggleMessageHistory
+            | Command::ToggleSearchCase
+            | Command::ToggleSearchScope
             | Command::Quit
             | Command::Move(_) => false,
         }
     }
+    /// Commands that change the data, after them cached row counts and page keys are out of date.
+    pub fn is_mutating(&self) -> bool {
+        match self {
+            Command::Copy
+            | Command::RegexTransform
+            | Command::RegexFilter
+            | Command::Edit
+            | Command::SqlQuery
+            | Command::TextToInt
+            | Command::IntToText
+            | Command::DeleteColumn
+            | Command::DeleteRow
+            | Command::DeleteTable
+            | Command::MathOperation
+            | Command::RenameTable
+            | Command::RenameColumn
+            | Command::Materialize => true,
+            Command::None
+            | Command::IllegalOperation
+            | Command::Quit
+            | Command::Sort
+            | Command::AddSortColumn
+            | Command::ClearSort
+            | Command::CycleCollation
+            | Command::Save
+            | Command::ExportAll
+            | Command::WriteBack
+            | Command::ConfirmQuit
+            | Command::ToggleMessageHistory
+            | Command::Move(_)
+            | Command::NextTable
+            | Command::PrevTable
+            | Command::ExactSearch
+            | Command::FuzzySearch
+            | Command::RegexSearch
+            | Command::SearchNext
+            | Command::SearchPrev
+            | Command::ToggleSearchCase
+            | Command::ToggleSearchScope
+            | Command::ToggleMetadataTable => false,
+        }
+    }
+    /// Commands that read or write the whole table, so they have to wait until it has loaded.
+    pub fn requires_full_table(&self) -> bool {
+        match self {
+            Command::Copy
+            | Command::RegexTransform
+            | Command::RegexFilter
+            | Command::Edit
+            | Command::SqlQuery
+            | Command::Sort
+            | Command::AddSortColumn
+            | Command::ClearSort
+            | Command::CycleCollation
+            | Command::Save
+            | Command::ExportAll
+            | Command::WriteBack
+            | Command::ExactSearch
+            | Command::FuzzySearch
+            | Command::RegexSearch
+            | Command::SearchNext
+            | Command::SearchPrev
+            | Command::TextToInt
+            | Command::IntToText
+            | Command::DeleteColumn
+            | Command::DeleteRow
+            | Command::DeleteTable
+            | Command::MathOperation
+            | Command::RenameTable
+            | Command::RenameColumn
+            | Command::Materialize => true,
+            Command::None
+            | Command::IllegalOperation
+            | Command::Quit
+            | Command::ConfirmQuit
+            | Command::ToggleMessageHistory
+            | Command::Move(_)
+            | Command::NextTable
+            | Command::PrevTable
+            | Command::ToggleSearchCase
+            | Command::ToggleSearchScope
+            | Command::ToggleMetadataTable => false,
+        }
+    }
+    /// Commands that ask for input first, they are queued and run once the input is complete.
+    pub fn needs_input(&self) -> bool {
+        matches!(
+            self,
+            Command::RegexTransform
+                | Command::Save
+                | Command::RegexFilter
+                | Command::Edit
+                | Command::ExactSearch
+                | Command::FuzzySearch
+                | Command::RegexSearch
+                | Command::SqlQuery
+                | Command::RenameColumn
+                | Command::MathOperation
+                | Command::ExportAll
+                | Command::RenameTable
+        )
+    }
+    /// Commands that throw data away, they ask for confirmation first unless `--no-confirm` is
+    /// given.
+    pub fn is_destructive(&self) -> bool {
+        matches!(
+            self,
+            Command::DeleteColumn | Command::DeleteRow | Command::DeleteTable
+        )
+    }
+    /// Commands that alter or update the table, a virtual table has to be materialized first.
+    /// Writing back would overwrite the file the virtual table reads.
+    pub fn needs_real_table(&self) -> bool {
+        matches!(
+            self,
+            Command::Copy
+                | Command::RegexTransform
+                | Command::Edit
+                | Command::TextToInt
+                | Command::IntToText
+                | Command::DeleteColumn
+                | Command::DeleteRow
+                | Command::MathOperation
+                | Command::RenameColumn
+                | Command::WriteBack
+        )
+    }
     fn as_str(&self) -> String {
         match self {
             Command::None => "None".to_string(),
@@ -97,19 +319,34 @@ impl Command {
             Command::IllegalOperation => "Illegal Operation".to_string(),
             Command::Quit => "Quit".to_string(),
             Command::Sort => "Sort".to_string(),
+            Command::AddSortColumn => "Add Sort Column".to_string(),
+            Command::ClearSort => "Clear Sort".to_string(),
+            Command::CycleCollation => "Cycle Collation".to_string(),
             Command::Save => "Save".to_string(),
             Command::Move(dir) => format!("Move {}", dir),
             Command::NextTable => "Next Table".to_string(),
             Command::PrevTable => "Prev Table".to_string(),
             Command::ExactSearch => "Exact Search".to_string(),
+            Command::FuzzySearch => "Fuzzy Search".to_string(),
+            Command::RegexSearch => "Regex Search".to_string(),
+            Command::SearchNext => "Search Next".to_string(),
+            Command::SearchPrev => "Search Previous".to_string(),
+            Command::ToggleSearchCase => "Toggle Search Ignore Case".to_string(),
+            Command::ToggleSearchScope => "Toggle Search All Columns".to_string(),
             Command::TextToInt => "Text to Int".to_string(),
             Command::IntToText => "Int to Text".to_string(),
             Command::DeleteColumn => "Delete Column".to_string(),
+            Command::DeleteRow => "Delete Row".to_string(),
             Command::MathOperation => "Math Operation".to_string(),
             Command::DeleteTable => "Delete Table".to_string(),
             Command::RenameTable => "Rename Table".to_string(),
             Command::RenameColumn => "Rename Column".to_string(),
             Command::ToggleMetadataTable => "Showing table of tables(metadata)".to_string(),
+            Command::Materialize => "Materialize".to_string(),
+            Command::ExportAll => "Export All".to_string(),
+            Command::WriteBack => "Write Back".to_string(),
+            Command::ConfirmQuit => "Quit".to_string(),
+            Command::ToggleMessageHistory => "Messages".to_string(),
         }
     }
 }
@@ -129,8 +366,18 @@ impl From<KeyEvent> for Command {
                 }
                 Command::Move(Direction::from(key_event.code))
             }
-            KeyCode::Char('w') => Command::Sort,
+            KeyCode::Char('w') => {
+                if key_event.modifiers.contains(KeyModifiers::CONTROL) {
+                    Command::WriteBack
+                } else {
+                    Command::Sort
+                }
+            }
+            KeyCode::Char('S') => Command::AddSortColumn,
+            KeyCode::Char('W') => Command::ClearSort,
+            KeyCode::Char('o') => Command::CycleCollation,
             KeyCode::Char('a') => Command::Save,
+            KeyCode::Char('E') => Command::ExportAll,
             KeyCode::Char('q') => Command::SqlQuery,
             KeyCode::Char('f') => Command::RegexFilter,
             KeyCode::Char('c') => {
@@ -147,15 +394,23 @@ impl From<KeyEvent> for Command {
                     Command::Sort
                 }
             }
-            KeyCode::Char('/') => Command::ExactSearch,
+            KeyCode::Char('/') => Command::FuzzySearch,
+            KeyCode::Char('=') => Command::ExactSearch,
+            KeyCode::Char('?') => Command::RegexSearch,
+            KeyCode::Char('n') => Command::SearchNext,
+            KeyCode::Char('N') => Command::SearchPrev,
+            KeyCode::Char('i') => Command::ToggleSearchCase,
+            KeyCode::Char('A') => Command::ToggleSearchScope,
             KeyCode::Char('#') => Command::TextToInt,
             KeyCode::Char('$') => Command::IntToText,
             KeyCode::Char('X') => Command::DeleteColumn,
             KeyCode::Char('D') => Command::DeleteTable,
+            KeyCode::Char('d') => Command::DeleteRow,
             KeyCode::Char('r') => Command::RenameColumn,
             KeyCode::Char('R') => Command::RenameTable,
             KeyCode::Char('m') => Command::MathOperation,
             KeyCode::Char('M') => Command::ToggleMetadataTable,
+            KeyCode::Char('H') => Command::ToggleMessageHistory,
             KeyCode::Char(c) => {
                 log::info!("clicked: {c}");
                 Command::None
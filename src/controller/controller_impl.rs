@@ -1,28 +1,50 @@
-use crate::app_error_other;
-use crate::controller::command::{Command, PreviousCommand};
+use crate::controller::command::{Command, Confirmation, PreviousCommand};
 use crate::controller::direction::Direction;
 use crate::controller::input::{self, InputMode};
 use crate::error::{AppError, AppResult};
-use crate::model::converter::save_to_csv_file;
-use crate::model::database::Database;
+use crate::model::compression::{self, Compression};
+use crate::model::database::{backup_path, Database, EXPORT_FORMATS, SAVE_FORMATS};
 use crate::model::datarow::DataTable;
+use crate::model::search::SearchKind;
 use crate::tui::TUI;
+use crate::{app_error_other, CONFIG};
 use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
 use super::command::QueuedCommand;
 use super::input::StateMachine;
 
 const POLL_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(3000);
+/// Redraw often while a job runs or a file loads, so the progress moves.
+const JOB_POLL_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(100);
+/// Messages that page up and page down scroll in the history popup.
+const HISTORY_PAGE: isize = 10;
 
 #[derive(Debug)]
 pub struct Controller {
     pub(crate) database: Database,
+    /// Set when the app should quit, the run loop stops before it waits for the next key.
+    quitting: bool,
+    /// Quit once the save that was asked for on quit has succeeded.
+    quit_after_save: bool,
+    /// The current table is written to stdout on quit, so quitting doesn't lose the changes.
+    pipe: bool,
 }
 
 impl Controller {
     pub fn new(database: Database) -> Self {
-        Self { database }
+        Self {
+            database,
+            quitting: false,
+            quit_after_save: false,
+            pipe: false,
+        }
+    }
+
+    /// For `--pipe`, the current table is written to stdout after quitting.
+    pub fn set_pipe(&mut self, pipe: bool) {
+        self.pipe = pipe;
     }
 
     pub(crate) fn save_to_sqlite_file(&self) -> AppResult<()> {
@@ -39,7 +61,7 @@ impl Controller {
         }
     }
 
-    pub(crate) fn sql_query(&self, inputs: Vec<String>) -> Result<(), AppError> {
+    pub(crate) fn sql_query(&mut self, inputs: Vec<String>) -> Result<(), AppError> {
         let query = inputs[0].to_owned();
         self.database.sql_query(&query)
     }
@@ -68,19 +90,53 @@ impl Controller {
     }
 
     fn submit_message(&mut self) {
+        let overwrites = self.export_overwrites();
         if let Some(queued_command) = &mut self.database.queued_command {
-            if queued_command.command != Command::RegexTransform || queued_command.inputs.len() == 1
-            {
+            if overwrites.is_empty() && !queued_command.wants_another_input(&self.database.input) {
                 self.database
                     .input_mode_state_machine
                     .transition(input::Event::FinishEditing)
                     .unwrap();
+            } else if queued_command.command == Command::Save {
+                self.database.last_command.message =
+                    Some("save all tables or only the current one? (all/current)".to_string());
+            } else if queued_command.inputs.is_empty() {
+                self.database.last_command.message = Some(format!(
+                    "format to export to? ({})",
+                    EXPORT_FORMATS.join(", ")
+                ));
+            } else {
+                self.database.last_command.message = Some(format!(
+                    "overwrite {} existing files in {}? (y/n)",
+                    overwrites.len(),
+                    queued_command.inputs[0]
+                ));
             }
             queued_command.inputs.push(self.database.input.clone());
         }
         self.reset_input();
     }
 
+    /// The files that exporting every table would overwrite, once the directory is known and the
+    /// format is being submitted.
+    fn export_overwrites(&self) -> Vec<PathBuf> {
+        match &self.database.queued_command {
+            Some(queued_command)
+                if queued_command.command == Command::ExportAll
+                    && queued_command.inputs.len() == 1 =>
+            {
+                let dir = Path::new(queued_command.inputs[0].trim());
+                self.database
+                    .export_paths(dir, &self.database.input)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|path| path.exists())
+                    .collect()
+            }
+            _ => vec![],
+        }
+    }
+
     fn reset_input(&mut self) {
         self.database.input.clear();
         self.reset_cursor();
@@ -118,6 +174,7 @@ impl Controller {
                     self.database
                         .input_mode_state_machine
                         .transition(input::Event::AbortEditing)?;
+                    self.database.cancel_incremental_search();
                     self.reset_input();
                     return Ok(());
                 }
@@ -127,9 +184,11 @@ impl Controller {
                     KeyCode::Enter => self.submit_message(),
                     KeyCode::Char(to_insert) => {
                         self.enter_char(to_insert);
+                        self.preview_search();
                     }
                     KeyCode::Backspace => {
                         self.delete_char();
+                        self.preview_search();
                     }
                     KeyCode::Left => {
                         self.move_cursor_left();
@@ -141,6 +200,7 @@ impl Controller {
                         self.database
                             .input_mode_state_machine
                             .transition(input::Event::AbortEditing)?;
+                        self.database.cancel_incremental_search();
                         self.reset_input();
                     }
                     KeyCode::Tab => {
@@ -151,6 +211,7 @@ impl Controller {
                         if let Ok(data) = res {
                             self.database.character_index = data.len();
                             self.database.input = data;
+                            self.preview_search();
                         }
                         self.database
                             .input_mode_state_machine
@@ -164,124 +225,425 @@ impl Controller {
     }
 
     fn normal_mode(&mut self) -> AppResult<()> {
-        if event::poll(POLL_TIMEOUT)? {
+        let timeout = if self.database.is_loading() {
+            JOB_POLL_TIMEOUT
+        } else {
+            POLL_TIMEOUT
+        };
+        if event::poll(timeout)? {
             let res = match if let Event::Key(key) = event::read()? {
                 Ok(Command::from(key))
             } else {
                 Err(app_error_other!("Could not poll"))
             } {
-                Ok(command) => {
-                    self.database.last_command = PreviousCommand::new(command.clone(), None);
-                    let result = match command {
-                        Command::RegexTransform
-                        | Command::Save
-                        | Command::RegexFilter
-                        | Command::Edit
-                        | Command::ExactSearch
-                        | Command::SqlQuery
-                        | Command::RenameColumn
-                        | Command::MathOperation
-                        | Command::RenameTable => {
-                            self.database.queued_command =
-                                Some(QueuedCommand::new(command.clone()));
-                            self.database
-                                .input_mode_state_machine
-                                .transition(input::Event::StartEditing)?;
-                            Ok(())
-                        }
-                        Command::Quit => Ok(()),
-                        Command::Copy => self.copy(),
-                        Command::IllegalOperation => Ok(()),
-                        Command::None => Ok(()),
-                        Command::Sort => self.sort(),
-                        Command::Move(direction) => self.database.move_cursor(direction),
-                        Command::NextTable => self.database.next_table(),
-                        Command::PrevTable => self.database.prev_table(),
-                        Command::TextToInt => self.text_to_int(),
-                        Command::IntToText => self.int_to_text(),
-                        Command::DeleteColumn => self.delete_column(),
-                        Command::DeleteTable => self.database.delete_table(),
-                        Command::ToggleMetadataTable => self.database.view_metadata_table(),
-                        // Command::Join(_) => todo!(),
-                    };
-                    match command {
-                        Command::RenameTable => {
-                            let old_table_name = self.database.get_current_table_name()?;
-                            self.database.character_index = old_table_name.len();
-                            self.database.input = old_table_name;
-                        }
-
-                        Command::RenameColumn => {
-                            log::info!("testing 2");
-                        }
-                        _ => {}
-                    }
-
-                    if command.requires_updating_view() {
-                        self.database.slice.has_changed();
-                    }
-                    if let Err(err) = &result {
-                        log::info!("Error: {:?}", err);
-                    }
-                    result
-                }
-                Err(result) => {
-                    self.database.slice.has_changed();
-
-                    self.database.last_command =
-                        PreviousCommand::new(Command::IllegalOperation, Some(result.to_string()));
+                Ok(command) if self.database.is_loading() && command.requires_full_table() => {
+                    self.database.last_command = PreviousCommand::new(
+                        Command::IllegalOperation,
+                        Some(format!("{command} has to wait until the table has loaded")),
+                    );
                     Ok(())
                 }
+                Ok(command) if command.is_destructive() && CONFIG.confirm => {
+                    self.ask_confirmation(command)
+                }
+                Ok(command) => self.run_command(command),
+                Err(err) => Err(err),
             };
             if let Err(e) = res {
-                self.database.slice.has_changed();
-                self.database.last_command =
-                    PreviousCommand::new(Command::IllegalOperation, Some(format!(": {}", e)));
+                self.show_error(e);
+            }
+        }
+        Ok(())
+    }
+    /// Runs a command, or offers to materialize the table first if it needs a real one.
+    fn run_command(&mut self, command: Command) -> AppResult<()> {
+        if command.needs_real_table() && self.database.is_virtual_table().unwrap_or(false) {
+            self.offer_materialize(command)
+        } else {
+            self.dispatch(command)
+        }
+    }
+
+    fn ask_confirmation(&mut self, command: Command) -> AppResult<()> {
+        let question = self.database.confirmation_question(&command)?;
+        self.database.confirmation = Some(Confirmation::new(command, question));
+        Ok(())
+    }
+
+    /// The confirmation dialog runs its command on y, any other key cancels it.
+    fn confirm_mode(&mut self) -> AppResult<()> {
+        if !event::poll(POLL_TIMEOUT)? {
+            return Ok(());
+        }
+        let Event::Key(key) = event::read()? else {
+            return Ok(());
+        };
+        let Some(confirmation) = self.database.confirmation.take() else {
+            return Ok(());
+        };
+        let result = if matches!(key.code, KeyCode::Char('y' | 'Y')) {
+            self.run_command(confirmation.command)
+        } else {
+            // a command waiting for the table to be materialized is cancelled with it
+            self.database.pending_command = None;
+            self.database.last_command = PreviousCommand::new(confirmation.command, None);
+            self.database.last_command.warn("cancelled");
+            Ok(())
+        };
+        if let Err(e) = result {
+            self.database.pending_command = None;
+            self.show_error(e);
+        }
+        Ok(())
+    }
+
+    /// Runs a command from a key press, commands that need input are queued instead.
+    fn dispatch(&mut self, command: Command) -> AppResult<()> {
+        self.database.last_command = PreviousCommand::new(command.clone(), None);
+        let result = match command {
+            Command::RegexTransform
+            | Command::Save
+            | Command::RegexFilter
+            | Command::Edit
+            | Command::ExactSearch
+            | Command::FuzzySearch
+            | Command::RegexSearch
+            | Command::SqlQuery
+            | Command::RenameColumn
+            | Command::MathOperation
+            | Command::ExportAll
+            | Command::RenameTable => {
+                self.database.queued_command = Some(QueuedCommand::new(command.clone()));
+                self.database
+                    .input_mode_state_machine
+                    .transition(input::Event::StartEditing)?;
+                Ok(())
             }
+            Command::Quit | Command::ConfirmQuit => self.quit(),
+            Command::Copy => self.copy(),
+            Command::IllegalOperation => Ok(()),
+            Command::None => Ok(()),
+            Command::Sort => self.sort(),
+            Command::AddSortColumn => self.database.add_sort_column(),
+            Command::CycleCollation => self.cycle_collation(),
+            Command::ClearSort => {
+                self.database.clear_sort();
+                Ok(())
+            }
+            Command::SearchNext => self.search_next(true),
+            Command::SearchPrev => self.search_next(false),
+            Command::ToggleSearchCase => self.toggle_search_case(),
+            Command::ToggleSearchScope => self.toggle_search_scope(),
+            Command::Move(direction) => self.database.move_cursor(direction),
+            Command::NextTable => self.database.next_table(),
+            Command::PrevTable => self.database.prev_table(),
+            Command::TextToInt => self.text_to_int(),
+            Command::IntToText => self.int_to_text(),
+            Command::DeleteColumn => self.delete_column(),
+            Command::DeleteRow => self.delete_row(),
+            Command::DeleteTable => self.database.delete_table(),
+            Command::ToggleMetadataTable => self.database.view_metadata_table(),
+            Command::Materialize => self.database.materialize_table(),
+            Command::WriteBack => self.write_back(),
+            Command::ToggleMessageHistory => {
+                self.database.messages.toggle();
+                Ok(())
+            } // Command::Join(_) => todo!(),
+        };
+        match command {
+            Command::FuzzySearch => self.database.start_incremental_search(),
+            Command::RenameTable => {
+                let old_table_name = self.database.get_current_table_name()?;
+                self.database.character_index = old_table_name.len();
+                self.database.input = old_table_name;
+            }
+
+            Command::RenameColumn => {
+                log::info!("testing 2");
+            }
+            Command::ExportAll => {
+                self.database.last_command.message =
+                    Some("directory to export every table to".to_string());
+            }
+            _ => {}
+        }
+
+        if command.requires_updating_view() {
+            self.database.slice.has_changed();
+        }
+        if command.is_mutating() {
+            self.database.invalidate_cache();
+        }
+        match &result {
+            // a command that was only queued has changed nothing yet, execute_queued_command
+            // marks the table once it has run
+            Ok(()) if command.needs_input() => {}
+            Ok(()) => self.database.mark_modified(&command),
+            Err(err) => log::info!("Error: {:?}", err),
         }
+        result
+    }
+
+    /// Asks whether to copy the virtual table into SQLite, `command` runs when that is done.
+    fn offer_materialize(&mut self, command: Command) -> AppResult<()> {
+        let table_name = self.database.get_current_table_name()?;
+        let question =
+            format!("{command} needs a real table, copy {table_name} into SQLite first?");
+        self.database.pending_command = Some(command);
+        self.database.confirmation = Some(Confirmation::new(Command::Materialize, question));
         Ok(())
     }
+
+    fn save(&mut self, inputs: Vec<String>) -> AppResult<()> {
+        let filename = inputs[0].as_str();
+        let path = PathBuf::from(filename);
+        let is_compressed = Compression::from_extension(&path).is_some();
+        let all_tables = inputs
+            .get(1)
+            .is_some_and(|answer| answer.trim().starts_with('a'));
+        let quit_after_save = std::mem::take(&mut self.quit_after_save);
+        let table_name = self.database.get_current_table_name()?;
+        let result = match compression::strip_extension(&path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            None => Err(AppError::unsupported_format("", SAVE_FORMATS)),
+            Some("sqlite" | "db" | "sqlite3") if is_compressed => Err(AppError::from(
+                "Only csv, json, markdown, html and sql files can be saved compressed",
+            )),
+            Some("xlsx") if !is_compressed => self.database.save_to_xlsx(&path, all_tables),
+            Some("sql") => self.database.save_to_sql(&path, all_tables),
+            Some("sqlite" | "db" | "sqlite3") => self
+                .database
+                .backup_db(path)
+                .and_then(|()| self.database.get_table_names()),
+            _ => self
+                .database
+                .export_table(&table_name, &path)
+                .map(|()| vec![table_name]),
+        };
+        self.database.mark_saved(&result?);
+        if quit_after_save && self.database.is_modified() {
+            self.database
+                .last_command
+                .warn("other tables have unsaved changes, did not quit");
+        } else {
+            self.quitting = quit_after_save;
+        }
+        Ok(())
+    }
+
+    fn write_back(&mut self) -> AppResult<()> {
+        let table_name = self.database.get_current_table_name()?;
+        let (path, table_names) = self.database.write_back()?;
+        self.database.mark_saved(&table_names);
+        self.database.last_command.message = Some(format!(
+            "wrote {table_name} to {}, the old file is {}",
+            path.display(),
+            backup_path(&path).display()
+        ));
+        Ok(())
+    }
+
+    fn export_all(&mut self, inputs: Vec<String>) -> AppResult<()> {
+        let dir = PathBuf::from(inputs[0].trim());
+        let extension = inputs.get(1).map_or("csv", |extension| extension.trim());
+        if let Some(answer) = inputs.get(2) {
+            if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+                self.database.last_command.warn("nothing was exported");
+                return Ok(());
+            }
+        }
+        let paths = self.database.export_all(&dir, extension)?;
+        let table_names = self.database.get_table_names()?;
+        self.database.mark_saved(&table_names);
+        let file_names = paths
+            .iter()
+            .filter_map(|path| path.file_name())
+            .map(|name| name.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.database.last_command.message = Some(format!(
+            "wrote {} files to {}: {file_names}",
+            paths.len(),
+            dir.display()
+        ));
+        Ok(())
+    }
+
+    /// Quits, or asks what to do with the changes first if they haven't been saved.
+    fn quit(&mut self) -> AppResult<()> {
+        if !self.database.is_modified() || self.pipe {
+            self.quitting = true;
+            return Ok(());
+        }
+        self.database.last_command = PreviousCommand::new(
+            Command::ConfirmQuit,
+            Some("there are unsaved changes, save, discard or cancel? (s/d/c)".to_string()),
+        );
+        self.database.queued_command = Some(QueuedCommand::new(Command::ConfirmQuit));
+        self.database
+            .input_mode_state_machine
+            .transition(input::Event::StartEditing)?;
+        Ok(())
+    }
+
+    fn confirm_quit(&mut self, inputs: Vec<String>) -> AppResult<()> {
+        let answer = inputs.first().map(|input| input.trim().to_lowercase());
+        match answer.as_deref() {
+            Some("s" | "save") => {
+                // the save asks for a file name once this input is done
+                self.quit_after_save = true;
+                self.database.pending_command = Some(Command::Save);
+            }
+            Some("d" | "discard") => self.quitting = true,
+            _ => self.database.last_command.warn("did not quit"),
+        }
+        Ok(())
+    }
+
+    /// Runs the command that was waiting for its table to be materialized.
+    fn resume_pending_command(&mut self) {
+        if let Some(command) = self.database.pending_command.take() {
+            if let Err(err) = self.dispatch(command) {
+                self.show_error(err);
+            }
+        }
+    }
+
+    /// The history popup scrolls with the arrow and page keys, Esc, q or H closes it.
+    fn history_mode(&mut self) -> AppResult<()> {
+        if !event::poll(POLL_TIMEOUT)? {
+            return Ok(());
+        }
+        let Event::Key(key) = event::read()? else {
+            return Ok(());
+        };
+        let messages = &mut self.database.messages;
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => messages.scroll_by(1),
+            KeyCode::Down | KeyCode::Char('j') => messages.scroll_by(-1),
+            KeyCode::PageUp => messages.scroll_by(HISTORY_PAGE),
+            KeyCode::PageDown => messages.scroll_by(-HISTORY_PAGE),
+            KeyCode::Home => messages.scroll_by(isize::MAX),
+            KeyCode::End => messages.scroll = 0,
+            KeyCode::Esc | KeyCode::Char('q' | 'H') => messages.toggle(),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Only ctrl-c, to cancel the job, does anything while a job runs on the worker thread.
+    fn busy_mode(&mut self) -> AppResult<()> {
+        if event::poll(JOB_POLL_TIMEOUT)? {
+            if let Event::Key(key) = event::read()? {
+                if Command::from(key) == Command::Quit {
+                    self.database.cancel_job();
+                } else {
+                    self.database.last_command.message =
+                        Some("busy, wait or press ctrl-c to cancel".to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Shows the error on the message line, a command the user cancelled is only a warning.
+    fn show_error(&mut self, err: AppError) {
+        log::info!("Error: {:?}", err);
+        self.database.slice.has_changed();
+        match err {
+            AppError::UserCancelled => self.database.last_command.warn(err.to_string()),
+            err => {
+                self.database.last_command =
+                    PreviousCommand::new(Command::IllegalOperation, Some(err.to_string()))
+            }
+        }
+    }
+
+    fn finish_job(&mut self) {
+        match self.database.poll_job() {
+            Some(Ok(message)) => self.database.last_command.message = Some(message),
+            Some(Err(err)) => self.show_error(err),
+            None => {}
+        }
+    }
+
+    fn finish_import(&mut self) {
+        match self.database.poll_import() {
+            Some(Ok(message)) => self.database.last_command.message = Some(message),
+            Some(Err(err)) => self.show_error(err),
+            None => {}
+        }
+    }
+
+    /// Writes the current table as CSV once the file has loaded, for `--pipe`.
+    pub fn write_current_table<W: Write>(&mut self, writer: W) -> AppResult<()> {
+        self.database.wait_for_import()?;
+        self.database.write_current_table(writer)
+    }
+
     pub fn run(&mut self, mut tui: TUI) -> AppResult<()> {
         loop {
+            if self.quitting {
+                tui.shutdown()?;
+                break Ok(());
+            }
+            self.finish_job();
+            self.finish_import();
+            self.database.messages.observe(&self.database.last_command);
             tui.draw(self)?;
             match self.database.input_mode_state_machine.get_state() {
-                InputMode::Finish => {
-                    if let Some(queued_command) = self.database.queued_command.clone() {
-                        self.execute_queued_command()?;
-                        self.database.queued_command = None;
-                        self.database.last_command =
-                            PreviousCommand::new(queued_command.command, None);
-                        self.database
-                            .input_mode_state_machine
-                            .transition(input::Event::Reset)?;
-                    } else {
-                        return Err(app_error_other!("No queued command, but in finish state"));
-                    }
-                }
+                InputMode::Finish => self.finish_input()?,
                 InputMode::Editing if (self.database.queued_command.is_some()) => {
                     let res = self.user_input_mode();
                 }
+                InputMode::Normal if self.database.is_busy() => self.busy_mode()?,
+                InputMode::Normal if self.database.confirmation.is_some() => self.confirm_mode()?,
+                InputMode::Normal if self.database.messages.open => self.history_mode()?,
+                InputMode::Normal if self.database.pending_command.is_some() => {
+                    self.resume_pending_command()
+                }
                 InputMode::Normal => {
                     let res = self.normal_mode();
-                    if self.database.last_command.command == Command::Quit {
-                        tui.shutdown()?;
-                        break Ok(());
-                    }
-                }
-                InputMode::Abort => {
-                    self.database.last_command =
-                        PreviousCommand::new(Command::None, Some("Aborted input".to_string()));
-
-                    self.database.queued_command = None;
-                    self.database
-                        .input_mode_state_machine
-                        .transition(input::Event::Reset)?;
                 }
+                InputMode::Abort => self.abort_input()?,
                 InputMode::ExternalEditor => todo!(),
                 InputMode::Editing => unreachable!(),
             }
         }
     }
+    /// Runs the queued command once its input is complete and goes back to normal mode.
+    fn finish_input(&mut self) -> AppResult<()> {
+        let Some(queued_command) = self.database.queued_command.clone() else {
+            return Err(app_error_other!("No queued command, but in finish state"));
+        };
+        let result = self.execute_queued_command();
+        self.database.queued_command = None;
+        match result {
+            Ok(()) => self.database.last_command.command = queued_command.command,
+            Err(err) => self.show_error(err),
+        }
+        self.database
+            .input_mode_state_machine
+            .transition(input::Event::Reset)?;
+        Ok(())
+    }
+
+    /// Drops the queued command, and whatever was waiting for it, when its input is cancelled.
+    fn abort_input(&mut self) -> AppResult<()> {
+        self.database.last_command = PreviousCommand::new(Command::None, None);
+        self.database.last_command.warn("Aborted input");
+
+        self.database.queued_command = None;
+        self.database.pending_command = None;
+        self.quit_after_save = false;
+        self.database
+            .input_mode_state_machine
+            .transition(input::Event::Reset)?;
+        Ok(())
+    }
+
     pub fn get_headers_and_rows(&mut self, limit: u32) -> AppResult<DataTable> {
         let binding = "default table name".to_string();
         let first_table = self.database.get_current_table_name()?;
@@ -344,19 +706,78 @@ impl Controller {
     pub(crate) fn sort(&mut self) -> AppResult<()> {
         self.database.sort()
     }
-    fn exact_search(&mut self, inputs: Vec<String>) -> AppResult<()> {
-        let pattern = inputs[0].to_owned();
+
+    fn cycle_collation(&mut self) -> AppResult<()> {
+        let collation = self.database.cycle_collation()?;
         let header = self.database.get_current_header()?;
-        match self.database.exact_search(&header, &pattern) {
-            Ok(_) => {
-                self.database.last_command =
-                    PreviousCommand::new(Command::ExactSearch, Some("Match found".to_string()));
-            }
-            Err(_) => {
-                self.database.last_command =
-                    PreviousCommand::new(Command::ExactSearch, Some("No match found".to_string()));
+        self.database.last_command.message = Some(format!("Sorting {header} with {collation}"));
+        Ok(())
+    }
+    fn search(&mut self, inputs: Vec<String>, kind: SearchKind) -> AppResult<()> {
+        let pattern = inputs[0].to_owned();
+        let command = self.database.last_command.command.clone();
+        let message = match self.database.search(&pattern, kind) {
+            Ok(_) => self.database.search.as_ref().map(|search| search.status()),
+            Err(err) => Some(err.to_string()),
+        };
+        self.database.last_command = PreviousCommand::new(command, message);
+        Ok(())
+    }
+
+    fn preview_search(&mut self) {
+        let is_fuzzy_search = self
+            .database
+            .queued_command
+            .as_ref()
+            .is_some_and(|queued_command| queued_command.command == Command::FuzzySearch);
+        if is_fuzzy_search {
+            let pattern = self.database.input.clone();
+            if let Err(err) = self.database.incremental_search(&pattern) {
+                log::info!("Error during incremental search: {:?}", err);
             }
         }
+    }
+
+    fn finish_incremental_search(&mut self, inputs: Vec<String>) -> AppResult<()> {
+        let pattern = inputs[0].to_owned();
+        let message = match self.database.finish_incremental_search(&pattern) {
+            Ok(_) => self.database.search.as_ref().map(|search| search.status()),
+            Err(err) => Some(err.to_string()),
+        };
+        self.database.last_command.message = message;
+        Ok(())
+    }
+
+    fn search_next(&mut self, forward: bool) -> AppResult<()> {
+        let message = match self.database.search_next(forward) {
+            Ok(_) => self.database.search.as_ref().map(|search| search.status()),
+            Err(err) => Some(err.to_string()),
+        };
+        self.database.last_command.message = message;
+        Ok(())
+    }
+
+    fn toggle_search_case(&mut self) -> AppResult<()> {
+        let options = &mut self.database.search_options;
+        options.ignore_case = !options.ignore_case;
+        let message = if options.ignore_case {
+            "Search ignores case"
+        } else {
+            "Search is case sensitive"
+        };
+        self.database.last_command.message = Some(message.to_string());
+        Ok(())
+    }
+
+    fn toggle_search_scope(&mut self) -> AppResult<()> {
+        let options = &mut self.database.search_options;
+        options.all_columns = !options.all_columns;
+        let message = if options.all_columns {
+            "Search in all columns"
+        } else {
+            "Search in current column"
+        };
+        self.database.last_command.message = Some(message.to_string());
         Ok(())
     }
 
@@ -376,6 +797,12 @@ impl Controller {
         Ok(())
     }
 
+    fn delete_row(&mut self) -> Result<(), AppError> {
+        let text = self.database.delete_row()?;
+        self.database.last_command = PreviousCommand::new(Command::DeleteRow, text);
+        Ok(())
+    }
+
     fn rename_column(&mut self, inputs: Vec<String>) -> Result<(), AppError> {
         let new_column = inputs[0].to_owned();
         self.database.rename_column(&new_column)?;
@@ -395,20 +822,12 @@ impl Controller {
                 Command::RegexFilter => self.regex_filter(inputs),
                 Command::RenameColumn => self.rename_column(inputs),
                 Command::RenameTable => self.rename_table(inputs),
-                Command::ExactSearch => self.exact_search(inputs),
-                Command::Save => {
-                    let filename = inputs[0].as_str();
-                    let path = PathBuf::from(filename);
-                    match path.extension().unwrap().to_str().unwrap() {
-                        "csv" => save_to_csv_file(
-                            &self.database.connection,
-                            &self.database.get_current_table_name()?,
-                            &path,
-                        ),
-                        "sqlite" | "db" | "sql" | "sqlite3" => self.database.backup_db(path),
-                        _ => Err(AppError::from("Unsupported file format")),
-                    }
-                }
+                Command::ExactSearch => self.search(inputs, SearchKind::Exact),
+                Command::RegexSearch => self.search(inputs, SearchKind::Regex),
+                Command::FuzzySearch => self.finish_incremental_search(inputs),
+                Command::Save => self.save(inputs),
+                Command::ConfirmQuit => self.confirm_quit(inputs),
+                Command::ExportAll => self.export_all(inputs),
                 Command::MathOperation => self.database.math_operation(inputs),
                 // _ => {
                 //     log::error!("Command not implemented: {:?}", queued_command.command);
@@ -420,12 +839,23 @@ impl Controller {
                 | Command::IllegalOperation
                 | Command::None
                 | Command::Sort
+                | Command::AddSortColumn
+                | Command::ClearSort
+                | Command::CycleCollation
+                | Command::SearchNext
+                | Command::SearchPrev
+                | Command::ToggleSearchCase
+                | Command::ToggleSearchScope
                 | Command::NextTable
                 | Command::PrevTable
                 | Command::TextToInt
                 | Command::IntToText
                 | Command::DeleteTable
                 | Command::ToggleMetadataTable
+                | Command::DeleteRow
+                | Command::WriteBack
+                | Command::Materialize
+                | Command::ToggleMessageHistory
                 | Command::DeleteColumn => {
                     log::error!(
                         "Non-queueable command executed as queued: {:?}",
@@ -434,11 +864,18 @@ impl Controller {
                     unreachable!("This command should not be queueable!!!");
                 }
             };
-            if let Some(queued_command) = self.database.queued_command.as_ref() {
+            if let Some(queued_command) = self.database.queued_command.clone() {
                 if queued_command.command.requires_updating_view() {
                     self.database.slice.has_changed();
                 }
+                if queued_command.command.is_mutating() {
+                    self.database.invalidate_cache();
+                }
+                if result.is_ok() {
+                    self.database.mark_modified(&queued_command.command);
+                }
             }
+            return result;
         }
         Ok(())
     }
@@ -451,3 +888,128 @@ impl Controller {
     }
     // Other methods from the Controller struct
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn setup_controller() -> Controller {
+        Controller::new(Database::try_from(vec![PathBuf::from("assets/data.csv")]).unwrap())
+    }
+
+    /// Types `input` into the prompt and presses enter.
+    fn submit(controller: &mut Controller, input: &str) {
+        controller.database.input = input.to_string();
+        controller.submit_message();
+    }
+
+    fn prompt(controller: &Controller) -> String {
+        controller
+            .database
+            .last_command
+            .message
+            .clone()
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn cancelled_input_is_not_a_change_test() {
+        let mut controller = setup_controller();
+        controller.dispatch(Command::Edit).unwrap();
+        controller
+            .database
+            .input_mode_state_machine
+            .transition(input::Event::AbortEditing)
+            .unwrap();
+        controller.abort_input().unwrap();
+        assert!(!controller.database.is_modified());
+        controller.quit().unwrap();
+        assert!(controller.quitting);
+    }
+
+    #[test]
+    fn rename_then_save_test() {
+        let path = std::env::temp_dir().join(format!("dataman-rename-{}.csv", std::process::id()));
+        let mut controller = setup_controller();
+        controller.dispatch(Command::DeleteRow).unwrap();
+        controller.dispatch(Command::RenameTable).unwrap();
+        assert_eq!(controller.database.input, "data");
+        submit(&mut controller, "people");
+        controller.finish_input().unwrap();
+        assert_eq!(
+            controller.database.modified,
+            HashSet::from(["people".to_string()])
+        );
+
+        controller.dispatch(Command::Save).unwrap();
+        submit(&mut controller, &path.display().to_string());
+        controller.finish_input().unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(!controller.database.is_modified());
+        controller.quit().unwrap();
+        assert!(controller.quitting);
+    }
+
+    #[test]
+    fn save_and_quit_test() {
+        let path = std::env::temp_dir().join(format!("dataman-quit-{}.csv", std::process::id()));
+        let mut controller = setup_controller();
+        controller.dispatch(Command::DeleteRow).unwrap();
+        controller.dispatch(Command::Quit).unwrap();
+        assert!(!controller.quitting);
+        assert_eq!(
+            prompt(&controller),
+            "there are unsaved changes, save, discard or cancel? (s/d/c)"
+        );
+        submit(&mut controller, "s");
+        controller.finish_input().unwrap();
+        // the save asks for its file name next
+        controller.resume_pending_command();
+        submit(&mut controller, &path.display().to_string());
+        controller.finish_input().unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(!controller.database.is_modified());
+        assert!(controller.quitting);
+    }
+
+    #[test]
+    fn export_all_asks_before_overwriting_test() {
+        let dir =
+            std::env::temp_dir().join(format!("dataman-export-prompt-{}", std::process::id()));
+        let dir_input = dir.display().to_string();
+        let mut controller = setup_controller();
+
+        controller.dispatch(Command::ExportAll).unwrap();
+        submit(&mut controller, &dir_input);
+        assert_eq!(
+            prompt(&controller),
+            "format to export to? (csv, json, ndjson, md, html, sql, xlsx, parquet)"
+        );
+        // nothing exists yet, so the format is the last input
+        submit(&mut controller, "csv");
+        assert_eq!(
+            controller.database.input_mode_state_machine.get_state(),
+            InputMode::Finish
+        );
+        controller.finish_input().unwrap();
+        assert!(dir.join("data.csv").exists());
+
+        controller.dispatch(Command::ExportAll).unwrap();
+        submit(&mut controller, &dir_input);
+        submit(&mut controller, "csv");
+        assert_eq!(
+            prompt(&controller),
+            format!("overwrite 1 existing files in {dir_input}? (y/n)")
+        );
+        submit(&mut controller, "n");
+        controller.finish_input().unwrap();
+        assert_eq!(prompt(&controller), "nothing was exported");
+        assert_eq!(
+            controller.database.input_mode_state_machine.get_state(),
+            InputMode::Normal
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
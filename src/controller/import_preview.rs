@@ -0,0 +1,36 @@
+use std::path::Path;
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+
+use crate::error::AppResult;
+use crate::model::csv_options::{CsvOptions, CsvPreview};
+use crate::tui::TUI;
+
+/// Shows the start of the CSV file as it would be imported until the user imports it with enter,
+/// returns None when they quit instead.
+pub fn run(tui: &mut TUI, path: &Path, lenient: bool) -> AppResult<Option<CsvOptions>> {
+    let mut preview = CsvPreview::new(path, lenient)?;
+    let mut column = 0;
+    loop {
+        tui.draw_import_preview(&preview, column)?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('d') => preview.cycle_delimiter(),
+            KeyCode::Char('h') => preview.toggle_headers(),
+            KeyCode::Char('e') => preview.cycle_encoding(),
+            KeyCode::Char('l') => preview.options.lenient = !preview.options.lenient,
+            KeyCode::Char('t') => preview.cycle_column_type(column),
+            KeyCode::Left => column = column.saturating_sub(1),
+            KeyCode::Right => column += 1,
+            KeyCode::Enter => return Ok(Some(preview.options)),
+            KeyCode::Esc | KeyCode::Char('q') => return Ok(None),
+            _ => {}
+        }
+        column = column.min(preview.headers.len().saturating_sub(1));
+    }
+}
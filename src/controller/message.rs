@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+
+use chrono::{Local, NaiveTime};
+
+use super::command::PreviousCommand;
+
+/// How many messages the history keeps, the oldest are dropped first.
+const HISTORY_SIZE: usize = 500;
+
+/// How bad a message is, it decides the colour of the message line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Severity {
+    #[default]
+    Info,
+    /// Nothing went wrong but the command didn't do what was asked, e.g. it was cancelled.
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Message {
+    pub(crate) time: NaiveTime,
+    pub(crate) severity: Severity,
+    pub(crate) text: String,
+}
+
+/// The messages and errors shown on the message line, so they can be read again in the history
+/// popup after the next command has replaced them.
+#[derive(Debug, Default)]
+pub(crate) struct MessageLog {
+    messages: VecDeque<Message>,
+    /// The last command we saw, it is only logged again after it has changed.
+    last_seen: Option<(Severity, String)>,
+    /// Whether the history popup is open.
+    pub(crate) open: bool,
+    /// How many of the newest messages are scrolled out of view below the popup.
+    pub(crate) scroll: usize,
+}
+
+impl MessageLog {
+    /// Logs the message of the last command if it is new. Commands without a message, like
+    /// moving the cursor, aren't logged but do count as a change, so the same error twice in a
+    /// row with a move in between is logged twice.
+    pub(crate) fn observe(&mut self, last_command: &PreviousCommand) {
+        let seen = (last_command.severity, last_command.to_string());
+        if self.last_seen.as_ref() == Some(&seen) {
+            return;
+        }
+        self.last_seen = Some(seen.clone());
+        if last_command.message.is_none() && last_command.severity == Severity::Info {
+            return;
+        }
+        if self.messages.len() == HISTORY_SIZE {
+            self.messages.pop_front();
+        }
+        let (severity, text) = seen;
+        self.messages.push_back(Message {
+            time: Local::now().time(),
+            severity,
+            text,
+        });
+    }
+
+    /// Oldest first.
+    pub(crate) fn messages(&self) -> impl DoubleEndedIterator<Item = &Message> {
+        self.messages.iter()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub(crate) fn toggle(&mut self) {
+        self.open = !self.open;
+        self.scroll = 0;
+    }
+
+    /// Scrolls towards older messages, `lines` is negative to scroll back to the newest.
+    pub(crate) fn scroll_by(&mut self, lines: isize) {
+        let max = self.messages.len().saturating_sub(1);
+        self.scroll = self.scroll.saturating_add_signed(lines).min(max);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::command::Command;
+    use crate::controller::direction::Direction;
+
+    #[test]
+    fn message_log_test() {
+        let mut log = MessageLog::default();
+        let error = PreviousCommand::new(Command::IllegalOperation, Some("no such table".into()));
+        log.observe(&error);
+        // drawn again without a new command
+        log.observe(&error);
+        log.observe(&PreviousCommand::new(Command::Move(Direction::Down), None));
+        log.observe(&error);
+        let mut warning = PreviousCommand::new(Command::Materialize, None);
+        warning.warn("kept the virtual table");
+        log.observe(&warning);
+
+        let messages: Vec<_> = log
+            .messages()
+            .map(|message| (message.severity, message.text.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (Severity::Error, "Illegal Operation, no such table"),
+                (Severity::Error, "Illegal Operation, no such table"),
+                (Severity::Warning, "Materialize, kept the virtual table"),
+            ]
+        );
+
+        log.scroll_by(10);
+        assert_eq!(log.scroll, 2);
+        log.scroll_by(-1);
+        assert_eq!(log.scroll, 1);
+        log.toggle();
+        assert!(log.open);
+        assert_eq!(log.scroll, 0);
+    }
+}
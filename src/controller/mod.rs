@@ -1,4 +1,6 @@
 pub(super) mod command;
 pub mod controller_impl;
 pub(crate) mod direction;
+pub mod import_preview;
 pub(super) mod input;
+pub(super) mod message;
@@ -1,4 +1,5 @@
 use crate::CONFIG;
+use std::path::PathBuf;
 use std::{error::Error, fmt};
 
 pub type AppResult<T> = Result<T, AppError>;
@@ -9,9 +10,88 @@ pub enum AppError {
     Parse(csv::Error),
     Regex(regex::Error),
     Sqlite(rusqlite::Error),
+    Spreadsheet(calamine::Error),
+    /// A file that could not be imported, with the line and column of the bad field when the
+    /// parser knows them. Both start at 1.
+    Import {
+        file: PathBuf,
+        line: Option<u64>,
+        column: Option<u64>,
+        reason: String,
+    },
+    /// A table or column name that can't be used in a query.
+    InvalidIdentifier {
+        name: String,
+        reason: String,
+    },
+    /// A file we can't read or write, the extension tells which format it is.
+    UnsupportedFormat {
+        extension: String,
+        supported: String,
+    },
+    /// A query that SQLite rejected, with the SQL so the user can see what went wrong.
+    QueryFailed {
+        sql: String,
+        source: rusqlite::Error,
+    },
+    /// The user cancelled the command, nothing was changed.
+    UserCancelled,
     Other(Option<String>),
 }
 
+impl AppError {
+    /// The error of a CSV file that could not be parsed, at the line and field of the error.
+    pub(crate) fn import(file: impl Into<PathBuf>, err: csv::Error) -> Self {
+        let line = err.position().map(|position| position.line());
+        let column = match err.kind() {
+            csv::ErrorKind::Utf8 { err, .. } => Some(err.field() as u64 + 1),
+            csv::ErrorKind::UnequalLengths {
+                expected_len, len, ..
+            } => Some((*len).min(*expected_len) + 1),
+            _ => None,
+        };
+        let reason = match err.kind() {
+            csv::ErrorKind::Utf8 { .. } => {
+                "the field is not valid UTF-8, convert the file to UTF-8 first".to_string()
+            }
+            csv::ErrorKind::UnequalLengths {
+                expected_len, len, ..
+            } => {
+                format!("the row has {len} fields but the header has {expected_len}, --lenient pads or skips such rows")
+            }
+            csv::ErrorKind::Io(err) => err.to_string(),
+            _ => err.to_string(),
+        };
+        Self::Import {
+            file: file.into(),
+            line,
+            column,
+            reason,
+        }
+    }
+
+    pub(crate) fn query_failed(sql: &str, source: rusqlite::Error) -> Self {
+        match source {
+            rusqlite::Error::SqliteFailure(err, _)
+                if err.code == rusqlite::ErrorCode::OperationInterrupted =>
+            {
+                Self::UserCancelled
+            }
+            source => Self::QueryFailed {
+                sql: sql.to_string(),
+                source,
+            },
+        }
+    }
+
+    pub(crate) fn unsupported_format(extension: &str, supported: &str) -> Self {
+        Self::UnsupportedFormat {
+            extension: extension.to_string(),
+            supported: supported.to_string(),
+        }
+    }
+}
+
 impl fmt::Display for AppError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -19,6 +99,45 @@ impl fmt::Display for AppError {
             AppError::Parse(err) => write!(f, "Csv parsing error: {}", err),
             AppError::Regex(err) => write!(f, "Regex parsing error: {}", err),
             AppError::Sqlite(err) => write!(f, "Sqlite error: {}", err),
+            AppError::Spreadsheet(err) => write!(f, "Spreadsheet error: {}", err),
+            AppError::Import {
+                file,
+                line,
+                column,
+                reason,
+            } => {
+                write!(f, "Could not import {}", file.display())?;
+                if let Some(line) = line {
+                    write!(f, ", line {line}")?;
+                }
+                if let Some(column) = column {
+                    write!(f, ", column {column}")?;
+                }
+                write!(f, ": {reason}")
+            }
+            AppError::InvalidIdentifier { name, reason } => {
+                write!(f, "Invalid name \"{name}\": {reason}")
+            }
+            AppError::UnsupportedFormat {
+                extension,
+                supported,
+            } if extension.is_empty() => {
+                write!(f, "The file name needs an extension, one of {supported}")
+            }
+            AppError::UnsupportedFormat {
+                extension,
+                supported,
+            } => write!(
+                f,
+                "Unsupported file format .{extension}, use one of {supported}"
+            ),
+            // the SQL is on one line, it is shown on the message line
+            AppError::QueryFailed { sql, source } => write!(
+                f,
+                "Query failed: {source}, in {}",
+                sql.split_whitespace().collect::<Vec<_>>().join(" ")
+            ),
+            AppError::UserCancelled => write!(f, "Cancelled, nothing was changed"),
             AppError::Other(Some(msg)) => write!(f, "Other error: {}", msg),
             AppError::Other(None) => write!(f, "Other error: no msg"),
         }
@@ -32,7 +151,13 @@ impl Error for AppError {
             AppError::Parse(err) => Some(err),
             AppError::Regex(err) => Some(err),
             AppError::Sqlite(err) => Some(err),
-            AppError::Other(_) => Some(self),
+            AppError::Spreadsheet(err) => Some(err),
+            AppError::QueryFailed { source, .. } => Some(source),
+            AppError::Import { .. }
+            | AppError::InvalidIdentifier { .. }
+            | AppError::UnsupportedFormat { .. }
+            | AppError::UserCancelled
+            | AppError::Other(_) => None,
         }
     }
 }
@@ -60,6 +185,26 @@ impl From<rusqlite::Error> for AppError {
     }
 }
 
+impl From<calamine::Error> for AppError {
+    fn from(err: calamine::Error) -> AppError {
+        AppError::Spreadsheet(err)
+    }
+}
+
+impl From<rust_xlsxwriter::XlsxError> for AppError {
+    fn from(err: rust_xlsxwriter::XlsxError) -> AppError {
+        AppError::Other(Some(format!("Could not write the workbook: {err}")))
+    }
+}
+
+impl From<parquet::errors::ParquetError> for AppError {
+    fn from(err: parquet::errors::ParquetError) -> AppError {
+        AppError::Other(Some(format!(
+            "Could not read or write the Parquet file: {err}"
+        )))
+    }
+}
+
 impl From<&str> for AppError {
     fn from(s: &str) -> AppError {
         AppError::Other(Some(s.to_string()))
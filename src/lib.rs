@@ -13,17 +13,19 @@ pub mod tui;
 #[derive(Debug)]
 pub struct Config {
     verbose: bool,
+    /// Ask before running a destructive command like deleting a table.
+    confirm: bool,
 }
 
 impl Config {
-    pub fn new(verbose: bool) -> Self {
-        Self { verbose }
+    pub fn new(verbose: bool, confirm: bool) -> Self {
+        Self { verbose, confirm }
     }
 }
 
 static CONFIG: Lazy<Config> = Lazy::new(|| {
     let cli_args = parse_cli_args();
-    Config::new(cli_args.verbose)
+    Config::new(cli_args.verbose, !cli_args.no_confirm)
 });
 
 #[derive(Parser, Debug)]
@@ -34,9 +36,30 @@ static CONFIG: Lazy<Config> = Lazy::new(|| {
     long_about = "Parse csv files and explore them in a friendly TUI"
 )]
 pub struct Cli {
+    /// CSV files or a SQLite database, `-` reads a CSV file from stdin
     pub paths: Vec<std::path::PathBuf>,
     #[arg(short, long)]
     pub verbose: bool,
+    /// Query CSV files in place instead of copying them into SQLite, for files too big to import
+    #[arg(long)]
+    pub no_import: bool,
+    /// Title rows to skip at the top of each spreadsheet sheet, the headers are in the next row
+    #[arg(long, default_value_t = 0)]
+    pub skip_rows: usize,
+    /// Pad or skip malformed CSV rows instead of failing, they are listed in the import_errors table
+    #[arg(long)]
+    pub lenient: bool,
+    /// Show the first rows of the CSV file with the detected delimiter, header row, encoding and
+    /// column types, and let them be changed before importing. It is opt-in so a file opens
+    /// without a key press, and it needs a single file, stdin can only be read once
+    #[arg(long)]
+    pub preview: bool,
+    /// Write the current table to stdout as CSV on quit, so dataman can sit in a pipeline
+    #[arg(long)]
+    pub pipe: bool,
+    /// Delete rows, columns and tables without asking first
+    #[arg(long)]
+    pub no_confirm: bool,
 }
 
 fn parse_cli_args() -> Cli {
@@ -9,7 +9,14 @@ use crossterm::terminal::disable_raw_mode;
 use env_logger::{Builder, Env};
 
 use dataman::{
-    controller::controller_impl::Controller, error::AppError, model::database::Database, tui::TUI,
+    app_error_other,
+    controller::{controller_impl::Controller, import_preview},
+    error::AppError,
+    model::{
+        csv_options::CsvOptions,
+        database::{Database, ImportOptions},
+    },
+    tui::TUI,
     Cli,
 };
 use rusqlite::Connection;
@@ -19,22 +26,71 @@ fn main() -> Result<(), AppError> {
     // if release mode, logs are not printed
     TUI::install_panic_hook();
     setup_logging();
-    let (mut controller, tui) = setup_application()?;
-    controller.run(tui)
+    let cli = <Cli as clap::Parser>::parse();
+    let pipe = cli.pipe;
+    let Some((mut controller, tui)) = setup_application(cli)? else {
+        return Ok(());
+    };
+    controller.set_pipe(pipe);
+    controller.run(tui)?;
+    if pipe {
+        controller.write_current_table(std::io::stdout().lock())?;
+    }
+    Ok(())
 }
 
-fn setup_application() -> Result<(Controller, TUI), AppError> {
+/// None when the user quit the import preview.
+fn setup_application(cli: Cli) -> Result<Option<(Controller, TUI)>, AppError> {
     let time_start = std::time::Instant::now();
-    let cli = <Cli as clap::Parser>::parse();
     let paths = cli.paths;
-    let database = Database::try_from(paths)?;
+    let mut csv = CsvOptions {
+        lenient: cli.lenient,
+        ..Default::default()
+    };
+    let mut tui = None;
+    if cli.preview {
+        let [path] = paths.as_slice() else {
+            return Err(app_error_other!("--preview needs a single CSV file"));
+        };
+        if path.as_os_str() == "-" {
+            return Err(app_error_other!("--preview can't read from stdin"));
+        }
+        if cli.no_import {
+            return Err(app_error_other!(
+                "--preview can't be used with --no-import, a file queried in place is read as plain CSV"
+            ));
+        }
+        let preview_tui = tui.insert(TUI::default());
+        match import_preview::run(preview_tui, path, cli.lenient)? {
+            Some(options) => csv = options,
+            None => {
+                preview_tui.shutdown()?;
+                return Ok(None);
+            }
+        }
+    }
+    let options = ImportOptions {
+        no_import: cli.no_import,
+        skip_rows: cli.skip_rows,
+        csv,
+    };
+    let database = match Database::from_paths(paths, options) {
+        Ok(database) => database,
+        Err(err) => {
+            // the preview left the terminal in raw mode
+            if let Some(tui) = tui.as_mut() {
+                tui.shutdown()?;
+            }
+            return Err(err);
+        }
+    };
     let time_end = std::time::Instant::now();
     log::debug!(
         "Time taken to setup application: {:?}",
         time_end - time_start
     );
-    let tui = TUI::default();
-    Ok((Controller::new(database), tui))
+    let tui = tui.unwrap_or_default();
+    Ok(Some((Controller::new(database), tui)))
 }
 
 fn setup_logging() {
@@ -0,0 +1,282 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use r2d2::{CustomizeConnection, Pool};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Batch, Connection, InterruptHandle};
+
+use crate::app_error_other;
+use crate::controller::command::Command;
+use crate::error::{AppError, AppResult};
+
+use super::converter::CsvImport;
+use super::regexping::custom_functions::add_custom_functions;
+
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+pub type ConnectionPool = Pool<SqliteConnectionManager>;
+
+/// Whether every statement of `sql` only reads. SQL that doesn't prepare, e.g. because it uses a
+/// table an earlier statement creates, counts as changing something.
+pub(crate) fn only_reads(connection: &Connection, sql: &str) -> bool {
+    Batch::new(connection, sql).all(|stmt| stmt.is_ok_and(|stmt| stmt.readonly()))
+}
+
+/// Adds REGEXP, the other custom functions, the collations and the csv module for virtual tables
+/// to every connection in a pool.
+#[derive(Debug)]
+pub struct CustomFunctionAdder;
+
+impl CustomizeConnection<Connection, rusqlite::Error> for CustomFunctionAdder {
+    fn on_acquire(&self, conn: &mut Connection) -> Result<(), rusqlite::Error> {
+        add_custom_functions(conn)?;
+        rusqlite::vtab::csvtab::load_module(conn)
+    }
+}
+
+/// A pool of connections to the database at `uri`. It has to be a file or a shared in-memory
+/// database, a private in-memory database can't be opened twice.
+pub(crate) fn connection_pool(uri: &str) -> AppResult<ConnectionPool> {
+    let manager = SqliteConnectionManager::file(uri);
+    Pool::builder()
+        .max_size(2)
+        .min_idle(Some(0))
+        .connection_customizer(Box::new(CustomFunctionAdder))
+        .build(manager)
+        .map_err(|err| app_error_other!(format!("Could not create connection pool: {err}")))
+}
+
+/// Lets `connection` read tables while a worker thread writes to them. WAL does it for a file, a
+/// shared in-memory database needs read_uncommitted since it has locks per table instead.
+pub(crate) fn allow_reads_while_writing(connection: &Connection) -> AppResult<()> {
+    connection.execute_batch("PRAGMA journal_mode = WAL; PRAGMA read_uncommitted = true;")?;
+    Ok(())
+}
+
+/// An in-memory database that other connections in this process can open, unique for each call.
+pub(crate) fn shared_memory_uri() -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!(
+        "file:dataman-{}-{n}?mode=memory&cache=shared",
+        std::process::id()
+    )
+}
+
+/// SQL that runs in a transaction on a worker thread with its own connection, so the UI stays
+/// responsive. If it is cancelled or fails the transaction is rolled back.
+pub(crate) struct Job {
+    pub(crate) command: Command,
+    /// The table that was shown when the job started, the UI shows it without asking the database.
+    pub(crate) table_name: String,
+    started: Instant,
+    /// The SQL of the job, for the error if it fails.
+    sql: String,
+    /// Whether the SQL can change a table, a plain SELECT in the SQL prompt doesn't.
+    pub(crate) modifies: bool,
+    interrupt: InterruptHandle,
+    cancelled: bool,
+    receiver: Receiver<rusqlite::Result<()>>,
+}
+
+impl fmt::Debug for Job {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Job")
+            .field("command", &self.command)
+            .field("table_name", &self.table_name)
+            .field("started", &self.started)
+            .field("cancelled", &self.cancelled)
+            .finish()
+    }
+}
+
+impl Job {
+    pub(crate) fn spawn(
+        pool: &ConnectionPool,
+        command: Command,
+        table_name: &str,
+        sql: String,
+        modifies: bool,
+    ) -> AppResult<Self> {
+        let mut conn = pool
+            .get()
+            .map_err(|err| app_error_other!(format!("Could not get a connection: {err}")))?;
+        let interrupt = conn.get_interrupt_handle();
+        let (sender, receiver) = mpsc::channel();
+        let job_sql = sql.clone();
+        thread::spawn(move || {
+            log::info!("{job_sql}");
+            let result = conn.transaction().and_then(|transaction| {
+                transaction.execute_batch(&job_sql)?;
+                transaction.commit()
+            });
+            // nobody is listening if the app quit while we were working
+            let _ = sender.send(result);
+        });
+        Ok(Self {
+            command,
+            table_name: table_name.to_string(),
+            started: Instant::now(),
+            sql,
+            modifies,
+            interrupt,
+            cancelled: false,
+            receiver,
+        })
+    }
+
+    /// Interrupts the running statement, the transaction is rolled back.
+    pub(crate) fn cancel(&mut self) {
+        self.cancelled = true;
+        self.interrupt.interrupt();
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    /// The result of the job, or `None` if it is still running.
+    pub(crate) fn try_finish(&self) -> Option<AppResult<()>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result.map_err(|err| AppError::query_failed(&self.sql, err))),
+            Err(TryRecvError::Empty) => {
+                // an interrupt between two statements is lost, so we keep interrupting
+                if self.cancelled {
+                    self.interrupt.interrupt();
+                }
+                None
+            }
+            Err(TryRecvError::Disconnected) => Some(Err(app_error_other!(
+                "The worker thread stopped unexpectedly"
+            ))),
+        }
+    }
+
+    pub(crate) fn elapsed_secs(&self) -> f32 {
+        self.started.elapsed().as_secs_f32()
+    }
+
+    /// E.g. "⠙ Math Operation 3.2s, ctrl-c to cancel".
+    pub(crate) fn status(&self) -> String {
+        let elapsed = self.started.elapsed();
+        let frame = SPINNER[(elapsed.as_millis() / 100) as usize % SPINNER.len()];
+        let hint = if self.cancelled {
+            "cancelling"
+        } else {
+            "ctrl-c to cancel"
+        };
+        format!(
+            "{frame} {} {:.1}s, {hint}",
+            self.command,
+            elapsed.as_secs_f32()
+        )
+    }
+}
+
+/// Inserts the rest of a CSV file on a worker thread, while the rows we already have are shown.
+pub(crate) struct Import {
+    pub(crate) table_name: String,
+    /// The sqlite_master rowid of the table that is loading.
+    pub(crate) table_idx: i64,
+    started: Instant,
+    total_bytes: u64,
+    rows: Arc<AtomicU64>,
+    /// Malformed rows of a lenient import, they are listed in `import_errors`.
+    problems: Arc<AtomicU64>,
+    bytes: Arc<AtomicU64>,
+    receiver: Receiver<AppResult<()>>,
+}
+
+impl fmt::Debug for Import {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Import")
+            .field("table_name", &self.table_name)
+            .field("table_idx", &self.table_idx)
+            .field("rows", &self.rows())
+            .finish()
+    }
+}
+
+impl Import {
+    pub(crate) fn spawn(
+        pool: &ConnectionPool,
+        mut csv_import: CsvImport,
+        table_idx: i64,
+    ) -> AppResult<Self> {
+        let conn = pool
+            .get()
+            .map_err(|err| app_error_other!(format!("Could not get a connection: {err}")))?;
+        let rows = Arc::new(AtomicU64::new(csv_import.rows));
+        let problems = Arc::new(AtomicU64::new(csv_import.problems));
+        let bytes = Arc::new(AtomicU64::new(csv_import.bytes_read()));
+        let (sender, receiver) = mpsc::channel();
+        let import = Self {
+            table_name: csv_import.table_name.clone(),
+            table_idx,
+            started: Instant::now(),
+            total_bytes: csv_import.total_bytes,
+            rows: Arc::clone(&rows),
+            problems: Arc::clone(&problems),
+            bytes: Arc::clone(&bytes),
+            receiver,
+        };
+        thread::spawn(move || {
+            let result = loop {
+                match csv_import.insert_batch(&conn) {
+                    Ok(has_more) => {
+                        rows.store(csv_import.rows, Ordering::Relaxed);
+                        problems.store(csv_import.problems, Ordering::Relaxed);
+                        bytes.store(csv_import.bytes_read(), Ordering::Relaxed);
+                        if !has_more {
+                            break Ok(());
+                        }
+                    }
+                    Err(err) => break Err(err),
+                }
+            };
+            // nobody is listening if the app quit while we were loading
+            let _ = sender.send(result);
+        });
+        Ok(import)
+    }
+
+    /// Rows inserted so far.
+    pub(crate) fn rows(&self) -> u64 {
+        self.rows.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn problems(&self) -> u64 {
+        self.problems.load(Ordering::Relaxed)
+    }
+
+    /// The result of the import, or `None` if it is still loading.
+    pub(crate) fn try_finish(&self) -> Option<AppResult<()>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(app_error_other!(
+                "The import thread stopped unexpectedly"
+            ))),
+        }
+    }
+
+    pub(crate) fn elapsed_secs(&self) -> f32 {
+        self.started.elapsed().as_secs_f32()
+    }
+
+    /// E.g. "loading data: 120000 rows, 12.0 of 2048.0 MB".
+    pub(crate) fn status(&self) -> String {
+        let megabytes = |bytes: u64| bytes as f64 / 1_000_000.0;
+        format!(
+            "loading {}: {} rows, {:.1} of {:.1} MB",
+            self.table_name,
+            self.rows(),
+            megabytes(self.bytes.load(Ordering::Relaxed)),
+            megabytes(self.total_bytes)
+        )
+    }
+}
@@ -0,0 +1,192 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use rusqlite::Connection;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// How text is compared when sorting a column.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) enum Collation {
+    /// sqlite's default, compares the bytes so "Zebra" comes before "apple" and "10" before "2".
+    #[default]
+    Binary,
+    /// Numbers inside the text are compared by value and letters ignore case, "2" comes before "10".
+    Natural,
+    /// Ignores case for all of unicode, not only ASCII like sqlite's NOCASE.
+    NoCase,
+    /// Ignores case and accents first, so "émile" sorts next to "emma" instead of after "zoe".
+    Unicode,
+}
+
+impl Collation {
+    pub(crate) fn sql_name(&self) -> &str {
+        match self {
+            Collation::Binary => "BINARY",
+            Collation::Natural => "NATURAL",
+            Collation::NoCase => "UNICODE_NOCASE",
+            Collation::Unicode => "UNICODE",
+        }
+    }
+
+    pub(crate) fn from_sql_name(name: &str) -> Option<Self> {
+        [
+            Collation::Binary,
+            Collation::Natural,
+            Collation::NoCase,
+            Collation::Unicode,
+        ]
+        .into_iter()
+        .find(|collation| collation.sql_name().eq_ignore_ascii_case(name))
+    }
+
+    pub(crate) fn next(&self) -> Self {
+        match self {
+            Collation::Binary => Collation::Natural,
+            Collation::Natural => Collation::NoCase,
+            Collation::NoCase => Collation::Unicode,
+            Collation::Unicode => Collation::Binary,
+        }
+    }
+}
+
+impl fmt::Display for Collation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.sql_name())
+    }
+}
+
+pub(crate) fn add_collations(conn: &Connection) -> rusqlite::Result<()> {
+    conn.create_collation(Collation::Natural.sql_name(), natural_cmp)?;
+    conn.create_collation(Collation::NoCase.sql_name(), nocase_cmp)?;
+    conn.create_collation(Collation::Unicode.sql_name(), unicode_cmp)
+}
+
+/// Compares the canonical compositions, so "é" is the same letter whether it is one code point
+/// or an "e" with a combining accent.
+fn lowercase_cmp(a: &str, b: &str) -> Ordering {
+    a.nfc()
+        .flat_map(char::to_lowercase)
+        .cmp(b.nfc().flat_map(char::to_lowercase))
+}
+
+pub(crate) fn nocase_cmp(a: &str, b: &str) -> Ordering {
+    lowercase_cmp(a, b).then_with(|| a.cmp(b))
+}
+
+pub(crate) fn unicode_cmp(a: &str, b: &str) -> Ordering {
+    base_letters(a)
+        .cmp(base_letters(b))
+        .then_with(|| lowercase_cmp(a, b))
+        .then_with(|| a.cmp(b))
+}
+
+pub(crate) fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+    loop {
+        let ordering = match (a_chars.peek(), b_chars.peek()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a_chars);
+                let y = take_number(&mut b_chars);
+                x.len().cmp(&y.len()).then_with(|| x.cmp(&y))
+            }
+            (Some(&x), Some(&y)) => {
+                a_chars.next();
+                b_chars.next();
+                x.to_lowercase().cmp(y.to_lowercase())
+            }
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Takes a run of digits, without leading zeros, so the length tells which number is bigger.
+fn take_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut number = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        if !(number.is_empty() && c == '0') {
+            number.push(c);
+        }
+    }
+    number
+}
+
+/// The lowercase letters without accents, the text is decomposed with NFD and the combining
+/// marks are dropped, which works for every script that writes accents that way.
+fn base_letters(text: &str) -> impl Iterator<Item = char> + '_ {
+    text.nfd()
+        .filter(|&c| !is_combining_mark(c))
+        .flat_map(char::to_lowercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut values: Vec<&str>, cmp: fn(&str, &str) -> Ordering) -> Vec<&str> {
+        values.sort_by(|a, b| cmp(a, b));
+        values
+    }
+
+    #[test]
+    fn natural_compares_numbers_by_value() {
+        assert_eq!(
+            sorted(vec!["10", "2", "1"], natural_cmp),
+            vec!["1", "2", "10"]
+        );
+        assert_eq!(
+            sorted(vec!["file10.txt", "File2.txt", "file1.txt"], natural_cmp),
+            vec!["file1.txt", "File2.txt", "file10.txt"]
+        );
+        assert_eq!(natural_cmp("a007", "a7"), Ordering::Less);
+        assert_eq!(natural_cmp("a7b", "a7"), Ordering::Greater);
+    }
+
+    #[test]
+    fn nocase_ignores_unicode_case() {
+        assert_eq!(
+            sorted(vec!["Zebra", "apple", "Ärm", "äpple"], nocase_cmp),
+            vec!["apple", "Zebra", "äpple", "Ärm"]
+        );
+        assert_eq!(nocase_cmp("Émile", "émile"), Ordering::Less);
+    }
+
+    #[test]
+    fn unicode_ignores_accents_first() {
+        assert_eq!(
+            sorted(vec!["zoe", "Émile", "emma", "Eva"], unicode_cmp),
+            vec!["Émile", "emma", "Eva", "zoe"]
+        );
+        // outside Latin-1 and Latin Extended-A, and an accent written as a combining mark
+        assert_eq!(
+            sorted(vec!["ωβ", "Ώα", "ζ"], unicode_cmp),
+            vec!["ζ", "Ώα", "ωβ"]
+        );
+        assert!(base_letters("e\u{301}mile").eq(base_letters("émile")));
+        assert_eq!(lowercase_cmp("E\u{301}", "é"), Ordering::Equal);
+    }
+
+    #[test]
+    fn from_sql_name_round_trip() {
+        let mut collation = Collation::Binary;
+        for _ in 0..4 {
+            assert_eq!(
+                Collation::from_sql_name(collation.sql_name()),
+                Some(collation)
+            );
+            collation = collation.next();
+        }
+        assert_eq!(collation, Collation::Binary);
+        assert_eq!(
+            Collation::from_sql_name("natural"),
+            Some(Collation::Natural)
+        );
+        assert_eq!(Collation::from_sql_name("klingon"), None);
+    }
+}
@@ -0,0 +1,179 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::AppResult;
+
+/// A compressed file, detected by its last extension or by its first bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    /// E.g. `Gzip` for `data.csv.gz`.
+    pub(crate) fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" | "gzip" => Some(Self::Gzip),
+            "zst" | "zstd" => Some(Self::Zstd),
+            "bz2" => Some(Self::Bzip2),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn from_magic_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [0x1f, 0x8b, ..] => Some(Self::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Self::Zstd),
+            [b'B', b'Z', b'h', ..] => Some(Self::Bzip2),
+            _ => None,
+        }
+    }
+
+    /// Looks at the extension first and then at the first bytes of the file, so a compressed
+    /// file is found even if it is named `data.csv`.
+    pub(crate) fn detect(path: &Path) -> AppResult<Option<Self>> {
+        if let Some(compression) = Self::from_extension(path) {
+            return Ok(Some(compression));
+        }
+        let mut bytes = Vec::with_capacity(4);
+        File::open(path)?.take(4).read_to_end(&mut bytes)?;
+        Ok(Self::from_magic_bytes(&bytes))
+    }
+}
+
+/// The path without its compression extension, `data.csv.gz` becomes `data.csv`.
+pub(crate) fn strip_extension(path: &Path) -> PathBuf {
+    match Compression::from_extension(path) {
+        Some(_) => path.with_extension(""),
+        None => path.to_path_buf(),
+    }
+}
+
+/// Decompresses `reader` while it is read, nothing is held in memory.
+pub(crate) fn decompress<R: Read + Send + 'static>(
+    reader: R,
+    compression: Option<Compression>,
+) -> AppResult<Box<dyn Read + Send>> {
+    Ok(match compression {
+        Some(Compression::Gzip) => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+        Some(Compression::Zstd) => Box::new(zstd::Decoder::new(reader)?),
+        Some(Compression::Bzip2) => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+        None => Box::new(reader),
+    })
+}
+
+/// A file being written by [`create`]. Call [`CompressedWriter::finish`] when done, a dropped
+/// writer leaves the compressed stream unterminated and any error while ending it unseen.
+pub(crate) enum CompressedWriter {
+    Gzip(flate2::write::GzEncoder<File>),
+    Zstd(zstd::Encoder<'static, File>),
+    Bzip2(bzip2::write::BzEncoder<File>),
+    Plain(File),
+}
+
+impl CompressedWriter {
+    /// Writes the end of the compressed stream and flushes the file.
+    pub(crate) fn finish(self) -> io::Result<()> {
+        let mut file = match self {
+            Self::Gzip(encoder) => encoder.finish()?,
+            Self::Zstd(encoder) => encoder.finish()?,
+            Self::Bzip2(encoder) => encoder.finish()?,
+            Self::Plain(file) => file,
+        };
+        file.flush()
+    }
+}
+
+impl Write for CompressedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Gzip(encoder) => encoder.write(buf),
+            Self::Zstd(encoder) => encoder.write(buf),
+            Self::Bzip2(encoder) => encoder.write(buf),
+            Self::Plain(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Gzip(encoder) => encoder.flush(),
+            Self::Zstd(encoder) => encoder.flush(),
+            Self::Bzip2(encoder) => encoder.flush(),
+            Self::Plain(file) => file.flush(),
+        }
+    }
+}
+
+/// Creates the file, compressed if its name ends in `.gz`, `.zst` or `.bz2`.
+pub(crate) fn create(path: &Path) -> AppResult<CompressedWriter> {
+    let file = File::create(path)?;
+    Ok(match Compression::from_extension(path) {
+        Some(Compression::Gzip) => CompressedWriter::Gzip(flate2::write::GzEncoder::new(
+            file,
+            flate2::Compression::default(),
+        )),
+        Some(Compression::Zstd) => CompressedWriter::Zstd(zstd::Encoder::new(file, 0)?),
+        Some(Compression::Bzip2) => CompressedWriter::Bzip2(bzip2::write::BzEncoder::new(
+            file,
+            bzip2::Compression::default(),
+        )),
+        None => CompressedWriter::Plain(file),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(name: &str) -> (Option<Compression>, String) {
+        let path = std::env::temp_dir().join(name);
+        let mut writer = create(&path).unwrap();
+        writer.write_all(b"name,age\nanna,20\n").unwrap();
+        writer.finish().unwrap();
+        let compression = Compression::detect(&path).unwrap();
+        let mut content = String::new();
+        decompress(File::open(&path).unwrap(), compression)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+        (compression, content)
+    }
+
+    #[test]
+    fn compressed_round_trip_test() {
+        for (name, compression) in [
+            ("dataman_compression_test.csv.gz", Some(Compression::Gzip)),
+            ("dataman_compression_test.csv.zst", Some(Compression::Zstd)),
+            ("dataman_compression_test.csv.bz2", Some(Compression::Bzip2)),
+            ("dataman_compression_test.csv", None),
+        ] {
+            assert_eq!(
+                round_trip(name),
+                (compression, "name,age\nanna,20\n".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn detect_by_magic_bytes_test() {
+        let compressed = std::env::temp_dir().join("dataman_magic_test.csv.zst");
+        let renamed = std::env::temp_dir().join("dataman_magic_test.csv");
+        let mut writer = create(&compressed).unwrap();
+        writer.write_all(b"a,b\n").unwrap();
+        writer.finish().unwrap();
+        std::fs::rename(&compressed, &renamed).unwrap();
+        assert_eq!(
+            Compression::detect(&renamed).unwrap(),
+            Some(Compression::Zstd)
+        );
+        std::fs::remove_file(renamed).unwrap();
+        assert_eq!(
+            strip_extension(Path::new("dir/data.csv.gz")),
+            PathBuf::from("dir/data.csv")
+        );
+    }
+}
@@ -1,229 +1,783 @@
+use std::collections::HashMap;
+use std::io::{IsTerminal, Read, Write};
 use std::path::PathBuf;
-use std::{error::Error, fs::File, path, path::Path};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread;
+use std::{fmt, fs::File, path, path::Path};
 
-use csv::{Reader, StringRecord, Writer};
+use calamine::Reader as _;
+use csv::{ByteRecord, Reader, StringRecord, Writer};
 use rusqlite::types::ValueRef;
-use rusqlite::{Connection, Rows};
+use rusqlite::{params, params_from_iter, Connection, Rows};
 use serde::Serialize;
+use tempfile::TempDir;
 
 use crate::app_error_other;
 use crate::error::{AppError, AppResult};
 use crate::model::datarow::DataItem;
 
+use super::compression::{self, Compression};
+use super::csv_options::CsvOptions;
 use super::database::Database;
+use super::metadata::{
+    create_temp_metadata_tables, export_query, not_metadata_table_condition, set_key_column,
+};
+use super::sql_queries::{key_column_name, validate_identifier};
 
-pub(crate) fn database_from_csv(path: PathBuf, connection: Connection) -> AppResult<Database> {
-    insert_csv_data_database(path, &connection)?;
+/// Creates the database with the first batch of rows from the CSV file. If the file has more rows
+/// the import is returned, so the caller can insert the rest.
+pub(crate) fn database_from_csv(
+    path: PathBuf,
+    connection: Connection,
+    options: &CsvOptions,
+) -> AppResult<(Database, Option<CsvImport>)> {
+    let mut import = CsvImport::new(path, &connection, options)?;
+    let has_more = import.insert_batch(&connection)?;
+    // the rest is loaded with another connection
+    import.end_bulk_load(&connection)?;
     let mut database = Database::new(connection)?;
     let query =
         r#"SELECT rowid FROM sqlite_master WHERE type='table' ORDER BY rowid LIMIT 1;"#.to_string();
-    let table_idx: u16 = database
+    let table_idx: i64 = database
         .connection
         .query_row(&query, [], |row| row.get(0))?;
     database.current_table_idx = table_idx;
 
-    Ok(database)
+    Ok((database, has_more.then_some(import)))
 }
 
-fn value_query(record: &StringRecord) -> String {
-    let row = record
-        .iter()
-        .map(|s| {
-            if s.is_empty() {
-                "NULL".to_string()
-            } else {
-                format!("'{}'", s.replace('\'', "''"))
-            }
-        })
-        .collect::<Vec<String>>()
-        .join(",");
-    format!("({})", row)
+/// Creates a database that reads each CSV file through a virtual table every time it is queried,
+/// nothing is copied. The tables are read only until they are materialized. SQLite's csv module
+/// only reads comma separated UTF-8 with a header row, so other `options` are an error.
+pub(crate) fn database_from_csv_in_place(
+    paths: &[PathBuf],
+    connection: Connection,
+    options: &CsvOptions,
+) -> AppResult<Database> {
+    if *options != CsvOptions::default() {
+        return Err(app_error_other!(
+            "Only comma separated UTF-8 files with a header row can be queried in place, import the file instead"
+        ));
+    }
+    rusqlite::vtab::csvtab::load_module(&connection)?;
+    for path in paths {
+        if Compression::detect(path)?.is_some() {
+            return Err(app_error_other!(format!(
+                "{} is compressed, it can't be queried in place",
+                path.display()
+            )));
+        }
+        let table_name = Database::get_table_name(path.clone())
+            .ok_or(app_error_other!("The path has no file name"))?;
+        let filename = path.to_string_lossy().replace('\'', "''");
+        connection.execute_batch(&format!(
+            r#"CREATE VIRTUAL TABLE "{table_name}" USING csv(filename='{filename}', header=yes);"#
+        ))?;
+    }
+    Database::new(connection)
+}
+
+/// Copies stdin to `stdin.csv` in a temporary directory, so it is imported like any other file.
+/// The directory is removed when the `TempDir` is dropped.
+pub(crate) fn stdin_to_file() -> AppResult<(TempDir, PathBuf)> {
+    let mut stdin = std::io::stdin().lock();
+    if stdin.is_terminal() {
+        return Err(app_error_other!("Nothing was piped to stdin"));
+    }
+    let dir = tempfile::Builder::new().prefix("dataman-").tempdir()?;
+    let path = dir.path().join("stdin.csv");
+    std::io::copy(&mut stdin, &mut File::create(&path)?)?;
+    Ok((dir, path))
 }
 
 pub(crate) fn database_from_sqlite(connection: Connection) -> AppResult<Database> {
+    create_temp_metadata_tables(&connection)?;
     let database = Database::new(connection)?;
 
     Ok(database)
 }
 
+/// The column names for the headers of a file. A blank header becomes `column_<n>`, like the
+/// columns of a file without a header row, and a header that is already taken gets `_2`, `_3`...
+/// SQLite compares column names without case.
+fn column_names(headers: &StringRecord) -> Vec<String> {
+    let mut names: Vec<String> = Vec::with_capacity(headers.len());
+    for (column, header) in headers.iter().enumerate() {
+        let name = match header {
+            header if header.trim().is_empty() => format!("column_{}", column + 1),
+            header => header.to_string(),
+        };
+        let mut unique = name.clone();
+        let mut n = 1;
+        while names.iter().any(|used| used.eq_ignore_ascii_case(&unique)) {
+            n += 1;
+            unique = format!("{name}_{n}");
+        }
+        names.push(unique);
+    }
+    names
+}
+
+/// `name` between double quotes, with any double quote in it doubled.
+fn quote_column(name: &str) -> String {
+    format!(r#""{}""#, name.replace('"', r#""""#))
+}
+
+/// The columns get the types in `options`, TEXT if it has none for them. `key_column` is the
+/// rowid. Headers are taken as they are, see `column_names`.
 pub(crate) fn create_table_query(
-    csv: &mut Reader<File>,
+    headers: &StringRecord,
     table_name: &str,
-) -> Result<String, Box<dyn Error>> {
-    let headers = csv.headers()?;
-    let columns: String = headers
-        .iter()
-        .map(|header| format!(r#""{}""#, header))
-        .collect::<Vec<String>>()
-        .join(", ");
-
-    let headers_string: String = headers
+    key_column: &str,
+    options: &CsvOptions,
+) -> AppResult<String> {
+    validate_identifier(table_name)?;
+    let headers_string: String = column_names(headers)
         .iter()
-        .map(|header| format!(r#""{}" TEXT"#, header))
+        .enumerate()
+        .map(|(column, name)| {
+            format!(
+                "{} {}",
+                quote_column(name),
+                options.column_type(column).as_sql()
+            )
+        })
         .collect::<Vec<String>>()
         .join(",");
     log::info!("Creating table with headers: {}", headers_string);
     let query = format!(
-        r#"CREATE TABLE IF NOT EXISTS '{}'
-	(id INTEGER PRIMARY KEY, {})
+        r#"CREATE TABLE IF NOT EXISTS "{}"
+	("{}" INTEGER PRIMARY KEY, {})
 	;"#,
-        table_name, headers_string
+        table_name, key_column, headers_string
     );
     log::info!("Query: {}", query);
     Ok(query)
 }
 
-pub(crate) fn get_headers_for_query(
-    csv: &mut Reader<File>,
-    table_name: &str,
-) -> Result<String, Box<dyn Error>> {
-    let headers = csv.headers()?;
-    let columns: String = headers
+const LIMIT: usize = 10000;
+/// How many batches the parser thread may read ahead of the inserts.
+const READ_AHEAD: usize = 4;
+/// Durability doesn't matter until the whole file is in, so we trade it for speed while loading.
+const BULK_LOAD_PRAGMAS: &str =
+    "PRAGMA journal_mode = WAL; PRAGMA synchronous = OFF; PRAGMA cache_size = -65536;";
+/// The rows a lenient import skipped or padded, a table like any other so it can be browsed.
+const IMPORT_ERRORS_TABLE: &str = "import_errors";
+
+struct Batch {
+    records: Vec<StringRecord>,
+    /// The rows that were skipped or padded, only a lenient import has them.
+    problems: Vec<Problem>,
+    is_last: bool,
+}
+
+/// A malformed row of a lenient import.
+#[derive(Debug)]
+struct Problem {
+    line: u64,
+    reason: String,
+    /// The row as CSV with the delimiter of the file, fields that need quotes are quoted.
+    raw: String,
+}
+
+/// A CSV file that is inserted into its own table. The file is parsed on another thread while
+/// the rows are inserted with a prepared statement.
+#[derive(Debug)]
+pub(crate) struct CsvImport {
+    batches: Receiver<csv::Result<Batch>>,
+    /// The file, for the errors of rows that can't be parsed.
+    path: PathBuf,
+    pub(crate) table_name: String,
+    insert_query: String,
+    /// Rows inserted so far.
+    pub(crate) rows: u64,
+    /// Rows that were skipped or padded so far, they are listed in `import_errors`.
+    pub(crate) problems: u64,
+    pub(crate) total_bytes: u64,
+    bytes_read: Arc<AtomicU64>,
+    is_done: bool,
+    /// Set while the connection we insert with has the bulk load pragmas.
+    saved_pragmas: Option<SavedPragmas>,
+}
+
+/// The pragmas of a connection from before a bulk load changed them.
+#[derive(Debug)]
+struct SavedPragmas {
+    journal_mode: String,
+    synchronous: i64,
+    cache_size: i64,
+}
+
+impl SavedPragmas {
+    /// Saves the pragmas of the connection and switches it to `BULK_LOAD_PRAGMAS`.
+    fn bulk_load(connection: &Connection) -> AppResult<Self> {
+        let saved = Self {
+            journal_mode: connection.query_row("PRAGMA journal_mode;", [], |row| row.get(0))?,
+            synchronous: connection.query_row("PRAGMA synchronous;", [], |row| row.get(0))?,
+            cache_size: connection.query_row("PRAGMA cache_size;", [], |row| row.get(0))?,
+        };
+        connection.execute_batch(BULK_LOAD_PRAGMAS)?;
+        Ok(saved)
+    }
+
+    fn restore(&self, connection: &Connection) -> AppResult<()> {
+        connection.execute_batch(&format!(
+            "PRAGMA synchronous = {}; PRAGMA cache_size = {};",
+            self.synchronous, self.cache_size
+        ))?;
+        // leaving WAL needs the database to itself, so we only do it if the load switched to it
+        let journal_mode: String =
+            connection.query_row("PRAGMA journal_mode;", [], |row| row.get(0))?;
+        if !journal_mode.eq_ignore_ascii_case(&self.journal_mode) {
+            connection.execute_batch(&format!("PRAGMA journal_mode = {};", self.journal_mode))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Batch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Batch")
+            .field("records", &self.records.len())
+            .field("problems", &self.problems.len())
+            .field("is_last", &self.is_last)
+            .finish()
+    }
+}
+
+impl CsvImport {
+    /// A lenient import pads rows with too few fields and skips rows with too many fields or
+    /// invalid UTF-8, instead of failing. Each of them is listed in the `import_errors` table.
+    pub(crate) fn new(
+        path: PathBuf,
+        connection: &Connection,
+        options: &CsvOptions,
+    ) -> AppResult<Self> {
+        Self::with_batch_size(path, connection, LIMIT, options)
+    }
+
+    /// Creates the table and starts parsing the file, without inserting any rows.
+    pub(crate) fn with_batch_size(
+        path: PathBuf,
+        connection: &Connection,
+        batch_size: usize,
+        options: &CsvOptions,
+    ) -> AppResult<Self> {
+        let total_bytes = std::fs::metadata(&path)?.len();
+        let bytes_read = Arc::new(AtomicU64::new(0));
+        let file = CountingReader {
+            inner: File::open(&path)?,
+            count: Arc::clone(&bytes_read),
+        };
+        let reader = compression::decompress(file, Compression::detect(&path)?)?;
+        let mut csv = options.reader(reader);
+        let table_name = Database::get_table_name(path.clone())
+            .ok_or(app_error_other!("could not get table name."))?;
+        let headers = options
+            .headers(&mut csv)
+            .map_err(|err| AppError::import(&path, err))?;
+        let key_column = key_column_name(&column_names(&headers));
+        let query = create_table_query(&headers, &table_name, &key_column, options)?;
+        connection.execute_batch(&query)?;
+        set_key_column(connection, &table_name, &key_column)?;
+        let insert_query = insert_query(&headers, &table_name);
+        let (sender, batches) = mpsc::sync_channel(READ_AHEAD);
+        let lenient = options.lenient;
+        let delimiter = options.delimiter;
+        thread::spawn(move || parse_batches(csv, batch_size, lenient, delimiter, sender));
+        Ok(Self {
+            batches,
+            path,
+            table_name,
+            insert_query,
+            rows: 0,
+            problems: 0,
+            total_bytes,
+            bytes_read,
+            is_done: false,
+            saved_pragmas: None,
+        })
+    }
+
+    pub(crate) fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+
+    /// Inserts the next batch of rows, returns false when the whole file has been inserted.
+    /// The connection keeps the bulk load pragmas until the import is done or fails, call
+    /// `end_bulk_load` to stop earlier and go on with another connection.
+    pub(crate) fn insert_batch(&mut self, connection: &Connection) -> AppResult<bool> {
+        if self.saved_pragmas.is_none() {
+            self.saved_pragmas = Some(SavedPragmas::bulk_load(connection)?);
+        }
+        let result = self.insert_batches(connection, 1);
+        if !matches!(result, Ok(true)) {
+            self.end_bulk_load(connection)?;
+        }
+        result
+    }
+
+    /// Inserts the rest of the file in one transaction.
+    pub(crate) fn insert_all(&mut self, connection: &Connection) -> AppResult<()> {
+        if self.saved_pragmas.is_none() {
+            self.saved_pragmas = Some(SavedPragmas::bulk_load(connection)?);
+        }
+        let result = self.insert_batches(connection, usize::MAX);
+        self.end_bulk_load(connection)?;
+        result.map(|_| ())
+    }
+
+    /// Gives the connection its pragmas from before the import back.
+    pub(crate) fn end_bulk_load(&mut self, connection: &Connection) -> AppResult<()> {
+        match self.saved_pragmas.take() {
+            Some(saved) => saved.restore(connection),
+            None => Ok(()),
+        }
+    }
+
+    fn insert_batches(&mut self, connection: &Connection, max_batches: usize) -> AppResult<bool> {
+        let transaction = connection.unchecked_transaction()?;
+        {
+            let mut stmt = transaction.prepare_cached(&self.insert_query)?;
+            for _ in 0..max_batches {
+                if self.is_done {
+                    break;
+                }
+                // the parser is gone when it has sent the last batch
+                let batch = self
+                    .batches
+                    .recv()
+                    .unwrap_or(Ok(Batch {
+                        records: vec![],
+                        problems: vec![],
+                        is_last: true,
+                    }))
+                    .map_err(|err| AppError::import(&self.path, err))?;
+                for record in batch.records.iter() {
+                    let fields = record
+                        .iter()
+                        .map(|field| (!field.is_empty()).then_some(field));
+                    stmt.execute(params_from_iter(fields))?;
+                }
+                self.rows += batch.records.len() as u64;
+                if !batch.problems.is_empty() {
+                    self.insert_problems(&transaction, &batch.problems)?;
+                }
+                self.is_done = batch.is_last;
+            }
+        }
+        transaction.commit()?;
+        Ok(!self.is_done)
+    }
+
+    fn insert_problems(&mut self, connection: &Connection, problems: &[Problem]) -> AppResult<()> {
+        connection.execute_batch(&format!(
+            r#"CREATE TABLE IF NOT EXISTS "{IMPORT_ERRORS_TABLE}"
+            (id INTEGER PRIMARY KEY, file TEXT, line INTEGER, reason TEXT, raw TEXT);"#
+        ))?;
+        set_key_column(connection, IMPORT_ERRORS_TABLE, "id")?;
+        let mut stmt = connection.prepare_cached(&format!(
+            r#"INSERT INTO "{IMPORT_ERRORS_TABLE}" (file, line, reason, raw) VALUES (?1, ?2, ?3, ?4);"#
+        ))?;
+        let file = self.path.to_string_lossy();
+        for problem in problems {
+            stmt.execute(params![file, problem.line, problem.reason, problem.raw])?;
+        }
+        self.problems += problems.len() as u64;
+        Ok(())
+    }
+}
+
+fn insert_query(headers: &StringRecord, table_name: &str) -> String {
+    let columns = column_names(headers)
         .iter()
-        .map(|header| format!("'{}'", header))
-        .collect::<Vec<String>>()
+        .map(|name| quote_column(name))
+        .collect::<Vec<_>>()
         .join(", ");
-    Ok(columns)
-}
-
-pub(crate) fn sqlite_to_out(connection: &Connection, path: PathBuf) -> AppResult<()> {
-    let mut stmt = connection
-        .prepare(r#"SELECT "name" FROM sqlite_master WHERE type='table' AND name != 'table_of_tables' ORDER BY "name";"#)?;
-    let tables: Vec<String> = stmt
-        .query_map([], |row| row.get(0))?
-        .collect::<Result<_, _>>()?;
-    let mut wtr = csv::Writer::from_path(path)?;
-    for table in tables {
-        let query = &format!(r#"SELECT * FROM "{}";"#, table);
-        let mut stmt = connection.prepare(query)?;
-        let headers = stmt
-            .column_names()
-            .iter()
-            .map(|s| s.to_string())
-            .collect::<Vec<String>>();
-        let rows = stmt.query_map([], |row| {
-            let mut items = Vec::new();
-
-            for i in 0..headers.len() {
-                let header = headers.get(i).unwrap();
-                dbg!(header);
-                if header == "id" {
-                    continue;
+    let params = (1..=headers.len())
+        .map(|i| format!("?{i}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(r#"INSERT INTO "{table_name}" ({columns}) VALUES ({params});"#)
+}
+
+/// Counts the bytes read from the file, for a compressed file that is before decompressing, so it
+/// can be compared with the size of the file.
+struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// Reads the file in batches and sends them until the file ends, fails or nobody listens.
+fn parse_batches(
+    mut csv: Reader<Box<dyn Read + Send>>,
+    batch_size: usize,
+    lenient: bool,
+    delimiter: u8,
+    sender: SyncSender<csv::Result<Batch>>,
+) {
+    let expected_len = csv.headers().map(|headers| headers.len()).unwrap_or(0);
+    loop {
+        let mut records = Vec::with_capacity(batch_size);
+        let mut problems = vec![];
+        let mut record = StringRecord::new();
+        let mut byte_record = ByteRecord::new();
+        let result = loop {
+            if records.len() == batch_size {
+                break Ok(false);
+            }
+            if lenient {
+                match csv.read_byte_record(&mut byte_record) {
+                    Ok(true) => {
+                        let (record, problem) =
+                            lenient_record(&byte_record, expected_len, delimiter);
+                        records.extend(record);
+                        problems.extend(problem);
+                    }
+                    Ok(false) => break Ok(true),
+                    Err(err) => break Err(err),
                 }
-                let item = DataItem::from(row.get_ref(i).unwrap());
-                items.push(item);
+                continue;
             }
-            Ok(items)
-        })?;
-        wtr.write_record(headers.iter().filter(|&el| el != "id").clone())
-            .unwrap();
-        for row in rows {
-            let row = row?;
-            let row: Vec<Option<DataItem>> = row
-                .into_iter()
-                .map(|el| match el {
-                    DataItem::Null => None,
-                    o => Some(o),
-                })
-                .collect();
-            wtr.serialize(row.clone()).unwrap();
+            match csv.read_record(&mut record) {
+                Ok(true) => records.push(std::mem::take(&mut record)),
+                Ok(false) => break Ok(true),
+                Err(err) => break Err(err),
+            }
+        };
+        let (message, is_last) = match result {
+            Ok(is_last) => (
+                Ok(Batch {
+                    records,
+                    problems,
+                    is_last,
+                }),
+                is_last,
+            ),
+            Err(err) => (Err(err), true),
+        };
+        if sender.send(message).is_err() || is_last {
+            return;
         }
     }
+}
 
-    Ok(())
+/// A row of a lenient import, padded with empty fields if it is short. A row with too many fields
+/// or invalid UTF-8 is skipped, it is only returned as a problem.
+fn lenient_record(
+    byte_record: &ByteRecord,
+    expected_len: usize,
+    delimiter: u8,
+) -> (Option<StringRecord>, Option<Problem>) {
+    let problem = |reason: String| Problem {
+        line: byte_record.position().map_or(0, |position| position.line()),
+        reason,
+        raw: raw_line(byte_record, delimiter),
+    };
+    let len = byte_record.len();
+    if len > expected_len {
+        let reason =
+            format!("the row has {len} fields but the header has {expected_len}, it was skipped");
+        return (None, Some(problem(reason)));
+    }
+    let mut record = match StringRecord::from_byte_record(byte_record.clone()) {
+        Ok(record) => record,
+        Err(err) => {
+            let field = err.utf8_error().field() + 1;
+            let reason = format!("field {field} is not valid UTF-8, the row was skipped");
+            return (None, Some(problem(reason)));
+        }
+    };
+    if len == expected_len {
+        return (Some(record), None);
+    }
+    for _ in len..expected_len {
+        record.push_field("");
+    }
+    let reason = format!(
+        "the row has {len} fields but the header has {expected_len}, the missing fields are empty"
+    );
+    (Some(record), Some(problem(reason)))
 }
 
-const LIMIT: usize = 10000;
+/// The record written back as a line of CSV, without the line break.
+fn raw_line(byte_record: &ByteRecord, delimiter: u8) -> String {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(vec![]);
+    let line = match writer.write_byte_record(byte_record) {
+        Ok(()) => writer.into_inner().unwrap_or_default(),
+        Err(_) => vec![],
+    };
+    String::from_utf8_lossy(&line)
+        .trim_end_matches('\n')
+        .to_string()
+}
 
-pub(crate) fn insert_csv_data_database(
-    path: PathBuf,
+/// Imports every sheet of a spreadsheet as its own table, named `<file>_<sheet>`. The first
+/// `skip_rows` rows are titles, the row after them has the headers. Returns the table names with
+/// the sheets they came from.
+pub(crate) fn insert_spreadsheet(
+    path: &Path,
     connection: &Connection,
-) -> Result<(), AppError> {
-    let mut csv = csv::ReaderBuilder::new().from_path(&path)?;
-    let table_name =
-        Database::get_table_name(path).ok_or(app_error_other!("could not get table name."))?;
-    let table_names = [table_name.to_string()];
-    let mut queries = String::new();
-    let query = create_table_query(&mut csv, &table_name).unwrap();
-    queries.push_str(&query);
-    connection.execute_batch(&queries)?;
-    queries.clear();
-    let columns = get_headers_for_query(&mut csv, &table_name).unwrap();
-    let mut i = 0;
-
-    let records = csv.records();
-    let mut items: Vec<String> = Vec::with_capacity(LIMIT);
-    for record in records {
-        i += 1;
-        create_insert_stmt_record(record?, &mut items);
-        if should_batch_execute(i) {
-            i = 0;
-            batch_exec_and_clear(&mut queries, &table_name, &columns, &mut items, connection)?;
-        }
-    }
-    if !items.is_empty() {
-        batch_exec_and_clear(&mut queries, &table_name, &columns, &mut items, connection)?;
+    skip_rows: usize,
+) -> AppResult<Vec<(String, String)>> {
+    let file_name = Database::get_table_name(path.to_path_buf())
+        .ok_or(app_error_other!("could not get table name."))?;
+    let mut workbook = calamine::open_workbook_auto(path)?;
+    let mut table_names = vec![];
+    for sheet in workbook.sheet_names() {
+        let range = workbook.worksheet_range(&sheet)?;
+        let mut rows = range.rows().skip(skip_rows);
+        let Some(header_row) = rows.next() else {
+            log::info!("Sheet {sheet} is empty, no table is created");
+            continue;
+        };
+        let headers: StringRecord = header_row.iter().map(|cell| cell.to_string()).collect();
+        let columns = column_names(&headers);
+        let records: Vec<Vec<DataItem>> = rows
+            .map(|row| row.iter().map(spreadsheet_item).collect())
+            .collect();
+        let table_name = format!("{file_name}_{sheet}");
+        validate_identifier(&table_name)?;
+        let column_types = columns
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let kind = column_type(records.iter().filter_map(|record| record.get(i)));
+                format!("{} {kind}", quote_column(name))
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let key_column = key_column_name(&columns);
+        let transaction = connection.unchecked_transaction()?;
+        transaction.execute_batch(&format!(
+            r#"CREATE TABLE "{table_name}" ("{key_column}" INTEGER PRIMARY KEY, {column_types});"#
+        ))?;
+        set_key_column(&transaction, &table_name, &key_column)?;
+        {
+            let mut stmt = transaction.prepare(&insert_query(&headers, &table_name))?;
+            for record in records.iter() {
+                stmt.execute(params_from_iter(record.iter().take(headers.len())))?;
+            }
+        }
+        transaction.commit()?;
+        table_names.push((table_name, sheet));
     }
+    Ok(table_names)
+}
 
-    Ok(())
+/// A spreadsheet cell as the value we store. Whole numbers are integers, spreadsheets keep every
+/// number as a float. Dates become ISO 8601 text, which sorts and compares as dates in SQLite.
+fn spreadsheet_item(cell: &calamine::Data) -> DataItem {
+    use calamine::Data;
+    match cell {
+        Data::Int(n) => DataItem::Integer(*n),
+        Data::Float(x) if x.fract() == 0.0 && x.abs() < i64::MAX as f64 => {
+            DataItem::Integer(*x as i64)
+        }
+        Data::Float(x) => DataItem::Float(*x),
+        Data::Bool(b) => DataItem::Integer(*b as i64),
+        Data::DateTime(date) => match date.as_datetime() {
+            Some(datetime) if datetime.time() == chrono::NaiveTime::MIN => {
+                DataItem::Text(datetime.format("%Y-%m-%d").to_string())
+            }
+            Some(datetime) => DataItem::Text(datetime.format("%Y-%m-%d %H:%M:%S").to_string()),
+            None => DataItem::Float(date.as_f64()),
+        },
+        Data::Empty => DataItem::Null,
+        Data::String(_) | Data::DateTimeIso(_) | Data::DurationIso(_) | Data::Error(_) => {
+            DataItem::Text(cell.to_string())
+        }
+    }
 }
-fn should_batch_execute(i: usize) -> bool {
-    i == LIMIT
+
+/// INTEGER or REAL if every value in the column is a number, TEXT if every value is text. A column
+/// with mixed values gets no type, so each cell keeps its own.
+fn column_type<'a>(items: impl Iterator<Item = &'a DataItem>) -> &'static str {
+    let (mut integers, mut floats, mut texts) = (false, false, false);
+    for item in items {
+        match item {
+            DataItem::Integer(_) => integers = true,
+            DataItem::Float(_) => floats = true,
+            DataItem::Text(_) => texts = true,
+            // a spreadsheet has no blobs
+            DataItem::Blob(_) | DataItem::Null => {}
+        }
+    }
+    match (integers, floats, texts) {
+        (_, _, true) if integers || floats => "",
+        (_, _, true) => "TEXT",
+        (_, true, false) => "REAL",
+        (true, false, false) => "INTEGER",
+        (false, false, false) => "TEXT",
+    }
+}
+
+pub fn insert_csv_data_database(
+    path: PathBuf,
+    connection: &Connection,
+    options: &CsvOptions,
+) -> Result<(), AppError> {
+    CsvImport::new(path, connection, options)?.insert_all(connection)
 }
-fn batch_exec_and_clear(
-    queries: &mut String,
+
+/// Writes the table as CSV, compressed if the name ends in `.gz`, `.zst` or `.bz2`. The delimiter,
+/// encoding and header row are those of `options`.
+pub(crate) fn save_to_csv_file(
+    connection: &Connection,
     table_name: &str,
-    columns: &str,
-    items: &mut Vec<String>,
+    save_file: &Path,
+    options: &CsvOptions,
+) -> AppResult<()> {
+    let query = export_query(connection, table_name, "")?;
+    let mut writer = compression::create(save_file)?;
+    write_csv(connection, &query, &mut writer, options)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Writes the rows of `query` as CSV, NULL becomes an empty field. The header row is left out
+/// when `options` say the file has none.
+pub(crate) fn write_csv<W: Write>(
     connection: &Connection,
+    query: &str,
+    writer: W,
+    options: &CsvOptions,
 ) -> AppResult<()> {
-    queries.clear();
-    *queries = format!(
-        r#"INSERT INTO '{}' ({}) VALUES {};"#,
-        table_name,
-        columns,
-        items.join(",\n")
-    );
-    connection.execute_batch(queries)?;
-    items.clear();
+    let mut stmt = connection.prepare(query)?;
+    let headers: Vec<String> = stmt.column_names().iter().map(|s| s.to_string()).collect();
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(options.delimiter)
+        .from_writer(options.encoding.writer(writer)?);
+    if options.has_headers {
+        wtr.write_record(&headers)?;
+    }
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let record = (0..headers.len())
+            .map(|i| Ok(String::from(DataItem::from(row.get_ref(i)?))))
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        wtr.write_record(&record)?;
+    }
+    wtr.flush()?;
     Ok(())
 }
 
-fn create_insert_stmt_record(record: StringRecord, items: &mut Vec<String>) {
-    let result = value_query(&record);
-    items.push(result);
+/// The first `skip_rows` rows of each sheet of a spreadsheet, by sheet name. These are the titles
+/// that `insert_spreadsheet` skipped.
+pub(crate) fn spreadsheet_titles(
+    path: &Path,
+    skip_rows: usize,
+) -> AppResult<HashMap<String, Vec<Vec<DataItem>>>> {
+    let mut titles = HashMap::new();
+    if skip_rows == 0 {
+        return Ok(titles);
+    }
+    let mut workbook = calamine::open_workbook_auto(path)?;
+    for sheet in workbook.sheet_names() {
+        let range = workbook.worksheet_range(&sheet)?;
+        let rows = range
+            .rows()
+            .take(skip_rows)
+            .map(|row| row.iter().map(spreadsheet_item).collect())
+            .collect();
+        titles.insert(sheet, rows);
+    }
+    Ok(titles)
 }
 
-pub(crate) fn save_to_csv_file(
+/// Writes each table to its own worksheet, named after the sheet next to it or else the table,
+/// with the headers in bold. Integers and floats are written as numbers, so INTEGER and REAL
+/// columns can be calculated with in the spreadsheet. The `id` column that we add on import is
+/// left out, see `export_columns`. A sheet with rows in `titles` has them above its headers.
+pub(crate) fn save_to_xlsx(
     connection: &Connection,
-    table_name: &str,
-    save_file: &PathBuf,
+    tables: &[(String, Option<String>)],
+    titles: &HashMap<String, Vec<Vec<DataItem>>>,
+    path: &Path,
 ) -> AppResult<()> {
-    let query = &format!(r#"SELECT * FROM "{}";"#, table_name);
-    let mut stmt = connection.prepare(query).unwrap();
-    let headers: Vec<String> = stmt.column_names().iter().map(|s| s.to_string()).collect();
+    let mut workbook = rust_xlsxwriter::Workbook::new();
+    let bold = rust_xlsxwriter::Format::new().set_bold();
+    let mut sheet_names = vec![];
+    for (table_name, sheet) in tables {
+        let worksheet = workbook.add_worksheet();
+        let sheet_name = worksheet_name(sheet.as_ref().unwrap_or(table_name), &sheet_names);
+        worksheet.set_name(&sheet_name)?;
+        sheet_names.push(sheet_name);
 
-    // Collect all rows into a vector to avoid borrowing issues
-    let rows: Vec<Vec<DataItem>> = stmt
-        .query_map([], |row| {
-            let mut items = Vec::new();
-            for i in 0..headers.len() {
-                let item = row.get_ref(i).unwrap();
-                items.push(DataItem::from(item));
+        let mut stmt = connection.prepare(&export_query(connection, table_name, "")?)?;
+        let headers: Vec<String> = stmt.column_names().iter().map(|s| s.to_string()).collect();
+        let title_rows = sheet.as_ref().and_then(|sheet| titles.get(sheet));
+        let mut row_number = 0;
+        for title_row in title_rows.into_iter().flatten() {
+            for (col, item) in title_row.iter().enumerate() {
+                write_cell(worksheet, row_number, col as u16, item.clone())?;
             }
-            Ok(items)
-        })
-        .unwrap()
-        .map(|row| row.unwrap())
-        .collect();
-
-    let mut wtr = Writer::from_path(save_file).unwrap();
-    wtr.write_record(&headers).unwrap();
-    for row in rows {
-        wtr.serialize(row).unwrap();
+            row_number += 1;
+        }
+        for (col, header) in headers.iter().enumerate() {
+            worksheet.write_string_with_format(row_number, col as u16, header, &bold)?;
+        }
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            row_number += 1;
+            for col in 0..headers.len() {
+                let item = DataItem::from(row.get_ref(col)?);
+                write_cell(worksheet, row_number, col as u16, item)?;
+            }
+        }
+        worksheet.autofit();
     }
+    workbook.save(path)?;
+    Ok(())
+}
+
+fn write_cell(
+    worksheet: &mut rust_xlsxwriter::Worksheet,
+    row: u32,
+    col: u16,
+    item: DataItem,
+) -> AppResult<()> {
+    match item {
+        DataItem::Integer(n) => worksheet.write_number(row, col, n as f64)?,
+        DataItem::Float(x) => worksheet.write_number(row, col, x)?,
+        DataItem::Text(text) => worksheet.write_string(row, col, text)?,
+        DataItem::Blob(_) => worksheet.write_string(row, col, item.to_string())?,
+        DataItem::Null => worksheet,
+    };
     Ok(())
 }
+
+/// Worksheet names are at most 31 characters, can't contain `[]:*?/\` and have to be unique.
+fn worksheet_name(table_name: &str, used: &[String]) -> String {
+    let name: String = table_name
+        .chars()
+        .map(|c| if "[]:*?/\\".contains(c) { '_' } else { c })
+        .take(31)
+        .collect();
+    let mut unique = name.clone();
+    let mut n = 1;
+    while used.iter().any(|used| used.eq_ignore_ascii_case(&unique)) {
+        n += 1;
+        let suffix = format!("_{n}");
+        let prefix: String = name.chars().take(31 - suffix.len()).collect();
+        unique = format!("{prefix}{suffix}");
+    }
+    unique
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -231,15 +785,225 @@ mod tests {
     use std::{assert_eq, println};
 
     #[test]
-    fn write_db_to_out_test() {
-        let mut database1 = Database::try_from(vec![PathBuf::from("assets/data.sqlite")]).unwrap();
-        sqlite_to_out(&database1.connection, PathBuf::from("assets/out-data.csv")).unwrap();
-        let mut database2 = Database::try_from(vec![PathBuf::from("assets/out-data.csv")]).unwrap();
+    fn insert_csv_with_quotes_and_empty_fields_test() {
+        let path = std::env::temp_dir().join("dataman_import_test.csv");
+        std::fs::write(&path, "name,city\no'neil,\"Lund, Sweden\"\nanna,\n").unwrap();
+        let connection = Connection::open_in_memory().unwrap();
+        insert_csv_data_database(path.clone(), &connection, &CsvOptions::default()).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let mut stmt = connection
+            .prepare(r#"SELECT "name", "city" FROM "dataman_import_test" ORDER BY "id";"#)
+            .unwrap();
+        let rows: Vec<(String, Option<String>)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                ("o'neil".to_string(), Some("Lund, Sweden".to_string())),
+                ("anna".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn import_blank_and_quoted_headers_test() {
+        let path = std::env::temp_dir().join(format!("headers-{}.csv", std::process::id()));
+        std::fs::write(&path, "name,,\"say \"\"hi\"\"\"\nanna,31,yo\n").unwrap();
+        let connection = Connection::open_in_memory().unwrap();
+        insert_csv_data_database(path.clone(), &connection, &CsvOptions::default()).unwrap();
+        let table_name = Database::get_table_name(path.clone()).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let mut stmt = connection
+            .prepare(&format!(r#"SELECT * FROM "{table_name}";"#))
+            .unwrap();
+        assert_eq!(
+            stmt.column_names(),
+            vec!["id", "name", "column_2", "say \"hi\""]
+        );
+        let row: (String, String, String) = stmt
+            .query_row([], |row| Ok((row.get(1)?, row.get(2)?, row.get(3)?)))
+            .unwrap();
+        assert_eq!(row, ("anna".into(), "31".into(), "yo".into()));
+    }
+
+    #[test]
+    fn spreadsheet_headers_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.xlsx");
+        let mut workbook = rust_xlsxwriter::Workbook::new();
+        let worksheet = workbook.add_worksheet().set_name("people").unwrap();
+        for (column, header) in ["name", "", "say \"hi\"", "Name"].iter().enumerate() {
+            worksheet.write(0, column as u16, *header).unwrap();
+            worksheet.write(1, column as u16, column as u32).unwrap();
+        }
+        workbook.save(&path).unwrap();
+        let connection = Connection::open_in_memory().unwrap();
+        let tables = insert_spreadsheet(&path, &connection, 0).unwrap();
+        assert_eq!(
+            tables,
+            vec![("book_people".to_string(), "people".to_string())]
+        );
+        let stmt = connection
+            .prepare(r#"SELECT * FROM "book_people";"#)
+            .unwrap();
+        assert_eq!(
+            stmt.column_names(),
+            vec!["id", "name", "column_2", "say \"hi\"", "Name_2"]
+        );
+
+        // the table name comes from the sheet, so it has to be a valid name
+        let mut workbook = rust_xlsxwriter::Workbook::new();
+        let worksheet = workbook.add_worksheet().set_name("say \"hi\"").unwrap();
+        worksheet.write(0, 0, "name").unwrap();
+        workbook.save(&path).unwrap();
+        assert!(matches!(
+            insert_spreadsheet(&path, &connection, 0),
+            Err(AppError::InvalidIdentifier { .. })
+        ));
+    }
+
+    #[test]
+    fn import_error_test() {
+        let path = std::env::temp_dir().join(format!("ragged-{}.csv", std::process::id()));
+        std::fs::write(&path, "name,city\nanna,lund\nbo\n").unwrap();
+        let connection = Connection::open_in_memory().unwrap();
+        let err = insert_csv_data_database(path.clone(), &connection, &CsvOptions::default())
+            .unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            err,
+            AppError::Import {
+                line: Some(3),
+                column: Some(2),
+                ..
+            }
+        ));
+        assert!(err
+            .to_string()
+            .contains("line 3, column 2: the row has 1 fields but the header has 2"));
+    }
+
+    #[test]
+    fn import_restores_pragmas_test() {
+        let path = std::env::temp_dir().join(format!("pragmas-{}.csv", std::process::id()));
+        let db = path.with_extension("sqlite");
+        std::fs::write(&path, "name\nanna\nbo\n").unwrap();
+        let connection = Connection::open(&db).unwrap();
+        connection
+            .execute_batch("PRAGMA synchronous = FULL; PRAGMA cache_size = -1234;")
+            .unwrap();
+        let mut import =
+            CsvImport::with_batch_size(path.clone(), &connection, 1, &CsvOptions::default())
+                .unwrap();
+        assert!(import.insert_batch(&connection).unwrap());
+        let pragma = |name: &str| -> String {
+            connection
+                .query_row(&format!("PRAGMA {name};"), [], |row| {
+                    Ok(DataItem::from(row.get_ref(0)?).to_string())
+                })
+                .unwrap()
+        };
+        // the bulk load pragmas stay between batches
+        assert_eq!(pragma("synchronous"), "0");
+        assert_eq!(pragma("journal_mode"), "wal");
+        import.insert_all(&connection).unwrap();
+        assert_eq!(pragma("synchronous"), "2");
+        assert_eq!(pragma("cache_size"), "-1234");
+        assert_eq!(pragma("journal_mode"), "delete");
+        drop(connection);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&db).unwrap();
+    }
+
+    #[test]
+    fn lenient_import_test() {
+        let dir = std::env::temp_dir().join(format!("dataman-lenient-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("people.csv");
+        std::fs::write(
+            &path,
+            b"name,city\nanna,lund\nbo\ncarl,\"x, y\",z\nd\xffn,oslo\neva,\"bergen\"\n",
+        )
+        .unwrap();
+        let connection = Connection::open_in_memory().unwrap();
+        let options = CsvOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        insert_csv_data_database(path.clone(), &connection, &options).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let rows: Vec<(String, Option<String>)> = connection
+            .prepare("SELECT name, city FROM people ORDER BY id;")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                ("anna".to_string(), Some("lund".to_string())),
+                ("bo".to_string(), None),
+                ("eva".to_string(), Some("bergen".to_string())),
+            ]
+        );
+        let problems: Vec<(i64, String, String)> = connection
+            .prepare("SELECT line, reason, raw FROM import_errors ORDER BY id;")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(problems.len(), 3);
+        assert_eq!(problems[0].0, 3);
+        assert!(problems[0].1.ends_with("the missing fields are empty"));
+        assert_eq!(
+            (problems[1].0, problems[1].2.as_str()),
+            (4, "carl,\"x, y\",z")
+        );
+        assert_eq!(problems[2].0, 5);
+        assert!(problems[2].1.starts_with("field 1 is not valid UTF-8"));
+    }
+
+    #[test]
+    fn export_all_test() {
+        // opening a database adds our metadata tables, so we work on a copy of the fixture
+        let fixture =
+            std::env::temp_dir().join(format!("export-all-{}.sqlite", std::process::id()));
+        std::fs::copy("assets/data.sqlite", &fixture).unwrap();
+        let mut database1 = Database::try_from(vec![fixture.clone()]).unwrap();
+        let dir = std::env::temp_dir().join("dataman_export_all_test");
+        let paths = database1.export_all(&dir, "csv").unwrap();
+        assert!(paths.contains(&dir.join("data.csv")));
+        assert!(!paths.contains(&dir.join("table_of_tables.csv")));
+        let mut database2 = Database::try_from(vec![dir.join("data.csv")]).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::remove_file(fixture).unwrap();
         let first_row_db1 = database1.get(1, 0, "data".to_string()).unwrap().1;
-        let first_row_db2 = database2.get(1, 0, "out-data".to_string()).unwrap().1;
+        let first_row_db2 = database2.get(1, 0, "data".to_string()).unwrap().1;
 
-        for (i, item) in first_row_db1.iter().enumerate() {
-            assert_eq!(item, first_row_db2.get(i).unwrap());
+        // the id column of the fixture is its own, so it is exported and the import adds _id,
+        // every column comes back as TEXT
+        let (headers, _) = database2.get(1, 0, "data".to_string()).unwrap();
+        assert_eq!(headers[..2], ["_id", "id"]);
+        for (i, item) in first_row_db1[0].iter().enumerate() {
+            assert_eq!(item.to_string(), first_row_db2[0][i + 1].to_string());
         }
     }
+
+    #[test]
+    fn worksheet_name_test() {
+        let long = "a_table_name_that_is_longer_than_31_characters";
+        assert_eq!(worksheet_name("sales/2024", &[]), "sales_2024");
+        assert_eq!(worksheet_name(long, &[]).chars().count(), 31);
+        let used = vec![worksheet_name(long, &[])];
+        let second = worksheet_name(long, &used);
+        assert_eq!(second.chars().count(), 31);
+        assert!(second.ends_with("_2"));
+    }
 }
@@ -0,0 +1,497 @@
+use std::fs::File;
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+
+use csv::{Reader, StringRecord};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+
+use crate::error::{AppError, AppResult};
+
+use super::compression::{self, Compression};
+
+/// Rows shown in the import preview.
+pub(crate) const PREVIEW_LINES: usize = 20;
+/// The start of the file the preview and the detection look at.
+const SAMPLE_BYTES: u64 = 64 * 1024;
+/// The delimiters the detection tries, and that `d` cycles through in the preview.
+const DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+
+/// The encoding of a CSV file, anything but UTF-8 is decoded to UTF-8 while it is read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Windows1252,
+}
+
+impl Encoding {
+    const ALL: [Encoding; 4] = [
+        Encoding::Utf8,
+        Encoding::Utf16Le,
+        Encoding::Utf16Be,
+        Encoding::Windows1252,
+    ];
+
+    pub(crate) fn name(self) -> &'static str {
+        self.encoding_rs().name()
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|encoding| encoding.name() == name)
+    }
+
+    /// Writes the UTF-8 written to it to `writer` in this encoding, UTF-16 starts with a byte
+    /// order mark so that it is detected when the file is read again.
+    pub(crate) fn writer<'a, W: Write + 'a>(
+        self,
+        mut writer: W,
+    ) -> io::Result<Box<dyn Write + 'a>> {
+        match self {
+            Encoding::Utf8 => return Ok(Box::new(writer)),
+            Encoding::Utf16Le => writer.write_all(&[0xff, 0xfe])?,
+            Encoding::Utf16Be => writer.write_all(&[0xfe, 0xff])?,
+            Encoding::Windows1252 => {}
+        }
+        Ok(Box::new(EncodingWriter {
+            writer,
+            encoding: self,
+            partial: vec![],
+        }))
+    }
+
+    fn encode(self, text: &str) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            Encoding::Utf16Le => Ok(text.encode_utf16().flat_map(u16::to_le_bytes).collect()),
+            Encoding::Utf16Be => Ok(text.encode_utf16().flat_map(u16::to_be_bytes).collect()),
+            Encoding::Windows1252 => match encoding_rs::WINDOWS_1252.encode(text) {
+                (_, _, true) => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "the text has characters that windows-1252 doesn't have",
+                )),
+                (bytes, _, false) => Ok(bytes.into_owned()),
+            },
+        }
+    }
+
+    fn encoding_rs(self) -> &'static encoding_rs::Encoding {
+        match self {
+            Encoding::Utf8 => encoding_rs::UTF_8,
+            Encoding::Utf16Le => encoding_rs::UTF_16LE,
+            Encoding::Utf16Be => encoding_rs::UTF_16BE,
+            Encoding::Windows1252 => encoding_rs::WINDOWS_1252,
+        }
+    }
+
+    fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&e| e == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    /// The byte order mark decides, without one the start of the file is UTF-8 if it decodes as
+    /// UTF-8 and Windows-1252 otherwise.
+    fn detect(sample: &[u8]) -> Self {
+        match sample {
+            [0xff, 0xfe, ..] => Encoding::Utf16Le,
+            [0xfe, 0xff, ..] => Encoding::Utf16Be,
+            _ => match std::str::from_utf8(sample) {
+                Ok(_) => Encoding::Utf8,
+                // the sample may end in the middle of a character
+                Err(err) if err.error_len().is_none() => Encoding::Utf8,
+                Err(_) => Encoding::Windows1252,
+            },
+        }
+    }
+}
+
+/// Encodes UTF-8 on its way to `writer`, see `Encoding::writer`.
+struct EncodingWriter<W: Write> {
+    writer: W,
+    encoding: Encoding,
+    /// The start of a character that the last write cut in two.
+    partial: Vec<u8>,
+}
+
+impl<W: Write> Write for EncodingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.partial.extend_from_slice(buf);
+        let complete = match std::str::from_utf8(&self.partial) {
+            Ok(text) => text,
+            Err(err) if err.error_len().is_none() => {
+                std::str::from_utf8(&self.partial[..err.valid_up_to()])
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+            }
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        };
+        let bytes = self.encoding.encode(complete)?;
+        let written = complete.len();
+        self.writer.write_all(&bytes)?;
+        self.partial.drain(..written);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// The SQLite type of an imported column. TEXT keeps the values as they are in the file, INTEGER
+/// and REAL store numbers as numbers so they sort and compute as numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColumnType {
+    #[default]
+    Text,
+    Integer,
+    Real,
+}
+
+impl ColumnType {
+    pub(crate) fn as_sql(self) -> &'static str {
+        match self {
+            ColumnType::Text => "TEXT",
+            ColumnType::Integer => "INTEGER",
+            ColumnType::Real => "REAL",
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            ColumnType::Text => ColumnType::Integer,
+            ColumnType::Integer => ColumnType::Real,
+            ColumnType::Real => ColumnType::Text,
+        }
+    }
+
+    /// INTEGER if every value is an integer, REAL if every value is a number and TEXT otherwise.
+    /// Empty values become NULL, so they don't count. A number with a leading zero or `+`, like a
+    /// zip code or a phone number, is TEXT, as a number it would lose them. So are `inf` and `nan`,
+    /// Rust parses them as floats but in a file they are words.
+    fn infer<'a>(values: impl Iterator<Item = &'a str>) -> Self {
+        let mut column_type = ColumnType::Integer;
+        for value in values.filter(|value| !value.is_empty()) {
+            let digits = value.strip_prefix('-').unwrap_or(value);
+            let leading_zero = digits.len() > 1
+                && digits.starts_with('0')
+                && digits[1..].starts_with(|c: char| c.is_ascii_digit());
+            if leading_zero || value.starts_with('+') {
+                return ColumnType::Text;
+            }
+            if value.parse::<i64>().is_ok() {
+                continue;
+            }
+            if value.parse::<f64>().is_ok_and(f64::is_finite) {
+                column_type = ColumnType::Real;
+                continue;
+            }
+            return ColumnType::Text;
+        }
+        column_type
+    }
+}
+
+/// How a CSV file is read. The defaults are those of a plain CSV file, the import preview detects
+/// them from the start of the file and lets the user change them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvOptions {
+    pub delimiter: u8,
+    /// Whether the first row has the column names, without it the columns are `column_1`, ...
+    pub has_headers: bool,
+    pub encoding: Encoding,
+    /// The type of each column, columns without one are TEXT.
+    pub column_types: Vec<ColumnType>,
+    /// Pad or skip malformed rows instead of failing, they are listed in `import_errors`.
+    pub lenient: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            has_headers: true,
+            encoding: Encoding::Utf8,
+            column_types: vec![],
+            lenient: false,
+        }
+    }
+}
+
+impl CsvOptions {
+    /// A CSV reader for `reader` that decodes, splits and checks the rows as the options say.
+    pub(crate) fn reader(&self, reader: Box<dyn Read + Send>) -> Reader<Box<dyn Read + Send>> {
+        let reader: Box<dyn Read + Send> = match self.encoding {
+            // read as it is, so invalid UTF-8 is reported instead of replaced
+            Encoding::Utf8 => reader,
+            encoding => Box::new(
+                DecodeReaderBytesBuilder::new()
+                    .encoding(Some(encoding.encoding_rs()))
+                    .build(reader),
+            ),
+        };
+        csv::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .has_headers(self.has_headers)
+            .flexible(self.lenient)
+            .from_reader(reader)
+    }
+
+    /// The column names, from the first row or `column_1`, `column_2`, ... when it has none.
+    pub(crate) fn headers<R: Read>(&self, csv: &mut Reader<R>) -> csv::Result<StringRecord> {
+        let first_row = csv.headers()?;
+        if self.has_headers {
+            return Ok(first_row.clone());
+        }
+        Ok((1..=first_row.len())
+            .map(|column| format!("column_{column}"))
+            .collect())
+    }
+
+    pub(crate) fn column_type(&self, column: usize) -> ColumnType {
+        self.column_types.get(column).copied().unwrap_or_default()
+    }
+
+    pub(crate) fn delimiter_name(&self) -> String {
+        match self.delimiter {
+            b'\t' => "tab".to_string(),
+            delimiter => format!("'{}'", delimiter as char),
+        }
+    }
+
+    /// Guesses the encoding, the delimiter and whether there is a header row from the start of a
+    /// file. The column types are inferred by the preview.
+    fn detect(sample: &[u8], lenient: bool) -> Self {
+        let encoding = Encoding::detect(sample);
+        let (text, _, _) = encoding.encoding_rs().decode(sample);
+        let delimiter = sniff_delimiter(&text);
+        let first_row = text.lines().next().unwrap_or_default();
+        // a header row has names, a data row usually has a number or an empty field somewhere
+        let has_headers = first_row
+            .split(delimiter as char)
+            .map(|field| field.trim_matches('"'))
+            .all(|field| !field.is_empty() && field.parse::<f64>().is_err());
+        Self {
+            delimiter,
+            has_headers,
+            encoding,
+            column_types: vec![],
+            lenient,
+        }
+    }
+}
+
+/// The delimiter that splits the first lines into the same number of fields, the more fields the
+/// better. A comma wins a tie, so a file with a single column is read as CSV.
+fn sniff_delimiter(text: &str) -> u8 {
+    let lines: Vec<&str> = text.lines().take(PREVIEW_LINES).collect();
+    DELIMITERS
+        .into_iter()
+        .rev()
+        .max_by_key(|&delimiter| {
+            let counts: Vec<usize> = lines
+                .iter()
+                .map(|line| line.bytes().filter(|&byte| byte == delimiter).count())
+                .collect();
+            let first = counts.first().copied().unwrap_or(0);
+            let consistent = counts.iter().filter(|&&count| count == first).count();
+            (first > 0, consistent, first)
+        })
+        .unwrap_or(b',')
+}
+
+/// The first rows of a CSV file as they would be imported with `options`, parsed again whenever
+/// the options change.
+#[derive(Debug)]
+pub struct CsvPreview {
+    pub path: PathBuf,
+    pub options: CsvOptions,
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    /// Why the start of the file can't be read with these options.
+    pub error: Option<String>,
+    sample: Vec<u8>,
+    /// Whether the file is longer than the sample, the last row of the sample may be cut off.
+    truncated: bool,
+}
+
+impl CsvPreview {
+    /// Reads the start of the file and detects how it should be read.
+    pub fn new(path: &Path, lenient: bool) -> AppResult<Self> {
+        let file = File::open(path)?;
+        let mut sample = vec![];
+        compression::decompress(file, Compression::detect(path)?)?
+            .take(SAMPLE_BYTES)
+            .read_to_end(&mut sample)?;
+        let mut preview = Self {
+            path: path.to_path_buf(),
+            options: CsvOptions::detect(&sample, lenient),
+            headers: vec![],
+            rows: vec![],
+            error: None,
+            truncated: sample.len() as u64 == SAMPLE_BYTES,
+            sample,
+        };
+        preview.refresh();
+        Ok(preview)
+    }
+
+    /// Parses the sample again and infers the column types from it.
+    pub fn refresh(&mut self) {
+        let options = CsvOptions {
+            // show the rows even if they are malformed
+            lenient: true,
+            ..self.options.clone()
+        };
+        let mut csv = options.reader(Box::new(Cursor::new(self.sample.clone())));
+        self.error = None;
+        self.rows.clear();
+        self.headers = match options.headers(&mut csv) {
+            Ok(headers) => headers.iter().map(str::to_string).collect(),
+            Err(err) => {
+                self.error = Some(AppError::import(&self.path, err).to_string());
+                vec![]
+            }
+        };
+        for record in csv.records().take(PREVIEW_LINES) {
+            match record {
+                Ok(record) => self.rows.push(record.iter().map(str::to_string).collect()),
+                Err(err) => {
+                    self.error = Some(AppError::import(&self.path, err).to_string());
+                    break;
+                }
+            }
+        }
+        if self.truncated && self.rows.len() < PREVIEW_LINES {
+            self.rows.pop();
+        }
+        self.options.column_types = (0..self.headers.len())
+            .map(|column| {
+                ColumnType::infer(
+                    self.rows
+                        .iter()
+                        .filter_map(|row| row.get(column).map(String::as_str)),
+                )
+            })
+            .collect();
+    }
+
+    pub fn cycle_delimiter(&mut self) {
+        let index = DELIMITERS
+            .iter()
+            .position(|&delimiter| delimiter == self.options.delimiter)
+            .map_or(0, |index| index + 1);
+        self.options.delimiter = DELIMITERS[index % DELIMITERS.len()];
+        self.refresh();
+    }
+
+    pub fn toggle_headers(&mut self) {
+        self.options.has_headers = !self.options.has_headers;
+        self.refresh();
+    }
+
+    pub fn cycle_encoding(&mut self) {
+        self.options.encoding = self.options.encoding.next();
+        self.refresh();
+    }
+
+    /// Only the type changes, the rows are the same.
+    pub fn cycle_column_type(&mut self, column: usize) {
+        if let Some(column_type) = self.options.column_types.get_mut(column) {
+            *column_type = column_type.next();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_preview_test() {
+        let path = std::env::temp_dir().join(format!("preview-{}.csv", std::process::id()));
+        let mut text: Vec<u8> = b"name;age;height\n".to_vec();
+        for row in 0..30 {
+            text.extend(format!("pér {row};{row};1.{row}\n").bytes());
+        }
+        // é in Windows-1252
+        let text: Vec<u8> = text
+            .iter()
+            .flat_map(|&byte| match byte {
+                0xc3 => None,
+                0xa9 => Some(0xe9),
+                byte => Some(byte),
+            })
+            .collect();
+        std::fs::write(&path, &text).unwrap();
+        let mut preview = CsvPreview::new(&path, false).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(preview.options.encoding, Encoding::Windows1252);
+        assert_eq!(preview.options.delimiter, b';');
+        assert!(preview.options.has_headers);
+        assert_eq!(preview.headers, vec!["name", "age", "height"]);
+        assert_eq!(preview.rows.len(), PREVIEW_LINES);
+        assert_eq!(preview.rows[1][0], "pér 1");
+        assert_eq!(
+            preview.options.column_types,
+            vec![ColumnType::Text, ColumnType::Integer, ColumnType::Real]
+        );
+
+        preview.toggle_headers();
+        assert_eq!(preview.headers, vec!["column_1", "column_2", "column_3"]);
+        assert_eq!(preview.rows[0], vec!["name", "age", "height"]);
+        assert_eq!(preview.options.column_types[1], ColumnType::Text);
+
+        preview.cycle_delimiter();
+        assert_eq!(preview.options.delimiter, b'\t');
+        assert_eq!(preview.headers.len(), 1);
+
+        preview.cycle_column_type(0);
+        assert_eq!(preview.options.column_types, vec![ColumnType::Integer]);
+    }
+
+    #[test]
+    fn infer_test() {
+        let infer = |values: &[&str]| ColumnType::infer(values.iter().copied());
+        assert_eq!(infer(&["1", "-20", "", "0"]), ColumnType::Integer);
+        assert_eq!(infer(&["1", "0.5", "-0.25"]), ColumnType::Real);
+        assert_eq!(infer(&["1", "007"]), ColumnType::Text);
+        assert_eq!(infer(&["-01"]), ColumnType::Text);
+        assert_eq!(infer(&["+4612345"]), ColumnType::Text);
+        assert_eq!(infer(&["1", "x"]), ColumnType::Text);
+        for word in ["inf", "-Infinity", "NaN", "1e999"] {
+            assert_eq!(infer(&["0.5", word]), ColumnType::Text);
+        }
+    }
+
+    #[test]
+    fn encoding_writer_test() {
+        let text = "name\npér\n";
+        for encoding in Encoding::ALL {
+            let mut bytes = vec![];
+            {
+                let mut writer = encoding.writer(&mut bytes).unwrap();
+                // é is cut in two between the writes
+                let (start, end) = text.as_bytes().split_at(7);
+                writer.write_all(start).unwrap();
+                writer.write_all(end).unwrap();
+            }
+            assert_eq!(Encoding::detect(&bytes), encoding);
+            let options = CsvOptions {
+                encoding,
+                ..Default::default()
+            };
+            let mut csv = options.reader(Box::new(Cursor::new(bytes)));
+            let rows: Vec<StringRecord> = csv.records().map(Result::unwrap).collect();
+            assert_eq!(rows, vec![StringRecord::from(vec!["pér"])]);
+        }
+        assert!(Encoding::Windows1252
+            .writer(vec![])
+            .unwrap()
+            .write_all("✓".as_bytes())
+            .is_err());
+        assert_eq!(Encoding::from_name("UTF-16LE"), Some(Encoding::Utf16Le));
+    }
+}
@@ -1,7 +1,8 @@
 use crate::controller;
-use crate::controller::command::{Command, PreviousCommand, QueuedCommand};
+use crate::controller::command::{Command, Confirmation, PreviousCommand, QueuedCommand};
 use crate::controller::input::StateMachine;
-use std::collections::HashMap;
+use crate::controller::message::MessageLog;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::hash::Hash;
 use std::path::{Path, PathBuf};
@@ -12,33 +13,91 @@ use crossterm::ExecutableCommand;
 use ratatui::widgets::TableState;
 use regex::Regex;
 use rusqlite::types::ValueRef;
-use rusqlite::{backup, params, Connection, Error, Statement};
+use rusqlite::{backup, params, params_from_iter, Connection, Error, OptionalExtension, Statement};
+use tempfile::TempDir;
 
 use crate::app_error_other;
 use crate::error::{AppError, AppResult};
 use crate::model::converter::insert_csv_data_database;
 use crate::model::datarow::DataItem;
-use crate::tui::TUI;
-
+use crate::tui::{PAGE_SIZE, TUI};
+
+use super::background::{
+    allow_reads_while_writing, connection_pool, only_reads, shared_memory_uri, ConnectionPool,
+    Import, Job,
+};
+use super::collation::Collation;
+use super::compression::{self, Compression};
+use super::convert::{Exportable, Importable};
+use super::converter::CsvImport;
+use super::csv_options::CsvOptions;
 use super::datarow::DataTable;
 use super::db_slice::DatabaseSlice;
-use super::metadata::{create_table_of_tables, populate_table_of_tables};
+use super::export::{TextExport, TextFormat};
+use super::metadata::{
+    create_column_collations, create_table_of_tables, delete_collations, delete_table_source,
+    export_query, get_column_collations, get_key_column, get_table_source, get_tables,
+    get_tables_from_source, not_metadata_table_condition, populate_table_of_tables,
+    rename_collation_column, rename_collation_table, rename_table_source, set_column_collation,
+    set_key_column, set_table_source, TableSource,
+};
+use super::parquet_file::Parquet;
 use super::regexping;
+use super::search::{Search, SearchKind, SearchOptions, SearchOrigin};
+use super::sort::{order_by_clause, OrderKey, SortStack};
+use super::sql_dump::SqlDump;
 use super::{converter, sql_queries};
 
+const ROWID_ALIAS: &str = "__dataman_rowid";
+/// The formats a single file can be opened in, for the error when it has another extension.
+const OPEN_FORMATS: &str = "csv, xlsx, xlsm, xlsb, xls, ods, parquet, sqlite or sqlite3";
+/// The formats a table can be exported to.
+pub(crate) const EXPORT_FORMATS: [&str; 8] = [
+    "csv", "json", "ndjson", "md", "html", "sql", "xlsx", "parquet",
+];
+/// The tables of the connection, with those of the temp schema, where a database that was opened
+/// directly keeps our metadata tables. Temp tables get negative indices, so they don't collide with
+/// the rowids of sqlite_master.
+const TABLES: &str = "(SELECT rowid AS idx, name, sql FROM sqlite_master WHERE type = 'table'
+    UNION ALL SELECT -rowid, name, sql FROM sqlite_temp_master WHERE type = 'table')";
+
+/// The formats the workspace can be saved in, a SQLite database or the export formats.
+pub(crate) const SAVE_FORMATS: &str = "csv, json, ndjson, md, html, sql, xlsx, parquet or sqlite";
+
 #[derive(Debug)]
 pub struct Database {
     pub(crate) connection: Connection,
     pub(crate) header_idx: u16,
-    pub(crate) order_column: Option<String>,
-    pub(crate) is_asc_order: bool,
-    pub(crate) current_table_idx: u16,
+    pub(crate) sort_stack: SortStack,
+    /// The rowid of the current table in sqlite_master, see `TABLES`.
+    pub(crate) current_table_idx: i64,
     pub(crate) slice: DatabaseSlice,
     pub(crate) input: String,
     pub(crate) character_index: usize,
     pub(crate) last_command: PreviousCommand,
     pub(crate) queued_command: Option<QueuedCommand>,
     pub(crate) input_mode_state_machine: StateMachine,
+    pub(crate) search: Option<Search>,
+    pub(crate) search_options: SearchOptions,
+    pub(crate) search_origin: Option<SearchOrigin>,
+    /// `(current_table_idx, rows)`, counting a big table takes long so we only do it after it changed.
+    row_count: Option<(i64, u32)>,
+    /// Connections for work on other threads, `None` for a private in-memory database.
+    pool: Option<ConnectionPool>,
+    pub(crate) job: Option<Job>,
+    /// The rest of a big CSV file, loading on a worker thread.
+    pub(crate) import: Option<Import>,
+    /// A command that needs a real table, it runs once the virtual table has been materialized.
+    pub(crate) pending_command: Option<Command>,
+    /// The tables whose data has changed since they were opened or last saved.
+    pub(crate) modified: HashSet<String>,
+    /// A destructive command waiting for a y or n, it's shown in a dialog over the table.
+    pub(crate) confirmation: Option<Confirmation>,
+    /// The messages and errors of earlier commands, for the history popup.
+    pub(crate) messages: MessageLog,
+    /// Where stdin was copied to, a virtual table may still read it. Dropped after the
+    /// connection, which removes the copy.
+    stdin_dirs: Vec<TempDir>,
     // regex_map: HashMap<String, Regex>,
 }
 
@@ -46,12 +105,13 @@ impl Database {
     pub fn new(connection: Connection) -> AppResult<Self> {
         let query = r#"SELECT rowid FROM sqlite_master WHERE type='table' ORDER BY rowid LIMIT 1;"#
             .to_string();
-        let rowid: u16 = connection.query_row(&query, [], |row| row.get(0))?;
+        let rowid: i64 = connection.query_row(&query, [], |row| row.get(0))?;
 
         let mut table_state = TableState::new();
         table_state.select(Some(0));
         let slice = DatabaseSlice::new(vec![], vec![], table_state, 0, 0);
         create_table_of_tables(&connection)?;
+        create_column_collations(&connection)?;
         if let Err(err) = regexping::custom_functions::add_custom_functions(&connection) {
             log::info!("Error adding custom functions, e.g. REGEXP: {}", err);
             Err(AppError::Sqlite(err))
@@ -59,8 +119,7 @@ impl Database {
             Ok(Database {
                 connection,
                 header_idx: 0,
-                order_column: Some("id".to_string()),
-                is_asc_order: true,
+                sort_stack: SortStack::default(),
                 current_table_idx: rowid,
                 slice,
                 input: String::new(),
@@ -68,6 +127,18 @@ impl Database {
                 last_command: PreviousCommand::new(Command::None, None),
                 queued_command: None,
                 input_mode_state_machine: StateMachine::new(),
+                search: None,
+                search_options: SearchOptions::default(),
+                search_origin: None,
+                row_count: None,
+                pool: None,
+                job: None,
+                import: None,
+                pending_command: None,
+                modified: HashSet::new(),
+                confirmation: None,
+                messages: MessageLog::default(),
+                stdin_dirs: vec![],
             })
         }
     }
@@ -89,78 +160,540 @@ impl Database {
             .ok_or(app_error_other!("Could not get header"))
     }
 
-    fn get_ordering(&self) -> String {
-        let ordering = if self.is_asc_order { "ASC" } else { "DESC" };
-        match &self.order_column {
-            Some(order_column) => format!(r#" ORDER BY "{}" {} "#, order_column, ordering),
-            None => "".to_string(),
-        }
+    /// The sort keys followed by the rowid, so rows that are equal in all sort keys keep their order.
+    fn get_order_keys(&self) -> AppResult<Vec<OrderKey>> {
+        let table_name = self.get_current_table_name()?;
+        let collations = get_column_collations(&self.connection, &table_name)?;
+        let mut keys = self.sort_stack.order_keys(&collations);
+        keys.push(OrderKey::rowid());
+        Ok(keys)
+    }
+
+    fn get_ordering(&self) -> AppResult<String> {
+        Ok(order_by_clause(&self.get_order_keys()?))
+    }
+
+    pub(crate) fn get_current_collation(&self) -> AppResult<Collation> {
+        let table_name = self.get_current_table_name()?;
+        let header = self.get_current_header()?;
+        let collations = get_column_collations(&self.connection, &table_name)?;
+        Ok(collations.get(&header).copied().unwrap_or_default())
+    }
+
+    /// Switches the current column to the next collation and returns the new one.
+    pub(crate) fn cycle_collation(&mut self) -> AppResult<Collation> {
+        let table_name = self.get_current_table_name()?;
+        let header = self.get_current_header()?;
+        let collation = self.get_current_collation()?.next();
+        set_column_collation(&self.connection, &table_name, &header, collation)?;
+        Ok(collation)
     }
     pub fn get(&mut self, limit: u32, offset: u32, table_name: String) -> AppResult<DataTable> {
-        if self.slice.is_unchanged() {
+        if self.slice.is_unchanged() || self.is_busy() {
             return Ok((self.slice.headers.clone(), self.slice.data_rows.clone()));
         }
-        let query = format!(
-            r#"SELECT * FROM "{}" {} LIMIT {} OFFSET {};"#,
-            table_name,
-            self.get_ordering(),
-            limit,
-            offset
-        );
-
-        let (headers, data_rows) = {
-            let mut data_rows = vec![];
-            let mut stmt = self.prepare(&query).unwrap();
-            let headers: Vec<String> = stmt
-                .column_names()
-                .into_iter()
-                .map(|h| h.to_string())
-                .collect();
-            let mut rows = stmt.query([])?;
-            while let Some(row) = rows.next()? {
-                let mut items = vec![];
-                let mut i = 0;
-                while let Ok(field) = row.get_ref(i) {
-                    items.push(DataItem::from(field));
-                    i += 1;
-                }
-                data_rows.push(items);
+        let keys = self.get_order_keys()?;
+        let ordering = order_by_clause(&keys);
+        let page_ordering = format!(r#""{table_name}"{ordering}"#);
+        let anchor = self.find_anchor(offset, &keys, &page_ordering, &table_name)?;
+        let columns = format!(r#"rowid AS "{ROWID_ALIAS}", *"#);
+        let read_row = |row: &rusqlite::Row| -> rusqlite::Result<(i64, Vec<DataItem>)> {
+            let mut items = vec![];
+            let mut i = 1;
+            while let Ok(field) = row.get_ref(i) {
+                items.push(DataItem::from(field));
+                i += 1;
+            }
+            Ok((row.get(0)?, items))
+        };
+        let (names, rows) = match &anchor {
+            Some(anchor) => {
+                self.query_after(&columns, &table_name, &keys, anchor, limit, 0, read_row)?
+            }
+            None => {
+                let query = format!(
+                    r#"SELECT {} FROM "{}" {} LIMIT {} OFFSET {};"#,
+                    columns, table_name, ordering, limit, offset
+                );
+                let mut stmt = self.prepare(&query)?;
+                let names = column_names(&stmt);
+                let rows = stmt
+                    .query_map([], read_row)?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                (names, rows)
             }
-            (headers, data_rows)
         };
+        // the first column is the rowid, which is not shown
+        let headers: Vec<String> = names.into_iter().skip(1).collect();
+        let (row_ids, data_rows): (Vec<i64>, Vec<Vec<DataItem>>) = rows.into_iter().unzip();
+        self.slice.page_keys.clear();
+        // the last key is the rowid, which we already have in row_ids
+        let key_columns: Option<Vec<usize>> = keys[..keys.len() - 1]
+            .iter()
+            .map(|key| headers.iter().position(|header| header == &key.column))
+            .collect();
+        if let Some(key_columns) = key_columns {
+            self.slice.page_keys.ordering = page_ordering;
+            self.slice.page_keys.offset = offset;
+            self.slice.page_keys.keys = data_rows
+                .iter()
+                .zip(row_ids.iter())
+                .map(|(row, id)| {
+                    let mut values: Vec<DataItem> =
+                        key_columns.iter().map(|i| row[*i].clone()).collect();
+                    values.push(DataItem::Integer(*id));
+                    values
+                })
+                .collect();
+        }
         self.slice.data_rows.clone_from(&data_rows);
         self.slice.headers.clone_from(&headers);
+        self.slice.row_ids = row_ids;
         self.slice.is_unchanged = true;
         Ok((headers, data_rows))
     }
-    pub(crate) fn count_rows(&self) -> Option<u32> {
+
+    /// The ordering values of the row just before `offset`, to start the page after. Found on the
+    /// last page if it is there, otherwise by stepping from the nearest end of the last page when
+    /// that is closer than the start of the table. `None` means we have to use OFFSET.
+    fn find_anchor(
+        &self,
+        offset: u32,
+        keys: &[OrderKey],
+        page_ordering: &str,
+        table_name: &str,
+    ) -> AppResult<Option<Vec<DataItem>>> {
+        let page_keys = &self.slice.page_keys;
+        let Some(before) = offset.checked_sub(1) else {
+            return Ok(None);
+        };
+        if let Some(anchor) = page_keys.row(page_ordering, before) {
+            return Ok(Some(anchor.clone()));
+        }
+        if let Some((last, anchor)) = page_keys.last(page_ordering) {
+            if before > last {
+                return self.step_from(anchor, keys, before - last - 1, table_name);
+            }
+        }
+        if let Some(anchor) = page_keys.row(page_ordering, page_keys.offset) {
+            let distance = page_keys.offset - offset;
+            if distance < offset {
+                let reversed: Vec<OrderKey> = keys.iter().map(OrderKey::reversed).collect();
+                return self.step_from(anchor, &reversed, distance, table_name);
+            }
+        }
+        Ok(None)
+    }
+
+    /// The ordering values of the row `skip` rows after the anchor row, ordered by `keys`.
+    fn step_from(
+        &self,
+        anchor: &[DataItem],
+        keys: &[OrderKey],
+        skip: u32,
+        table_name: &str,
+    ) -> AppResult<Option<Vec<DataItem>>> {
+        let columns = keys
+            .iter()
+            .map(|key| format!(r#""{}""#, key.column))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let (_, rows) = self.query_after(&columns, table_name, keys, anchor, 1, skip, |row| {
+            (0..keys.len())
+                .map(|i| Ok(DataItem::from(row.get_ref(i)?)))
+                .collect::<rusqlite::Result<Vec<_>>>()
+        })?;
+        Ok(rows.into_iter().next())
+    }
+
+    /// Reads `limit` of the rows after the anchor, ordered by `keys` and skipping the first `skip`,
+    /// together with the column names. When the rows where the first key is NULL come last they are
+    /// read with a query of their own, so that both queries can use an index on the first key.
+    #[allow(clippy::too_many_arguments)]
+    fn query_after<T>(
+        &self,
+        columns: &str,
+        table_name: &str,
+        keys: &[OrderKey],
+        anchor: &[DataItem],
+        limit: u32,
+        skip: u32,
+        read: impl Fn(&rusqlite::Row) -> rusqlite::Result<T>,
+    ) -> AppResult<(Vec<String>, Vec<T>)> {
+        let ordering = order_by_clause(keys);
+        let select = |condition: &str, limit: u32, skip: u32| {
+            format!(
+                r#"SELECT {columns} FROM "{table_name}" WHERE {condition} {ordering} LIMIT {limit} OFFSET {skip};"#
+            )
+        };
+        let split = sql_queries::build::nulls_come_last(keys, anchor);
+        let condition = if split {
+            sql_queries::build::keyset_condition_not_null(keys, anchor)
+        } else {
+            sql_queries::build::keyset_condition(keys, anchor)
+        };
+        let mut stmt = self.prepare(&select(&condition, limit, skip))?;
+        let names = column_names(&stmt);
+        let mut rows = stmt
+            .query_map(params_from_iter(anchor), &read)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let found = rows.len() as u32;
+        if !split || found == limit {
+            return Ok((names, rows));
+        }
+        // rows before the NULLs that were skipped, all of them if none were read
+        let skipped = if found > 0 || skip == 0 {
+            skip
+        } else {
+            let query = format!(r#"SELECT COUNT(*) FROM "{table_name}" WHERE {condition};"#);
+            self.prepare(&query)?
+                .query_row(params_from_iter(anchor), |row| row.get(0))?
+        };
+        let nulls = format!(r#""{}" IS NULL"#, keys[0].column);
+        let mut stmt =
+            self.prepare(&select(&nulls, limit - found, skip.saturating_sub(skipped)))?;
+        let null_rows = stmt.query_map([], &read)?;
+        for row in null_rows {
+            rows.push(row?);
+        }
+        Ok((names, rows))
+    }
+
+    /// Forget the row count and page keys, they are out of date after the data has changed.
+    pub fn invalidate_cache(&mut self) {
+        // while a job runs the caches are all the UI has, `poll_job` invalidates them when it is done
+        if self.is_busy() {
+            return;
+        }
+        self.row_count = None;
+        self.slice.page_keys.clear();
+        if let Some(search) = self.search.as_mut() {
+            search.count = None;
+        }
+    }
+
+    /// Runs the SQL on a worker thread if we have a connection pool, otherwise right away.
+    fn execute_in_background(&mut self, command: Command, sql: String) -> AppResult<()> {
+        match &self.pool {
+            Some(pool) => {
+                let table_name = self.get_current_table_name()?;
+                let modifies = !only_reads(&self.connection, &sql);
+                self.job = Some(Job::spawn(pool, command, &table_name, sql, modifies)?);
+                Ok(())
+            }
+            None => self.execute_batch(&sql),
+        }
+    }
+
+    /// Remembers that `command` has changed the current table. A command that runs as a job
+    /// counts once the job has finished, `poll_job` does that. The SQL prompt marks its table
+    /// itself, a plain SELECT changes nothing, and a deleted table has nothing left to save.
+    pub(crate) fn mark_modified(&mut self, command: &Command) {
+        if matches!(command, Command::SqlQuery | Command::DeleteTable) {
+            return;
+        }
+        if command.is_mutating() && !self.is_busy() {
+            if let Ok(table_name) = self.get_current_table_name() {
+                self.modified.insert(table_name);
+            }
+        }
+    }
+
+    /// Forgets the changes to `table_names`, they have been written to a file.
+    pub(crate) fn mark_saved(&mut self, table_names: &[String]) {
+        for table_name in table_names {
+            self.modified.remove(table_name);
+        }
+    }
+
+    /// Whether any table has changes that haven't been saved.
+    pub(crate) fn is_modified(&self) -> bool {
+        !self.modified.is_empty()
+    }
+
+    pub(crate) fn is_busy(&self) -> bool {
+        self.job.is_some()
+    }
+
+    pub(crate) fn cancel_job(&mut self) {
+        if let Some(job) = self.job.as_mut() {
+            job.cancel();
+        }
+    }
+
+    /// Returns the message for a finished job, `None` if there is no job or it is still running.
+    pub(crate) fn poll_job(&mut self) -> Option<AppResult<String>> {
+        let result = self.job.as_ref()?.try_finish()?;
+        let job = self.job.take()?;
+        self.slice.has_changed();
+        self.invalidate_cache();
+        let secs = job.elapsed_secs();
+        let result = match result {
+            Ok(()) if job.command == Command::Materialize => {
+                self.table_materialized(&job.table_name)
+            }
+            result => result,
+        };
+        if result.is_err() {
+            self.pending_command = None;
+        }
+        // a materialized table has the same data as the file it was read from
+        if result.is_ok()
+            && job.modifies
+            && job.command.is_mutating()
+            && job.command != Command::Materialize
+        {
+            self.modified.insert(job.table_name.clone());
+        }
+        Some(match result {
+            Ok(()) => Ok(format!("finished in {secs:.1}s")),
+            Err(_) if job.is_cancelled() => Err(AppError::UserCancelled),
+            Err(err) => Err(err),
+        })
+    }
+
+    /// Loads the rest of the CSV file on a worker thread if we have a connection pool, otherwise
+    /// right away.
+    fn start_import(&mut self, mut csv_import: CsvImport) -> AppResult<()> {
+        match &self.pool {
+            Some(pool) => {
+                self.import = Some(Import::spawn(pool, csv_import, self.current_table_idx)?);
+                Ok(())
+            }
+            None => csv_import.insert_all(&self.connection),
+        }
+    }
+
+    pub(crate) fn is_loading(&self) -> bool {
+        self.import.is_some()
+    }
+
+    /// Returns the message for a finished import, `None` if there is no import or it is still
+    /// loading. While loading, a page that isn't full is fetched again to show the new rows.
+    pub(crate) fn poll_import(&mut self) -> Option<AppResult<String>> {
+        let Some(result) = self.import.as_ref()?.try_finish() else {
+            if self.slice.data_rows.len() < PAGE_SIZE as usize && !self.is_busy() {
+                self.slice.has_changed();
+            }
+            return None;
+        };
+        let import = self.import.take()?;
+        self.slice.has_changed();
+        self.invalidate_cache();
+        Some(result.map(|()| {
+            let problems = match import.problems() {
+                0 => String::new(),
+                n => format!(", {n} malformed rows are listed in import_errors"),
+            };
+            format!(
+                "loaded {} rows into {} in {:.1}s{problems}",
+                import.rows(),
+                import.table_name,
+                import.elapsed_secs()
+            )
+        }))
+    }
+
+    /// Blocks until the rest of the CSV file has loaded.
+    pub(crate) fn wait_for_import(&mut self) -> AppResult<()> {
+        loop {
+            match self.poll_import() {
+                Some(result) => return result.map(|_| ()),
+                None if !self.is_loading() => return Ok(()),
+                None => std::thread::sleep(time::Duration::from_millis(10)),
+            }
+        }
+    }
+
+    /// The row count if we have counted the current table since it last changed.
+    pub(crate) fn cached_row_count(&self) -> Option<u32> {
+        self.row_count
+            .filter(|(table_idx, _)| *table_idx == self.current_table_idx)
+            .map(|(_, rows)| rows)
+    }
+
+    pub fn count_rows(&mut self) -> Option<u32> {
+        if let Some(rows) = self.cached_row_count() {
+            return Some(rows);
+        }
+        if let Some(import) = self
+            .import
+            .as_ref()
+            .filter(|import| import.table_idx == self.current_table_idx)
+        {
+            return Some(import.rows() as u32);
+        }
         let table_name = self.get_current_table_name().ok()?;
-        self.connection
+        let rows = self
+            .connection
             .query_row(
                 &format!(r#"SELECT COUNT(*) FROM "{table_name}";"#),
                 [],
                 |row| row.get(0),
             )
-            .ok()
+            .ok()?;
+        self.row_count = Some((self.current_table_idx, rows));
+        Some(rows)
+    }
+    /// Writes the current table as CSV in the order it is shown, e.g. to stdout at the end of a
+    /// pipeline.
+    pub fn write_current_table<W: std::io::Write>(&self, writer: W) -> AppResult<()> {
+        let table_name = self.get_current_table_name()?;
+        let query = export_query(&self.connection, &table_name, &self.get_ordering()?)?;
+        converter::write_csv(&self.connection, &query, writer, &CsvOptions::default())
+    }
+
+    /// Saves the current table, or all tables, as worksheets in an Excel workbook. Returns the
+    /// tables that were saved.
+    pub(crate) fn save_to_xlsx(&self, path: &Path, all_tables: bool) -> AppResult<Vec<String>> {
+        let table_names = if all_tables {
+            get_tables(&self.connection)?
+        } else {
+            vec![self.get_current_table_name()?]
+        };
+        let tables: Vec<_> = table_names
+            .iter()
+            .map(|table_name| (table_name.clone(), None))
+            .collect();
+        converter::save_to_xlsx(&self.connection, &tables, &HashMap::new(), path)?;
+        Ok(table_names)
+    }
+
+    /// Saves the current table, or all tables, as a SQL dump. Returns the tables that were saved.
+    pub(crate) fn save_to_sql(&self, path: &Path, all_tables: bool) -> AppResult<Vec<String>> {
+        let table_names = if all_tables {
+            get_tables(&self.connection)?
+        } else {
+            vec![self.get_current_table_name()?]
+        };
+        SqlDump::new(table_names.clone()).export_to_db(path, &self.connection)?;
+        Ok(table_names)
+    }
+
+    /// Writes a table to `path` in the format of its extension. Text formats and SQL are
+    /// compressed if the name ends in `.gz`, `.zst` or `.bz2`.
+    pub(crate) fn export_table(&self, table_name: &str, path: &Path) -> AppResult<()> {
+        if let Some(format) = TextFormat::from_extension(path) {
+            return TextExport::new(table_name, format).export_to_db(path, &self.connection);
+        }
+        let is_compressed = Compression::from_extension(path).is_some();
+        let extension = compression::strip_extension(path)
+            .extension()
+            .map(|extension| extension.to_string_lossy().into_owned());
+        match extension.as_deref() {
+            Some("csv") => converter::save_to_csv_file(
+                &self.connection,
+                table_name,
+                path,
+                &CsvOptions::default(),
+            ),
+            Some("sql") => {
+                SqlDump::new(vec![table_name.to_string()]).export_to_db(path, &self.connection)
+            }
+            Some("xlsx" | "parquet") if is_compressed => Err(AppError::from(
+                "Only csv, json, markdown, html and sql files can be saved compressed",
+            )),
+            Some("xlsx") => {
+                let tables = [(table_name.to_string(), None)];
+                converter::save_to_xlsx(&self.connection, &tables, &HashMap::new(), path)
+            }
+            Some("parquet") => Parquet::new(table_name).export_to_db(path, &self.connection),
+            extension => Err(AppError::unsupported_format(
+                extension.unwrap_or_default(),
+                &EXPORT_FORMATS.join(", "),
+            )),
+        }
+    }
+
+    /// Writes the current table back to the file it was imported from, in the same format. The
+    /// old file is kept as `<file>.bak`. A CSV file keeps the delimiter, encoding and header row it
+    /// was read with. A workbook is written with every table that came from it, each to its own
+    /// sheet below the title rows that were skipped on import. Returns the file that was written
+    /// and the tables in it.
+    pub(crate) fn write_back(&self) -> AppResult<(PathBuf, Vec<String>)> {
+        let table_name = self.get_current_table_name()?;
+        let source = get_table_source(&self.connection, &table_name)?.ok_or_else(|| {
+            app_error_other!(format!(
+                "{table_name} has no file to write back to, save it with ctrl-s"
+            ))
+        })?;
+        if !matches!(source.format.as_str(), "csv" | "parquet" | "xlsx") {
+            return Err(app_error_other!(format!(
+                ".{} files can't be written, save {table_name} with ctrl-s",
+                source.format
+            )));
+        }
+        if source.path.exists() {
+            std::fs::copy(&source.path, backup_path(&source.path))?;
+        }
+        let table_names = match source.format.as_str() {
+            "xlsx" => {
+                let tables = get_tables_from_source(&self.connection, &source.path)?;
+                let titles = converter::spreadsheet_titles(&source.path, source.skip_rows)?;
+                converter::save_to_xlsx(&self.connection, &tables, &titles, &source.path)?;
+                tables
+                    .into_iter()
+                    .map(|(table_name, _)| table_name)
+                    .collect()
+            }
+            "csv" => {
+                converter::save_to_csv_file(
+                    &self.connection,
+                    &table_name,
+                    &source.path,
+                    &source.csv,
+                )?;
+                vec![table_name]
+            }
+            _ => {
+                self.export_table(&table_name, &source.path)?;
+                vec![table_name]
+            }
+        };
+        Ok((source.path, table_names))
+    }
+
+    /// The file each table is exported to by `export_all`, `<dir>/<table>.<extension>`.
+    pub(crate) fn export_paths(&self, dir: &Path, extension: &str) -> AppResult<Vec<PathBuf>> {
+        let extension = extension.trim().trim_start_matches('.');
+        Ok(get_tables(&self.connection)?
+            .into_iter()
+            .map(|table_name| dir.join(format!("{table_name}.{extension}")))
+            .collect())
+    }
+
+    /// Writes every table to its own file in `dir`, which is created if needed. Returns the
+    /// files that were written.
+    pub(crate) fn export_all(&self, dir: &Path, extension: &str) -> AppResult<Vec<PathBuf>> {
+        std::fs::create_dir_all(dir)?;
+        let paths = self.export_paths(dir, extension)?;
+        for (table_name, path) in get_tables(&self.connection)?.iter().zip(&paths) {
+            self.export_table(table_name, path)?;
+        }
+        Ok(paths)
     }
-    pub fn get_cell(&self, id: i32, header: &str) -> AppResult<String> {
+
+    pub fn get_cell(&self, id: i64, header: &str) -> AppResult<String> {
         let table_name = self.get_current_table_name()?;
-        let query = format!(r#"SELECT "{}" FROM "{}" WHERE id = ?;"#, header, table_name);
+        let query = format!(
+            r#"SELECT "{}" FROM "{}" WHERE rowid = ?;"#,
+            header, table_name
+        );
         let mut stmt = self.prepare(&query)?;
         log::info!("id: {}", id);
-        let mut rows = stmt.query(params![id])?;
-        let row = rows.next()?.unwrap();
-        let cell = row.get(0)?;
+        let cell = stmt.query_row(params![id], |row| Ok(DataItem::from(row.get_ref(0)?)))?;
 
-        Ok(cell)
+        Ok(cell.to_string())
     }
-    fn prepare(&self, sql: &str) -> rusqlite::Result<Statement> {
+    fn prepare(&self, sql: &str) -> rusqlite::Result<Statement<'_>> {
         log::info!("{sql}");
         self.connection.prepare(sql)
     }
     fn execute<P: rusqlite::Params>(&self, sql: &str, params: P) -> AppResult<()> {
         log::info!("{sql}");
-        self.connection.execute(sql, params)?;
+        self.connection
+            .execute(sql, params)
+            .map_err(|err| AppError::query_failed(sql, err))?;
         Ok(())
     }
 
@@ -180,7 +713,7 @@ impl Database {
             Err(err) => {
                 self.execute("ROLLBACK;", [])?;
                 log::info!("Error executing batch query: {}", err);
-                Err(AppError::Sqlite(err))
+                Err(AppError::query_failed(sql, err))
             }
         }
     }
@@ -196,7 +729,7 @@ impl Database {
         // create a new column in the table. The new value for each row is the value string value of column name after running fun function on it.
         let table_name = self.get_current_table_name()?;
         // for each row in the table, run fun on the value of column name and insert the result into the new column
-        let query = format!(r#"SELECT "id", "{old_column_name}" FROM "{table_name}""#);
+        let query = format!(r#"SELECT rowid, "{old_column_name}" FROM "{table_name}""#);
         let mut binding = self.prepare(&query)?;
         let mut rows = binding.query([])?;
         let create_column_query =
@@ -210,7 +743,7 @@ impl Database {
             let value: String = row.get(1)?;
             let derived_value = fun(value).unwrap_or("NULL".to_string()).replace('\'', "''");
             let update_query = format!(
-                r#"UPDATE "{table_name}" SET "{new_column_name}" = '{derived_value}' WHERE id = '{id}';"#,
+                r#"UPDATE "{table_name}" SET "{new_column_name}" = '{derived_value}' WHERE rowid = '{id}';"#,
             );
             transaction.push_str(&update_query);
         }
@@ -218,32 +751,70 @@ impl Database {
         Ok(())
     }
 
-    pub(crate) fn get_current_id(&self) -> AppResult<i32> {
-        let i = self.slice.table_state.selected().unwrap_or(0);
+    /// The rowid of the row under the cursor.
+    pub(crate) fn get_current_id(&self) -> AppResult<i64> {
+        if let Some(id) = self.slice.selected_row_id() {
+            return Ok(id);
+        }
+        // the slice is out of date, so we look the row up with the same ordering and offset as the view
         let query = format!(
-            r#"SELECT rowid FROM "{}" LIMIT 1 OFFSET {};"#,
+            r#"SELECT rowid FROM "{}" {} LIMIT 1 OFFSET {};"#,
             self.get_current_table_name()?,
-            i
+            self.get_ordering()?,
+            self.current_row_number()
         );
-        let id: i32 = self.connection.query_row(&query, [], |row| row.get(0))?;
+        let id: i64 = self.connection.query_row(&query, [], |row| row.get(0))?;
         Ok(id)
     }
 
+    pub(crate) fn delete_row(&mut self) -> AppResult<Option<String>> {
+        let table_name = self.get_current_table_name()?;
+        let id = self.get_current_id()?;
+        let query = sql_queries::build::delete_row_query(&table_name);
+        self.execute(&query, params![id])?;
+        self.slice.has_changed();
+        self.invalidate_cache();
+        let max_row = self.count_rows().unwrap_or(0).saturating_sub(1);
+        if self.current_row_number() > max_row {
+            self.previous_row()?;
+        }
+        Ok(Some(format!("Deleted row {id} from {table_name}")))
+    }
+
     pub(crate) fn sort(&mut self) -> AppResult<()> {
         // sort by current header
         let header = self.get_current_header()?;
+        self.sort_by(&header);
+        Ok(())
+    }
+
+    /// Sorts by `header` like pressing sort on it does, and goes back to the first row.
+    pub fn sort_by(&mut self, header: &str) {
         self.slice.table_state.select(Some(0));
-        if self.order_column == Some(header.clone())
-        //|| (self.order_column.is_none() && (header == "id"))
-        {
-            self.is_asc_order = !self.is_asc_order;
-        } else {
-            self.is_asc_order = true;
-            self.order_column = Some(header);
-        }
+        self.slice.row_offset = 0;
+        self.sort_stack.sort_by(header);
+    }
+
+    /// Scrolls to the page that starts at row `row_offset`, the next `get` reads it.
+    pub fn scroll_to(&mut self, row_offset: u32) {
+        self.slice.update(0, row_offset);
+    }
 
+    /// Add the current header as another sort key, with lower priority than the existing ones.
+    pub(crate) fn add_sort_column(&mut self) -> AppResult<()> {
+        let header = self.get_current_header()?;
+        self.slice.table_state.select(Some(0));
+        self.slice.row_offset = 0;
+        self.sort_stack.add(&header);
         Ok(())
     }
+
+    pub(crate) fn clear_sort(&mut self) {
+        self.slice.table_state.select(Some(0));
+        self.slice.row_offset = 0;
+        self.sort_stack.clear();
+    }
+
     pub fn get_table_names(&self) -> AppResult<Vec<String>> {
         let query = r#"SELECT name FROM sqlite_master WHERE type='table' ORDER BY rowid;"#;
         let mut stmt = self.prepare(query)?;
@@ -256,11 +827,10 @@ impl Database {
         Ok(table_names)
     }
     pub fn get_current_table_name(&self) -> AppResult<String> {
-        let query = format!(
-            r#"SELECT name FROM sqlite_master WHERE type='table' AND rowid='{}';"#,
-            self.current_table_idx
-        );
-        let table_name = self.connection.query_row(&query, [], |row| row.get(0))?;
+        let query = format!("SELECT name FROM {TABLES} WHERE idx = ?;");
+        let table_name = self
+            .connection
+            .query_row(&query, [self.current_table_idx], |row| row.get(0))?;
         Ok(table_name)
     }
 
@@ -297,26 +867,283 @@ impl Database {
 
         let res = self.execute(&query, []);
         res?;
+        // the rows are copied with the key we added, it is still ours
+        if let Some(key_column) = get_key_column(&self.connection, &old_table_name)? {
+            set_key_column(&self.connection, &new_table_name, &key_column)?;
+        }
         self.select_table(&new_table_name)?;
         Ok(())
     }
 
-    // go to first match
-    pub(crate) fn exact_search(&mut self, search_header: &str, pattern: &str) -> AppResult<()> {
+    fn current_row_number(&self) -> u32 {
+        self.slice.row_offset + self.slice.table_state.selected().unwrap_or(0) as u32
+    }
+
+    /// The number of rows that match the search.
+    fn count_matches(&self, search: &Search) -> AppResult<u32> {
         let table_name = self.get_current_table_name()?;
-        let current_row =
-            self.slice.row_offset + self.slice.table_state.selected().unwrap_or(0) as u32;
-        let query = sql_queries::build::exact_search_query(
-            &self.get_ordering(),
-            search_header,
-            current_row,
-            &table_name,
-        );
+        let columns = self.search_columns(search, &table_name)?;
+        let condition = sql_queries::build::search_condition(&columns);
+        let count = self.connection.query_row(
+            &format!(r#"SELECT COUNT(*) FROM "{table_name}" WHERE {condition};"#),
+            [search.regex.as_str()],
+            |row| row.get(0),
+        )?;
+        Ok(count)
+    }
+
+    fn search_columns(&self, search: &Search, table_name: &str) -> AppResult<Vec<String>> {
+        match &search.column {
+            Some(column) => Ok(vec![column.clone()]),
+            None => self.get_headers(table_name),
+        }
+    }
+
+    // go to first match at or after the cursor
+    pub(crate) fn search(&mut self, pattern: &str, kind: SearchKind) -> AppResult<()> {
+        let column = if self.search_options.all_columns {
+            None
+        } else {
+            Some(self.get_current_header()?)
+        };
+        let mut search = Search::new(kind, pattern, column, self.search_options)?;
+        let row_number = self.current_row_number();
+        search.current = match self.key_at(row_number)? {
+            Some(anchor) => self.match_from(&search, &anchor, row_number, true, true)?,
+            None => None,
+        };
+        search.count = Some(self.count_matches(&search)?);
+        self.show_search(search)
+    }
+
+    pub(crate) fn search_next(&mut self, forward: bool) -> AppResult<()> {
+        let mut search = self
+            .search
+            .take()
+            .ok_or(app_error_other!("No previous search"))?;
+        let row_number = self.current_row_number();
+        let found = self.key_at(row_number).and_then(|anchor| match anchor {
+            Some(anchor) => self.match_from(&search, &anchor, row_number, forward, false),
+            None => Ok(None),
+        });
+        let found = match found {
+            Ok(found) => found,
+            Err(err) => {
+                self.search = Some(search);
+                return Err(err);
+            }
+        };
+        search.current = found;
+        // the data changed since the matches were counted
+        if search.count.is_none() {
+            search.count = self.count_matches(&search).ok();
+        }
+        self.show_search(search)
+    }
+
+    /// Keeps `search` and moves the cursor to its current match.
+    fn show_search(&mut self, search: Search) -> AppResult<()> {
+        let row_number = search.current;
+        let status = search.status();
+        self.search = Some(search);
+        match row_number {
+            Some(row_number) => self.jump_to_row(row_number),
+            None => Err(app_error_other!(status)),
+        }
+    }
+
+    pub(crate) fn start_incremental_search(&mut self) {
+        self.search_origin = Some(SearchOrigin {
+            selected: self.slice.table_state.selected().unwrap_or(0),
+            row_offset: self.slice.row_offset,
+            search: self.search.clone(),
+            key: self.row_key(self.current_row_number()),
+        });
+    }
+
+    /// The ordering keys of row `row_number` if it is on the page we read last.
+    fn row_key(&self, row_number: u32) -> Option<Vec<DataItem>> {
+        let table_name = self.get_current_table_name().ok()?;
+        let ordering = format!(r#""{table_name}"{}"#, self.get_ordering().ok()?);
+        self.slice.page_keys.row(&ordering, row_number).cloned()
+    }
 
-        let row_number: u32 = self
+    /// The ordering keys of row `row_number`, from the last page read if it is on it. `None` if
+    /// the table doesn't have that many rows.
+    fn key_at(&self, row_number: u32) -> AppResult<Option<Vec<DataItem>>> {
+        if let Some(key) = self.row_key(row_number) {
+            return Ok(Some(key));
+        }
+        let table_name = self.get_current_table_name()?;
+        let keys = self.get_order_keys()?;
+        let key_columns = keys
+            .iter()
+            .map(|key| format!(r#""{}""#, key.column))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let query = format!(
+            r#"SELECT {key_columns} FROM "{table_name}" {} LIMIT 1 OFFSET {row_number};"#,
+            order_by_clause(&keys)
+        );
+        let key = self
             .connection
-            .query_row(&query, [pattern], |row| row.get(0))?;
-        let row_number = row_number - 1;
+            .query_row(&query, [], |row| {
+                (0..keys.len())
+                    .map(|i| Ok(DataItem::from(row.get_ref(i)?)))
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .optional()?;
+        Ok(key)
+    }
+
+    /// The row number of the first row after the `anchor` row, which is row `row_number`, that
+    /// matches, or of the last one before it when going backward. The anchor row itself counts
+    /// with `include_anchor`. It wraps around at the end of the table. It doesn't number every
+    /// row: it scans from the anchor with `LIMIT 1` and only counts the rows it passed.
+    fn match_from(
+        &self,
+        search: &Search,
+        anchor: &[DataItem],
+        row_number: u32,
+        forward: bool,
+        include_anchor: bool,
+    ) -> AppResult<Option<u32>> {
+        let table_name = self.get_current_table_name()?;
+        let keys = self.get_order_keys()?;
+        let scan_keys: Vec<OrderKey> = if forward {
+            keys.clone()
+        } else {
+            keys.iter().map(OrderKey::reversed).collect()
+        };
+        let columns = self.search_columns(search, &table_name)?;
+        let key_columns = keys
+            .iter()
+            .map(|key| format!(r#""{}""#, key.column))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let ordering = order_by_clause(&scan_keys);
+        let condition = sql_queries::build::search_condition(&columns);
+        // the regex is ?1 and the anchor comes after it, the rowid is its last key
+        let regex = DataItem::Text(search.regex.as_str().to_string());
+        let mut from_anchor = sql_queries::build::at_or_after_condition(&scan_keys, anchor, 2);
+        if !include_anchor {
+            from_anchor = format!("{from_anchor} AND rowid <> ?{}", keys.len() + 1);
+        }
+        let first_match = |restriction: &str, params: Vec<&DataItem>| -> AppResult<_> {
+            let query = format!(
+                r#"SELECT {key_columns} FROM "{table_name}" WHERE ({condition}){restriction} {ordering} LIMIT 1;"#
+            );
+            let mut stmt = self.prepare(&query)?;
+            let found = stmt
+                .query_row(params_from_iter(params), |row| {
+                    (0..keys.len())
+                        .map(|i| Ok(DataItem::from(row.get_ref(i)?)))
+                        .collect::<rusqlite::Result<Vec<_>>>()
+                })
+                .optional()?;
+            Ok(found)
+        };
+        // the rows from the `from` row, or the top, up to the `to` row
+        let count_between = |from: Option<&[DataItem]>, to: &[DataItem]| -> AppResult<u32> {
+            let mut conditions = vec![];
+            let mut params: Vec<&DataItem> = vec![];
+            if let Some(from) = from {
+                conditions.push(sql_queries::build::at_or_after_condition(&keys, from, 1));
+                params.extend(from);
+            }
+            conditions.push(format!(
+                "NOT {}",
+                sql_queries::build::at_or_after_condition(&keys, to, params.len() + 1)
+            ));
+            params.extend(to);
+            let query = format!(
+                r#"SELECT COUNT(*) FROM "{table_name}" WHERE {};"#,
+                conditions.join(" AND ")
+            );
+            let count = self
+                .connection
+                .query_row(&query, params_from_iter(params), |row| row.get(0))?;
+            Ok(count)
+        };
+        let params = [&regex].into_iter().chain(anchor).collect();
+        if let Some(found) = first_match(&format!(" AND {from_anchor}"), params)? {
+            return Ok(Some(if forward {
+                row_number + count_between(Some(anchor), &found)?
+            } else {
+                row_number - count_between(Some(&found), anchor)?
+            }));
+        }
+        match first_match("", vec![&regex])? {
+            Some(found) => Ok(Some(count_between(None, &found)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Moves the cursor to the first row at or after where the search started that fuzzy matches the pattern.
+    /// If nothing matches the cursor goes back to where it was. The matches aren't counted until
+    /// the search is finished.
+    pub(crate) fn incremental_search(&mut self, pattern: &str) -> AppResult<()> {
+        let Some(origin) = self.search_origin.clone() else {
+            return Ok(());
+        };
+        if pattern.is_empty() {
+            self.slice.update(origin.selected as u32, origin.row_offset);
+            self.search = origin.search;
+            return Ok(());
+        }
+        let column = if self.search_options.all_columns {
+            None
+        } else {
+            Some(self.get_current_header()?)
+        };
+        let mut search = Search::new(SearchKind::Fuzzy, pattern, column, self.search_options)?;
+        let origin_row = origin.row_offset + origin.selected as u32;
+        let anchor = match origin.key {
+            Some(key) => Some(key),
+            None => self.key_at(origin_row)?,
+        };
+        let row_number = match anchor {
+            Some(anchor) => self.match_from(&search, &anchor, origin_row, true, true)?,
+            None => None,
+        };
+        search.current = row_number;
+        self.search = Some(search);
+        match row_number {
+            Some(row_number) => self.jump_to_row(row_number),
+            None => {
+                self.slice.update(origin.selected as u32, origin.row_offset);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn cancel_incremental_search(&mut self) {
+        if let Some(origin) = self.search_origin.take() {
+            self.slice.update(origin.selected as u32, origin.row_offset);
+            self.search = origin.search;
+        }
+    }
+
+    pub(crate) fn finish_incremental_search(&mut self, pattern: &str) -> AppResult<()> {
+        // the input might have come from the external editor, so we search one last time
+        self.incremental_search(pattern)?;
+        // the preview only found the first match, now that the search is confirmed we count them
+        if self.search_origin.take().is_some() && !pattern.is_empty() {
+            if let Some(mut search) = self.search.take() {
+                let count = self.count_matches(&search);
+                search.count = count.as_ref().ok().copied();
+                self.search = Some(search);
+                count?;
+            }
+        }
+        match &self.search {
+            Some(search) if search.current.is_some() => Ok(()),
+            Some(search) => Err(app_error_other!(search.status())),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn jump_to_row(&mut self, row_number: u32) -> AppResult<()> {
         let height = TUI::get_table_height()?;
         let row_idx = row_number % height;
         let row_offset = row_number - row_idx;
@@ -328,7 +1155,7 @@ impl Database {
     /// This is a regex capture without capture groups e.g. [g-k].*n.
     /// Get the first capture that matches the pattern, a letter between g and k, followed by any number of characters, followed by n.
     pub(crate) fn regex_capture_group_transform(
-        &self,
+        &mut self,
         pattern: &str,
         header: &str,
         transformation: &str,
@@ -345,11 +1172,10 @@ impl Database {
             transformation,
             &table_name,
         )?;
-        self.execute_batch(&queries)?;
-        Ok(())
+        self.execute_in_background(Command::RegexTransform, queries)
     }
     pub(crate) fn regex_no_capture_group_transform(
-        &self,
+        &mut self,
         pattern: &str,
         header: &str,
     ) -> AppResult<()> {
@@ -362,8 +1188,7 @@ impl Database {
             pattern,
             &table_name,
         )?;
-        self.execute_batch(&queries)?;
-        Ok(())
+        self.execute_in_background(Command::RegexTransform, queries)
     }
 
     pub(crate) fn copy(&self) -> AppResult<()> {
@@ -382,15 +1207,20 @@ impl Database {
         queries.push_str(&update_query);
         self.execute_batch(&queries)
     }
-    /// This is a regex capture without capture groups e.g. [g-k].*n.
-    /// Get the first capture that matches the pattern, a letter between g and k, followed by any number of characters, followed by n.
-
-    pub(crate) fn sql_query(&self, query: &str) -> AppResult<()> {
-        self.execute_batch(query)
+    pub(crate) fn sql_query(&mut self, query: &str) -> AppResult<()> {
+        self.execute_in_background(Command::SqlQuery, query.to_string())?;
+        // with a pool the query runs as a job, which marks the table once it has finished
+        if !self.is_busy() && !only_reads(&self.connection, query) {
+            self.modified.insert(self.get_current_table_name()?);
+        }
+        Ok(())
     }
 
+    /// The file name without its extensions, `data.csv.gz` becomes `data`.
     pub(crate) fn get_table_name(file: PathBuf) -> Option<String> {
-        file.file_stem().map(|el| el.to_string_lossy().into_owned())
+        compression::strip_extension(&file)
+            .file_stem()
+            .map(|el| el.to_string_lossy().into_owned())
     }
 
     pub fn get_headers(&self, table_name: &str) -> AppResult<Vec<String>> {
@@ -398,8 +1228,7 @@ impl Database {
         let mut stmt = self.connection.prepare(&query)?;
         let column_names: Vec<String> = stmt
             .query_map([], |row| row.get(1))?
-            .map(|result| result.expect("Failed to retrieve column name"))
-            .collect();
+            .collect::<rusqlite::Result<_>>()?;
         Ok(column_names)
     }
     pub fn count_headers(&self) -> AppResult<u16> {
@@ -480,41 +1309,46 @@ impl Database {
         self.slice.table_state.select(Some(i));
         Ok(())
     }
-    pub fn update_cell(&self, header: &str, id: i32, content: &str) -> AppResult<()> {
+    pub fn update_cell(&self, header: &str, id: i64, content: &str) -> AppResult<()> {
         let table_name = self.get_current_table_name()?;
-        let update_query = format!(r#"UPDATE "{table_name}" SET '{header}' = ? WHERE id = ?;"#);
+        let update_query = format!(r#"UPDATE "{table_name}" SET "{header}" = ? WHERE rowid = ?;"#);
         self.execute(&update_query, params![content, id])?;
         Ok(())
     }
 
     pub fn next_table(&mut self) -> AppResult<()> {
         let query = format!(
-            r#"SELECT rowid FROM sqlite_master WHERE type='table' AND rowid > '{}' AND name != 'table_of_tables' ORDER BY rowid ASC LIMIT 1;"#,
-            self.current_table_idx
+            "SELECT idx FROM {TABLES} WHERE idx > {} AND {} ORDER BY idx ASC LIMIT 1;",
+            self.current_table_idx,
+            not_metadata_table_condition()
         );
         self.current_table_idx = self.connection.query_row(&query, [], |row| row.get(0))?;
         self.slice.row_offset = 0;
         self.slice.table_state.select(Some(0));
+        self.sort_stack.clear();
         Ok(())
     }
     pub fn select_table(&mut self, table_name: &str) -> AppResult<()> {
-        let query = format!(
-            r#"SELECT rowid FROM sqlite_master WHERE type='table' AND name = '{table_name}';"#
-        );
-        self.current_table_idx = self.connection.query_row(&query, [], |row| row.get(0))?;
+        let query = format!("SELECT idx FROM {TABLES} WHERE name = ?;");
+        self.current_table_idx = self
+            .connection
+            .query_row(&query, [table_name], |row| row.get(0))?;
         self.slice.row_offset = 0;
         self.slice.table_state.select(Some(0));
+        self.sort_stack.clear();
         Ok(())
     }
 
     pub(crate) fn prev_table(&mut self) -> AppResult<()> {
         let query = format!(
-            r#"SELECT rowid FROM sqlite_master WHERE type='table' AND rowid < {} AND name != 'table_of_tables' ORDER BY rowid DESC LIMIT 1;"#,
-            self.current_table_idx
+            "SELECT idx FROM {TABLES} WHERE idx < {} AND {} ORDER BY idx DESC LIMIT 1;",
+            self.current_table_idx,
+            not_metadata_table_condition()
         );
         self.current_table_idx = self.connection.query_row(&query, [], |row| row.get(0))?;
         self.slice.row_offset = 0;
         self.slice.table_state.select(Some(0));
+        self.sort_stack.clear();
         Ok(())
     }
 
@@ -534,47 +1368,85 @@ impl Database {
 
     pub(crate) fn delete_column(&mut self) -> AppResult<Option<String>> {
         let table_name = self.get_current_table_name()?;
-        let order_column = &self.order_column;
         let column = self.get_current_header()?;
-        if Some(&column) == order_column.as_ref() {
-            self.order_column = None;
-        }
+        self.sort_stack.remove(&column);
         let queries = sql_queries::build::delete_column_query(&table_name, &column);
         self.execute_batch(&queries)?;
+        delete_collations(&self.connection, &table_name, Some(&column))?;
         self.header_idx = self.header_idx.saturating_sub(1);
         Ok(Some(format!("Deleted column {column} from {table_name}")))
     }
 
     pub(crate) fn rename_column(&mut self, new_column: &str) -> AppResult<()> {
         let table_name = self.get_current_table_name()?;
-        let order_column = &self.order_column;
         let column = self.get_current_header()?;
-        if Some(&column) == order_column.as_ref() {
-            self.order_column = Some(new_column.to_string());
-        }
+        sql_queries::validate_identifier(new_column)?;
+        self.sort_stack.rename(&column, new_column);
         let queries = sql_queries::build::rename_column_query(&table_name, &column, new_column);
-        self.execute_batch(&queries)
+        self.execute_batch(&queries)?;
+        rename_collation_column(&self.connection, &table_name, &column, new_column)
     }
 
     pub(crate) fn delete_table(&mut self) -> AppResult<()> {
         let table_name = self.get_current_table_name()?;
         let query = sql_queries::build::delete_table_query(&table_name);
         self.execute(&query, [])?;
+        delete_collations(&self.connection, &table_name, None)?;
+        delete_table_source(&self.connection, &table_name)?;
+        self.modified.remove(&table_name);
         log::info!("Deleted table {table_name}");
-        self.prev_table()?;
+        self.prev_table().or_else(|_| self.next_table())?;
         Ok(())
     }
 
-    pub(crate) fn rename_table(&self, new_table_name: &str) -> AppResult<()> {
+    /// The question for the confirmation dialog of a destructive command, it says what is lost.
+    pub(crate) fn confirmation_question(&mut self, command: &Command) -> AppResult<String> {
+        let table_name = self.get_current_table_name()?;
+        match command {
+            Command::DeleteTable => {
+                let rows = self.count_rows().unwrap_or(0);
+                Ok(format!(
+                    "Drop table {table_name} ({} rows)?",
+                    group_thousands(rows as u64)
+                ))
+            }
+            Command::DeleteColumn => {
+                let column = self.get_current_header()?;
+                let values: u64 = self.connection.query_row(
+                    &format!(r#"SELECT COUNT("{column}") FROM "{table_name}";"#),
+                    [],
+                    |row| row.get(0),
+                )?;
+                Ok(format!(
+                    "Drop column {column} from {table_name} ({} values)?",
+                    group_thousands(values)
+                ))
+            }
+            Command::DeleteRow => {
+                let id = self.get_current_id()?;
+                Ok(format!("Delete row {id} from {table_name}?"))
+            }
+            _ => Ok(format!("{command}?")),
+        }
+    }
+
+    pub(crate) fn rename_table(&mut self, new_table_name: &str) -> AppResult<()> {
         let old_table_name = &self.get_current_table_name()?;
+        sql_queries::validate_identifier(new_table_name)?;
         let query = sql_queries::build::rename_table_query(old_table_name, new_table_name);
         self.execute(&query, [])?;
+        rename_collation_table(&self.connection, old_table_name, new_table_name)?;
+        rename_table_source(&self.connection, old_table_name, new_table_name)?;
+        // the changes that were made under the old name still have to be saved
+        if self.modified.remove(old_table_name) {
+            self.modified.insert(new_table_name.to_string());
+        }
         Ok(())
     }
 
     // TODO if the column contains a float, 3.0, then ensure that ALL intermediary calculations are done with floats.
     // currently (3/2)*2.0 = 2.0, but it should be 3.0.
-    pub(crate) fn math_operation(&self, inputs: Vec<String>) -> AppResult<()> {
+    pub(crate) fn math_operation(&mut self, inputs: Vec<String>) -> AppResult<()> {
         let math_expr = inputs[0].clone();
         let new_math_expr_col = self.find_unused_header_name("math_expr")?;
         let query = sql_queries::build::math_expression_query(
@@ -582,15 +1454,53 @@ impl Database {
             &self.get_current_table_name()?,
             &math_expr,
         );
-        self.execute_batch(&query)
+        self.execute_in_background(Command::MathOperation, query)
+    }
+
+    /// A virtual table reads a CSV file in place, it can't be altered or updated.
+    pub(crate) fn is_virtual_table(&self) -> AppResult<bool> {
+        let is_virtual = self.connection.query_row(
+            &format!("SELECT sql LIKE 'CREATE VIRTUAL TABLE%' FROM {TABLES} WHERE idx = ?;"),
+            [self.current_table_idx],
+            |row| row.get(0),
+        )?;
+        Ok(is_virtual)
+    }
+
+    /// Copies the current virtual table into a real table with the same name.
+    pub(crate) fn materialize_table(&mut self) -> AppResult<()> {
+        let table_name = self.get_current_table_name()?;
+        let headers = self.get_headers(&table_name)?;
+        let query = sql_queries::build::materialize_query(&table_name, &headers);
+        self.execute_in_background(Command::Materialize, query)?;
+        if !self.is_busy() {
+            self.table_materialized(&table_name)?;
+        }
+        Ok(())
+    }
+
+    /// The materialized table has a new rowid in sqlite_master and a key column in front that is
+    /// left out of exports, the cursor stays on the same cell.
+    fn table_materialized(&mut self, table_name: &str) -> AppResult<()> {
+        let key_column: String = self.connection.query_row(
+            "SELECT name FROM pragma_table_info(?1) WHERE cid = 0",
+            [table_name],
+            |row| row.get(0),
+        )?;
+        set_key_column(&self.connection, table_name, &key_column)?;
+        let query = r#"SELECT rowid FROM sqlite_master WHERE type='table' AND name = ?;"#;
+        self.current_table_idx = self
+            .connection
+            .query_row(query, [table_name], |row| row.get(0))?;
+        self.header_idx += 1;
+        Ok(())
     }
 
     pub(crate) fn view_metadata_table(&mut self) -> Result<(), AppError> {
         let current_tbl_name = self.get_current_table_name()?;
         if current_tbl_name == "table_of_tables" {
-            let prev_tbl_res = self.prev_table();
-            let next_tbl_res = self.next_table();
-            prev_tbl_res.and(next_tbl_res)
+            // a temp table_of_tables comes before every table
+            self.prev_table().or_else(|_| self.next_table())
         } else {
             populate_table_of_tables(&self.connection)?;
             self.select_table("table_of_tables")
@@ -598,17 +1508,130 @@ impl Database {
     }
 }
 
+/// How the files given on the command line are opened.
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    /// Query CSV files in place through a virtual table instead of copying them into SQLite.
+    pub no_import: bool,
+    /// Title rows at the top of each spreadsheet, the headers are in the row after them.
+    pub skip_rows: usize,
+    /// How CSV files are read, the import preview sets them for the file it was shown.
+    pub csv: CsvOptions,
+}
+
+/// Where imported files are copied to, in memory while debugging and `db.sqlite` in release.
+fn scratch_database_uri() -> String {
+    if cfg!(debug_assertions) {
+        log::info!("Debug mode, opening in memory db.");
+        shared_memory_uri()
+    } else {
+        log::info!("Release mode, saving to file 'db.sqlite'.");
+        let _ = std::fs::remove_file("db.sqlite");
+        "db.sqlite".to_string()
+    }
+}
+
+/// The names of the columns `stmt` returns.
+fn column_names(stmt: &Statement) -> Vec<String> {
+    stmt.column_names().into_iter().map(String::from).collect()
+}
+
+/// E.g. `1,204` for 1204.
+fn group_thousands(n: u64) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+/// `data.csv.gz` is kept as `data.csv.gz.bak`, so the copy isn't picked up as a CSV file.
+pub(crate) fn backup_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".bak");
+    path.with_file_name(file_name)
+}
+
+/// Where the table of a file is imported from, in the format of its extension.
+fn file_source(path: &Path) -> TableSource {
+    let format = compression::strip_extension(path)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    TableSource {
+        path: path.to_path_buf(),
+        format,
+        sheet: None,
+        csv: CsvOptions::default(),
+        skip_rows: 0,
+    }
+}
+
+/// The tables of files that are imported to a table named after the file, CSV files are read with
+/// `csv`.
+fn file_sources(paths: &[PathBuf], csv: &CsvOptions) -> Vec<(String, TableSource)> {
+    paths
+        .iter()
+        .filter_map(|path| {
+            let source = TableSource {
+                csv: csv.clone(),
+                ..file_source(path)
+            };
+            Some((Database::get_table_name(path.clone())?, source))
+        })
+        .collect()
+}
+
 impl TryFrom<Vec<PathBuf>> for Database {
     type Error = AppError;
 
     fn try_from(paths: Vec<PathBuf>) -> Result<Self, AppError> {
+        Self::from_paths(paths, ImportOptions::default())
+    }
+}
+
+impl Database {
+    pub fn from_paths(paths: Vec<PathBuf>, options: ImportOptions) -> AppResult<Self> {
         if paths.is_empty() {
             return Err(AppError::Io(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 "No file paths provided",
             )));
         }
-        let database_result = if paths.len() == 1 {
+        let mut stdin_paths = vec![];
+        let mut stdin_dirs = vec![];
+        let paths = paths
+            .into_iter()
+            .map(|path| match path.to_str() {
+                Some("-") => {
+                    let (dir, path) = converter::stdin_to_file()?;
+                    stdin_paths.push(path.clone());
+                    stdin_dirs.push(dir);
+                    Ok(path)
+                }
+                _ => Ok(path),
+            })
+            .collect::<AppResult<Vec<_>>>()?;
+        let all_csv = paths
+            .iter()
+            .map(|p| compression::strip_extension(p))
+            .all(|p| p.extension().is_some_and(|el| el == "csv"));
+        let database_result = if options.no_import && all_csv {
+            let uri = scratch_database_uri();
+            let connection = Connection::open(&uri)?;
+            allow_reads_while_writing(&connection)?;
+            let database = converter::database_from_csv_in_place(&paths, connection, &options.csv)?;
+            Ok((
+                database,
+                uri,
+                None,
+                file_sources(&paths, &CsvOptions::default()),
+            ))
+        } else if paths.len() == 1 {
             let path = paths
                 .first()
                 .ok_or(AppError::Io(std::io::Error::new(
@@ -616,53 +1639,104 @@ impl TryFrom<Vec<PathBuf>> for Database {
                     "There is no first path",
                 )))?
                 .clone();
-            match path.extension().and_then(|s| s.to_str()) {
+            let extension = compression::strip_extension(&path)
+                .extension()
+                .map(|extension| extension.to_string_lossy().into_owned());
+            match extension.as_deref() {
                 Some("csv") => {
-                    let connection = if cfg!(debug_assertions) {
-                        log::info!("Debug mode, opening in memory db.");
-                        Connection::open_in_memory()?
-                    } else {
-                        log::info!("Release mode, saving to file 'db.sqlite'.");
-                        let _ = std::fs::remove_file("db.sqlite");
-                        Connection::open("db.sqlite")?
-                    };
-                    let database = converter::database_from_csv(path, connection)?;
-                    Ok(database)
+                    let uri = scratch_database_uri();
+                    let connection = Connection::open(&uri)?;
+                    allow_reads_while_writing(&connection)?;
+                    let (database, rest) =
+                        converter::database_from_csv(path, connection, &options.csv)?;
+                    Ok((database, uri, rest, file_sources(&paths, &options.csv)))
+                }
+                Some("xlsx" | "xlsm" | "xlsb" | "xls" | "ods") => {
+                    let uri = scratch_database_uri();
+                    let connection = Connection::open(&uri)?;
+                    allow_reads_while_writing(&connection)?;
+                    let tables =
+                        converter::insert_spreadsheet(&path, &connection, options.skip_rows)?;
+                    let database = Database::new(connection)?;
+                    let sources = tables
+                        .into_iter()
+                        .map(|(table_name, sheet)| {
+                            let source = TableSource {
+                                sheet: Some(sheet),
+                                skip_rows: options.skip_rows,
+                                ..file_source(&path)
+                            };
+                            (table_name, source)
+                        })
+                        .collect();
+                    Ok((database, uri, None, sources))
+                }
+                Some("parquet") => {
+                    let uri = scratch_database_uri();
+                    let connection = Connection::open(&uri)?;
+                    allow_reads_while_writing(&connection)?;
+                    let table_name = Database::get_table_name(path.clone())
+                        .ok_or(app_error_other!("The file has no name"))?;
+                    Parquet::new(&table_name).import_to_db(&path, &connection)?;
+                    let database = Database::new(connection)?;
+                    Ok((database, uri, None, vec![(table_name, file_source(&path))]))
                 }
                 Some("sqlite") | Some("sqlite3") => {
-                    let connection = Connection::open(path)?;
+                    let connection = Connection::open(&path)?;
                     let database = converter::database_from_sqlite(connection)?;
-                    Ok(database)
+                    Ok((database, path.to_string_lossy().into_owned(), None, vec![]))
                 }
-                _ => Err(AppError::Io(std::io::Error::new(
-                    std::io::ErrorKind::InvalidInput,
-                    "Invalid file extension",
-                ))),
+                extension => Err(AppError::unsupported_format(
+                    extension.unwrap_or_default(),
+                    OPEN_FORMATS,
+                )),
             }
-        } else if paths
-            .iter()
-            .map(|p| p.extension())
-            .all(|el| el.is_some_and(|el| el == "csv"))
-        {
+        } else if all_csv {
             let _ = std::fs::remove_file("db.sqlite");
             let connection = Connection::open("db.sqlite")?;
-            let database = converter::database_from_csv(
+            let (database, rest) = converter::database_from_csv(
                 paths
                     .first()
                     .ok_or(app_error_other!("There is no path"))?
                     .clone(),
                 connection,
+                &options.csv,
             )?;
+            if let Some(mut rest) = rest {
+                rest.insert_all(&database.connection)?;
+            }
             for path in paths.iter().skip(1) {
-                insert_csv_data_database(path.clone(), &database.connection)?;
+                insert_csv_data_database(path.clone(), &database.connection, &options.csv)?;
             }
-            Ok(database)
+            Ok((
+                database,
+                "db.sqlite".to_string(),
+                None,
+                file_sources(&paths, &options.csv),
+            ))
         } else {
             Err(AppError::Io(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
-                "Invalid file extension. One or several csv files or a single sqlite3 database can be provided.")))
+                "Invalid file extension. One or several csv files, or a single spreadsheet, parquet file or sqlite3 database can be provided.")))
+        };
+        let (mut database, uri, rest, sources) = database_result?;
+        database.stdin_dirs = stdin_dirs;
+        // before the import starts, it holds the write lock until it is done
+        for (table_name, source) in sources {
+            if !stdin_paths.contains(&source.path) {
+                set_table_source(&database.connection, &table_name, &source)?;
+            }
+        }
+        database.pool = match connection_pool(&uri) {
+            Ok(pool) => Some(pool),
+            Err(err) => {
+                log::info!("Long operations will block, no connection pool: {err}");
+                None
+            }
         };
-        let database = database_result?;
+        if let Some(rest) = rest {
+            database.start_import(rest)?;
+        }
         // populate_table_of_tables(&database.connection)?;
         Ok(database)
     }
@@ -670,10 +1744,14 @@ impl TryFrom<Vec<PathBuf>> for Database {
 
 #[cfg(test)]
 mod tests {
+    use std::fs::File;
+    use std::io::{Read, Write};
     use std::time::Instant;
 
     use controller::direction::Direction;
 
+    use crate::model::csv_options::Encoding;
+
     use super::*;
 
     fn setup_database() -> Database {
@@ -756,6 +1834,74 @@ mod tests {
         // assert_eq!(first, "hank");
     }
 
+    fn names_on_page(database: &mut Database, offset: u32) -> Vec<(String, String)> {
+        database.slice.has_changed();
+        let (_, rows) = database.get(10, offset, "data-long".to_string()).unwrap();
+        rows.iter()
+            .map(|row| (row[1].to_string(), row[2].to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn edit_sorted_and_paged_row_test() {
+        let p = vec![PathBuf::from("assets/data-long.csv")];
+        let mut database = Database::try_from(p).unwrap();
+        database.move_cursor(Direction::Right).unwrap();
+        database.sort().unwrap();
+        database.sort().unwrap();
+
+        // second page, fourth row
+        database.slice.update(3, 10);
+        let names = names_on_page(&mut database, 10);
+        let id = database.get_current_id().unwrap();
+        database.update_cell("lastname", id, "edited").unwrap();
+
+        let edited = names_on_page(&mut database, 10);
+        for (i, (before, after)) in names.iter().zip(edited.iter()).enumerate() {
+            assert_eq!(before.0, after.0);
+            if i == 3 {
+                assert_eq!(after.1, "edited");
+            } else {
+                assert_eq!(before.1, after.1);
+            }
+        }
+    }
+
+    #[test]
+    fn current_id_without_cached_slice_test() {
+        let p = vec![PathBuf::from("assets/data-long.csv")];
+        let mut database = Database::try_from(p).unwrap();
+        database.move_cursor(Direction::Right).unwrap();
+        database.sort().unwrap();
+        database.slice.update(5, 20);
+        let names = names_on_page(&mut database, 20);
+        let cached_id = database.get_current_id().unwrap();
+
+        database.slice.has_changed();
+        let queried_id = database.get_current_id().unwrap();
+        assert_eq!(cached_id, queried_id);
+        assert_eq!(
+            database.get_cell(queried_id, "first-name").unwrap(),
+            names[5].0
+        );
+    }
+
+    #[test]
+    fn delete_sorted_row_test() {
+        let mut database = setup_database();
+        database.move_cursor(Direction::Right).unwrap();
+        database.sort().unwrap();
+        database.sort().unwrap();
+        // richard, peter, karl, john, henrik, esther
+        database.slice.update(2, 0);
+        database.get(10, 0, "data".to_string()).unwrap();
+        database.delete_row().unwrap();
+        database.slice.has_changed();
+        let (_, rows) = database.get(10, 0, "data".to_string()).unwrap();
+        let names: Vec<String> = rows.iter().map(|row| row[1].to_string()).collect();
+        assert_eq!(names, vec!["richard", "peter", "john", "henrik", "esther"]);
+    }
+
     #[test]
     fn test_offset() {
         let mut database = Database::try_from(vec![PathBuf::from("assets/data.csv")]).unwrap();
@@ -801,9 +1947,479 @@ mod tests {
 
     #[test]
     fn count_rows_test() {
-        let database = setup_database();
+        let mut database = setup_database();
         let rows_len = database.count_rows().unwrap();
-        assert_eq!(rows_len, 6)
+        assert_eq!(rows_len, 6);
+
+        // the count is cached until the cache is invalidated
+        database
+            .execute(r#"DELETE FROM "data" WHERE rowid = 1;"#, [])
+            .unwrap();
+        assert_eq!(database.count_rows(), Some(6));
+        database.invalidate_cache();
+        assert_eq!(database.count_rows(), Some(5));
+    }
+
+    fn wait_for_job(database: &mut Database) -> AppResult<String> {
+        let started = Instant::now();
+        loop {
+            if let Some(result) = database.poll_job() {
+                return result;
+            }
+            assert!(started.elapsed().as_secs() < 10, "the job never finished");
+            std::thread::sleep(time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn math_operation_in_background_test() {
+        let mut database = setup_database();
+        database
+            .math_operation(vec!["age * 2".to_string()])
+            .unwrap();
+        assert!(database.is_busy());
+        database.mark_modified(&Command::MathOperation);
+        assert!(!database.is_modified());
+        assert!(wait_for_job(&mut database).unwrap().starts_with("finished"));
+        assert!(!database.is_busy());
+        assert!(database.modified.contains("data"));
+        database.mark_saved(&["other".to_string()]);
+        assert!(database.is_modified());
+        database.mark_saved(&["data".to_string()]);
+        assert!(!database.is_modified());
+        let (headers, rows) = database.get(1, 0, "data".to_string()).unwrap();
+        assert_eq!(headers.last().unwrap(), "math_expr");
+        assert_eq!(rows[0].last(), Some(&DataItem::Integer(40)));
+    }
+
+    #[test]
+    fn select_is_not_a_change_test() {
+        let mut database = setup_database();
+        database.sql_query("SELECT * FROM data;").unwrap();
+        assert!(wait_for_job(&mut database).unwrap().starts_with("finished"));
+        assert!(!database.is_modified());
+        database.sql_query("UPDATE data SET age = 1;").unwrap();
+        assert!(wait_for_job(&mut database).unwrap().starts_with("finished"));
+        assert!(database.modified.contains("data"));
+
+        // without a pool the query runs right away
+        let mut database = setup_three_table_db();
+        database.sql_query("SELECT * FROM t1;").unwrap();
+        assert!(!database.is_modified());
+        database.sql_query("INSERT INTO t1 VALUES (1);").unwrap();
+        assert!(database.is_modified());
+    }
+
+    #[test]
+    fn deleted_table_has_no_changes_test() {
+        let mut database = setup_three_table_db();
+        let table_name = database.get_current_table_name().unwrap();
+        database.modified.insert(table_name);
+        database.delete_table().unwrap();
+        database.mark_modified(&Command::DeleteTable);
+        assert!(!database.is_modified());
+        assert_eq!(database.get_current_table_name().unwrap(), "t2");
+    }
+
+    #[test]
+    fn cancel_job_rolls_back_test() {
+        let mut database = setup_database();
+        let endless = r#"CREATE TABLE "endless" AS
+            WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c) SELECT x FROM c;"#;
+        database.sql_query(endless).unwrap();
+        std::thread::sleep(time::Duration::from_millis(50));
+        assert!(database.poll_job().is_none());
+        database.cancel_job();
+        assert!(matches!(
+            wait_for_job(&mut database),
+            Err(AppError::UserCancelled)
+        ));
+        assert!(!database.is_modified());
+        let tables = database.get_table_names().unwrap();
+        assert!(!tables.contains(&"endless".to_string()));
+    }
+
+    #[test]
+    fn streaming_import_test() {
+        let uri = shared_memory_uri();
+        let connection = Connection::open(&uri).unwrap();
+        allow_reads_while_writing(&connection).unwrap();
+        let mut import = CsvImport::with_batch_size(
+            PathBuf::from("assets/data-long.csv"),
+            &connection,
+            10,
+            &CsvOptions::default(),
+        )
+        .unwrap();
+        assert!(import.insert_batch(&connection).unwrap());
+        let mut database = Database::new(connection).unwrap();
+        database.pool = Some(connection_pool(&uri).unwrap());
+        database.start_import(import).unwrap();
+
+        // the first batch is there right away, the rest arrives while we can still read
+        assert!(database.is_loading());
+        assert!(database.count_rows().unwrap() >= 10);
+        let (_, rows) = database.get(10, 0, "data-long".to_string()).unwrap();
+        assert_eq!(rows[0][1], DataItem::Text("henrik".to_string()));
+
+        let started = Instant::now();
+        let message = loop {
+            if let Some(result) = database.poll_import() {
+                break result.unwrap();
+            }
+            assert!(
+                started.elapsed().as_secs() < 10,
+                "the import never finished"
+            );
+            std::thread::sleep(time::Duration::from_millis(10));
+        };
+        assert!(message.starts_with("loaded 96 rows into data-long"));
+        assert!(!database.is_loading());
+        assert_eq!(database.count_rows(), Some(96));
+    }
+
+    #[test]
+    fn no_import_virtual_table_test() {
+        let options = ImportOptions {
+            no_import: true,
+            csv: CsvOptions {
+                delimiter: b';',
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(
+            Database::from_paths(vec![PathBuf::from("assets/data-long.csv")], options).is_err()
+        );
+        let options = ImportOptions {
+            no_import: true,
+            ..Default::default()
+        };
+        let mut database =
+            Database::from_paths(vec![PathBuf::from("assets/data-long.csv")], options).unwrap();
+        assert!(database.is_virtual_table().unwrap());
+        assert_eq!(database.count_rows(), Some(96));
+        let (headers, rows) = database.get(10, 0, "data-long".to_string()).unwrap();
+        assert_eq!(headers[0], "first-name");
+        assert_eq!(rows[0][0], DataItem::Text("henrik".to_string()));
+        let column = Some("lastname".to_string());
+        let search = Search::new(
+            SearchKind::Exact,
+            "zenkert",
+            column,
+            SearchOptions::default(),
+        );
+        assert_eq!(matching_rows(&database, &search.unwrap())[0], 0);
+
+        database.materialize_table().unwrap();
+        assert!(wait_for_job(&mut database).unwrap().starts_with("finished"));
+        assert!(!database.is_virtual_table().unwrap());
+        assert_eq!(database.get_current_header().unwrap(), "first-name");
+        assert_eq!(database.count_rows(), Some(96));
+        let (headers, rows) = database.get(10, 0, "data-long".to_string()).unwrap();
+        assert_eq!(headers[0], "id");
+        assert_eq!(rows[0][1], DataItem::Text("henrik".to_string()));
+        database.update_cell("first-name", 1, "anna").unwrap();
+        assert_eq!(database.get_cell(1, "first-name").unwrap(), "anna");
+    }
+
+    #[test]
+    fn write_current_table_test() {
+        let mut database = setup_database();
+        database.move_cursor(Direction::Right).unwrap();
+        database.sort().unwrap();
+        let mut csv = vec![];
+        database.write_current_table(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("firstname,lastname,age"));
+        let firstnames: Vec<&str> = lines.map(|line| line.split(',').next().unwrap()).collect();
+        let mut sorted = firstnames.clone();
+        sorted.sort();
+        assert_eq!(firstnames, sorted);
+    }
+
+    #[test]
+    fn import_compressed_csv_test() {
+        let path = std::env::temp_dir().join("dataman_compressed.csv.gz");
+        let csv = std::fs::read("assets/data-long.csv").unwrap();
+        let mut writer = compression::create(&path).unwrap();
+        writer.write_all(&csv).unwrap();
+        writer.finish().unwrap();
+        let mut database = Database::try_from(vec![path.clone()]).unwrap();
+        database.wait_for_import().unwrap();
+        assert_eq!(
+            database.get_current_table_name().unwrap(),
+            "dataman_compressed"
+        );
+        assert_eq!(database.count_rows(), Some(96));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn invalid_identifier_test() {
+        let mut database = setup_database();
+        for name in ["", "  ", r#"my "data""#, "sqlite_data"] {
+            assert!(matches!(
+                database.rename_table(name),
+                Err(AppError::InvalidIdentifier { .. })
+            ));
+        }
+        database.header_idx = 1;
+        let err = database.rename_column(r#"first"name"#).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"Invalid name "first"name": a name can't contain a double quote"#
+        );
+        assert!(matches!(
+            database.execute("SELECT * FROM missing;", []),
+            Err(AppError::QueryFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn confirmation_question_test() {
+        let mut database = setup_database();
+        assert_eq!(
+            database
+                .confirmation_question(&Command::DeleteTable)
+                .unwrap(),
+            "Drop table data (6 rows)?"
+        );
+        database.header_idx = 1;
+        database
+            .execute(r#"UPDATE "data" SET firstname = NULL WHERE id = 1;"#, [])
+            .unwrap();
+        assert_eq!(
+            database
+                .confirmation_question(&Command::DeleteColumn)
+                .unwrap(),
+            "Drop column firstname from data (5 values)?"
+        );
+        database.scroll_to(2);
+        assert_eq!(
+            database.confirmation_question(&Command::DeleteRow).unwrap(),
+            "Delete row 3 from data?"
+        );
+        assert_eq!(group_thousands(0), "0");
+        assert_eq!(group_thousands(999), "999");
+        assert_eq!(group_thousands(1204), "1,204");
+        assert_eq!(group_thousands(1234567), "1,234,567");
+    }
+
+    #[test]
+    fn write_back_test() {
+        use calamine::Reader;
+
+        let dir = std::env::temp_dir().join("dataman_write_back_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let csv = dir.join("people.csv.gz");
+        let mut writer = compression::create(&csv).unwrap();
+        writer.write_all(b"name,age\nanna,31\nbo,7\n").unwrap();
+        writer.finish().unwrap();
+        let database = Database::try_from(vec![csv.clone()]).unwrap();
+        database.update_cell("name", 2, "bob").unwrap();
+        assert_eq!(
+            database.write_back().unwrap(),
+            (csv.clone(), vec!["people".to_string()])
+        );
+        let read = |path: &Path| {
+            let mut content = String::new();
+            compression::decompress(
+                File::open(path).unwrap(),
+                Compression::detect(path).unwrap(),
+            )
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+            content
+        };
+        assert_eq!(read(&csv), "name,age\nanna,31\nbob,7\n");
+        assert_eq!(read(&backup_path(&csv)), "name,age\nanna,31\nbo,7\n");
+
+        let workbook = dir.join("report.xlsx");
+        std::fs::copy("assets/report.xlsx", &workbook).unwrap();
+        let options = ImportOptions {
+            skip_rows: 1,
+            ..Default::default()
+        };
+        let mut database = Database::from_paths(vec![workbook.clone()], options).unwrap();
+        database.select_table("report_people").unwrap();
+        database.rename_table("people").unwrap();
+        database.write_back().unwrap();
+        // the title row above the headers is still there, so it opens the same way again
+        let options = ImportOptions {
+            skip_rows: 1,
+            ..Default::default()
+        };
+        let mut database = Database::from_paths(vec![workbook.clone()], options).unwrap();
+        let (headers, _) = database.get(10, 0, "report_people".to_string()).unwrap();
+        assert_eq!(headers, vec!["id", "name", "age"]);
+        let workbook: calamine::Xlsx<_> = calamine::open_workbook(&workbook).unwrap();
+        assert_eq!(workbook.sheet_names(), vec!["sales", "people"]);
+        let original: calamine::Xlsx<_> = calamine::open_workbook("assets/report.xlsx").unwrap();
+        let title = |mut workbook: calamine::Xlsx<_>| {
+            workbook
+                .worksheet_range("people")
+                .unwrap()
+                .rows()
+                .next()
+                .unwrap()[0]
+                .to_string()
+        };
+        assert_eq!(title(workbook), title(original));
+
+        // the file is written with the delimiter, encoding and header row it was read with
+        let csv = dir.join("latin.csv");
+        std::fs::write(&csv, b"caf\xe9;1\nth\xe9;2\n").unwrap();
+        let options = ImportOptions {
+            csv: CsvOptions {
+                delimiter: b';',
+                has_headers: false,
+                encoding: Encoding::Windows1252,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let database = Database::from_paths(vec![csv.clone()], options).unwrap();
+        database.update_cell("column_2", 2, "3").unwrap();
+        database.write_back().unwrap();
+        assert_eq!(std::fs::read(&csv).unwrap(), b"caf\xe9;1\nth\xe9;3\n");
+        std::fs::remove_dir_all(dir).unwrap();
+
+        let database = setup_three_table_db();
+        assert!(database.write_back().is_err());
+    }
+
+    #[test]
+    fn import_spreadsheet_test() {
+        let options = ImportOptions {
+            skip_rows: 1,
+            ..Default::default()
+        };
+        let mut database =
+            Database::from_paths(vec![PathBuf::from("assets/report.xlsx")], options).unwrap();
+        let tables = database.get_table_names().unwrap();
+        assert!(tables.contains(&"report_sales".to_string()));
+        assert!(tables.contains(&"report_people".to_string()));
+        assert_eq!(database.get_current_table_name().unwrap(), "report_sales");
+
+        let (headers, rows) = database.get(10, 0, "report_sales".to_string()).unwrap();
+        assert_eq!(headers, vec!["id", "product", "units", "price", "sold on"]);
+        assert_eq!(
+            rows[0][1..],
+            [
+                DataItem::Text("apple".to_string()),
+                DataItem::Integer(12),
+                DataItem::Float(0.5),
+                DataItem::Text("2024-01-01".to_string()),
+            ]
+        );
+        // a column with numbers and text keeps the type of each cell, a REAL column has floats
+        assert_eq!(rows[1][2], DataItem::Integer(7));
+        assert_eq!(rows[2][2], DataItem::Text("n/a".to_string()));
+        assert_eq!(rows[2][3], DataItem::Float(2.0));
+        assert_eq!(rows[2][4], DataItem::Null);
+
+        database.select_table("report_people").unwrap();
+        database.slice.has_changed();
+        let (headers, rows) = database.get(10, 0, "report_people".to_string()).unwrap();
+        assert_eq!(headers, vec!["id", "name", "age"]);
+        assert_eq!(rows[0][2], DataItem::Integer(31));
+    }
+
+    #[test]
+    fn save_to_xlsx_test() {
+        use calamine::{Data, Reader};
+
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE people (id INTEGER PRIMARY KEY, name TEXT, age INTEGER, score REAL);
+                INSERT INTO people (name, age, score) VALUES ('anna', 31, 2.5), ('bo', NULL, 1);
+                CREATE TABLE empty (id INTEGER PRIMARY KEY, note TEXT);",
+            )
+            .unwrap();
+        set_key_column(&connection, "people", "id").unwrap();
+        set_key_column(&connection, "empty", "id").unwrap();
+        let database = Database::new(connection).unwrap();
+        let path = std::env::temp_dir().join("dataman_save_test.xlsx");
+        database.save_to_xlsx(&path, true).unwrap();
+        let workbook = calamine::open_workbook_auto(&path).unwrap();
+        assert_eq!(workbook.sheet_names(), vec!["people", "empty"]);
+
+        database.save_to_xlsx(&path, false).unwrap();
+        let mut workbook = calamine::open_workbook_auto(&path).unwrap();
+        assert_eq!(workbook.sheet_names(), vec!["people"]);
+        let range = workbook.worksheet_range("people").unwrap();
+        let rows: Vec<&[Data]> = range.rows().collect();
+        assert_eq!(
+            rows[0],
+            [
+                Data::String("name".to_string()),
+                Data::String("age".to_string()),
+                Data::String("score".to_string())
+            ]
+        );
+        assert_eq!(
+            rows[1],
+            [
+                Data::String("anna".to_string()),
+                Data::Float(31.0),
+                Data::Float(2.5)
+            ]
+        );
+        assert_eq!(rows[2][1], Data::Empty);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    fn page_ids(database: &mut Database, offset: u32) -> Vec<i64> {
+        database.slice.has_changed();
+        database.get(7, offset, "data-long".to_string()).unwrap();
+        database.slice.row_ids.clone()
+    }
+
+    fn offset_page_ids(database: &mut Database, offset: u32) -> Vec<i64> {
+        database.slice.page_keys.clear();
+        page_ids(database, offset)
+    }
+
+    #[test]
+    fn keyset_pages_match_offset_pages_test() {
+        let p = vec![PathBuf::from("assets/data-long.csv")];
+        let mut database = Database::try_from(p).unwrap();
+        database
+            .execute(
+                r#"UPDATE "data-long" SET "age" = NULL WHERE rowid % 5 = 0;"#,
+                [],
+            )
+            .unwrap();
+        // age has lots of ties and NULLs, which sort first ascending and last descending
+        database.header_idx = 2;
+        database.sort().unwrap();
+        database.header_idx = 1;
+        database.add_sort_column().unwrap();
+        database.cycle_collation().unwrap();
+        for descending in [false, true] {
+            if descending {
+                database.header_idx = 2;
+                database.add_sort_column().unwrap();
+            }
+            let expected: Vec<Vec<i64>> = (0..14)
+                .map(|page| offset_page_ids(&mut database, page * 7))
+                .collect();
+            assert_eq!(expected.concat().len(), 96);
+
+            database.slice.page_keys.clear();
+            for page in (0..14).chain((0..14).rev()) {
+                assert_eq!(page_ids(&mut database, page * 7), expected[page as usize]);
+            }
+            // jumps further than one page step from the closest end of the last page
+            for offset in [50, 45, 90, 12, 0, 30] {
+                let ids = page_ids(&mut database, offset);
+                assert_eq!(ids, offset_page_ids(&mut database, offset));
+            }
+        }
     }
 
     #[test]
@@ -816,6 +2432,194 @@ mod tests {
             .unwrap();
         assert_eq!(result, "henrik");
     }
+    /// The rows `search` finds going forward from the top, until it wraps around.
+    fn matching_rows(database: &Database, search: &Search) -> Vec<u32> {
+        let mut rows = vec![];
+        let anchor = database.key_at(0).unwrap().unwrap();
+        let mut found = database.match_from(search, &anchor, 0, true, true).unwrap();
+        while let Some(row) = found.filter(|row| rows.last() < Some(row)) {
+            rows.push(row);
+            let anchor = database.key_at(row).unwrap().unwrap();
+            found = database
+                .match_from(search, &anchor, row, true, false)
+                .unwrap();
+        }
+        rows
+    }
+
+    #[test]
+    fn search_matches_test() {
+        let mut database = setup_database();
+        database.move_cursor(Direction::Right).unwrap();
+        database.move_cursor(Direction::Right).unwrap();
+        let column = Some(database.get_current_header().unwrap());
+        let options = SearchOptions::default();
+        let search = Search::new(SearchKind::Exact, "zenkert", column.clone(), options).unwrap();
+        assert_eq!(matching_rows(&database, &search), vec![0, 2]);
+
+        let search = Search::new(SearchKind::Regex, "^[jk]", column, options).unwrap();
+        assert_eq!(matching_rows(&database, &search), vec![3]);
+    }
+
+    fn first_names(database: &mut Database) -> Vec<String> {
+        database.slice.has_changed();
+        let (_, rows) = database.get(10, 0, "data".to_string()).unwrap();
+        rows.iter().map(|row| row[1].to_string()).collect()
+    }
+
+    #[test]
+    fn multi_column_sort_test() {
+        let mut database = setup_database();
+        database.move_cursor(Direction::Right).unwrap();
+        database.move_cursor(Direction::Right).unwrap();
+        // lastname ascending, then age descending
+        database.sort().unwrap();
+        database.move_cursor(Direction::Right).unwrap();
+        database.add_sort_column().unwrap();
+        database.add_sort_column().unwrap();
+        assert_eq!(
+            first_names(&mut database),
+            vec!["karl", "richard", "john", "peter", "henrik", "esther"]
+        );
+
+        // lastname descending, age is still descending
+        database.move_cursor(Direction::Left).unwrap();
+        database.add_sort_column().unwrap();
+        assert_eq!(
+            first_names(&mut database),
+            vec!["henrik", "esther", "peter", "john", "richard", "karl"]
+        );
+
+        database.clear_sort();
+        assert_eq!(
+            first_names(&mut database),
+            vec!["henrik", "john", "esther", "karl", "peter", "richard"]
+        );
+    }
+
+    #[test]
+    fn sort_with_collation_test() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT);
+                INSERT INTO t (name) VALUES ('item10'), ('Item2'), ('item1'), ('apple');",
+            )
+            .unwrap();
+        let mut database = Database::new(connection).unwrap();
+        database.move_cursor(Direction::Right).unwrap();
+        let names = |database: &mut Database| -> Vec<String> {
+            database.slice.has_changed();
+            let (_, rows) = database.get(10, 0, "t".to_string()).unwrap();
+            rows.iter().map(|row| row[1].to_string()).collect()
+        };
+
+        database.sort().unwrap();
+        assert_eq!(
+            names(&mut database),
+            vec!["Item2", "apple", "item1", "item10"]
+        );
+
+        assert_eq!(database.cycle_collation().unwrap(), Collation::Natural);
+        assert_eq!(
+            names(&mut database),
+            vec!["apple", "item1", "Item2", "item10"]
+        );
+
+        assert_eq!(database.cycle_collation().unwrap(), Collation::NoCase);
+        assert_eq!(
+            names(&mut database),
+            vec!["apple", "item1", "item10", "Item2"]
+        );
+    }
+
+    #[test]
+    fn match_from_test() {
+        let mut database = setup_database();
+        // id descending: richard, peter, karl, esther, john, henrik
+        database.sort().unwrap();
+        database.sort().unwrap();
+        database.move_cursor(Direction::Right).unwrap();
+        database.get(10, 0, "data".to_string()).unwrap();
+        let column = Some(database.get_current_header().unwrap());
+        let options = SearchOptions::default();
+        let search = Search::new(SearchKind::Fuzzy, "hn", column.clone(), options).unwrap();
+
+        let peter = database.row_key(1).unwrap();
+        assert_eq!(
+            database.match_from(&search, &peter, 1, true, true).unwrap(),
+            Some(4)
+        );
+        // the row the search starts on counts
+        let henrik = database.row_key(5).unwrap();
+        assert_eq!(
+            database
+                .match_from(&search, &henrik, 5, true, true)
+                .unwrap(),
+            Some(5)
+        );
+        // next and previous leave the row they start on out, and wrap around
+        assert_eq!(
+            database
+                .match_from(&search, &henrik, 5, true, false)
+                .unwrap(),
+            Some(4)
+        );
+        assert_eq!(
+            database
+                .match_from(&search, &henrik, 5, false, false)
+                .unwrap(),
+            Some(4)
+        );
+        assert_eq!(
+            database
+                .match_from(&search, &peter, 1, false, false)
+                .unwrap(),
+            Some(5)
+        );
+        // nothing after henrik matches, so it wraps around to peter
+        let search = Search::new(SearchKind::Fuzzy, "pe", column, options).unwrap();
+        assert_eq!(
+            database
+                .match_from(&search, &henrik, 5, true, true)
+                .unwrap(),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn search_matches_fuzzy_test() {
+        let mut database = setup_database();
+        database.move_cursor(Direction::Right).unwrap();
+        let column = Some(database.get_current_header().unwrap());
+        let options = SearchOptions::default();
+        let search = Search::new(SearchKind::Fuzzy, "HR", column, options).unwrap();
+        // henrik, esther and richard
+        assert_eq!(matching_rows(&database, &search), vec![0, 2, 5]);
+    }
+
+    #[test]
+    fn search_matches_all_columns_ignore_case_test() {
+        let mut database = setup_database();
+        let options = SearchOptions {
+            ignore_case: true,
+            all_columns: true,
+        };
+        let search = Search::new(SearchKind::Regex, "^J", None, options).unwrap();
+        // john in firstname and johansson in lastname
+        assert_eq!(matching_rows(&database, &search), vec![1, 3]);
+
+        // integers are matched as they are displayed
+        let search = Search::new(SearchKind::Exact, "35", None, options).unwrap();
+        assert_eq!(matching_rows(&database, &search), vec![1]);
+
+        // sorting on id twice toggles to descending order
+        database.sort().unwrap();
+        database.sort().unwrap();
+        let search = Search::new(SearchKind::Regex, "^J", None, options).unwrap();
+        assert_eq!(matching_rows(&database, &search), vec![2, 4]);
+    }
+
     #[test]
     fn select_table_test() {
         let mut database = setup_three_table_db();
@@ -831,6 +2635,38 @@ mod tests {
         let database = setup_three_table_db();
     }
 
+    #[test]
+    fn opening_a_database_leaves_its_schema_alone_test() {
+        let path = std::env::temp_dir().join(format!("schema-{}.sqlite", std::process::id()));
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch("CREATE TABLE people (name TEXT); INSERT INTO people VALUES ('anna');")
+            .unwrap();
+        let schema = |path: &Path| -> Vec<String> {
+            Connection::open(path)
+                .unwrap()
+                .prepare("SELECT name FROM sqlite_master ORDER BY name;")
+                .unwrap()
+                .query_map([], |row| row.get(0))
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap()
+        };
+        let mut database = Database::try_from(vec![path.clone()]).unwrap();
+        database.view_metadata_table().unwrap();
+        assert_eq!(
+            database.get_current_table_name().unwrap(),
+            "table_of_tables"
+        );
+        assert!(database.count_rows().unwrap() > 0);
+        database.view_metadata_table().unwrap();
+        assert_eq!(database.get_current_table_name().unwrap(), "people");
+        assert_eq!(schema(&path), vec!["people"]);
+        drop(database);
+        assert_eq!(schema(&path), vec!["people"]);
+        std::fs::remove_file(path).unwrap();
+    }
+
     #[test]
     fn copy_column_long_test() {
         let p = vec![PathBuf::from("assets/data-long.csv")];
@@ -2,8 +2,8 @@ use ratatui::widgets::Cell;
 use std::collections::VecDeque;
 use std::fmt::Display;
 
-use rusqlite::types::ValueRef;
-use rusqlite::Row;
+use rusqlite::types::{ToSqlOutput, ValueRef};
+use rusqlite::{Row, ToSql};
 use serde::Serialize;
 
 pub type DataTable = (Vec<String>, Vec<Vec<DataItem>>);
@@ -21,6 +21,8 @@ pub enum DataItem {
     Text(String),
     Integer(i64),
     Float(f64),
+    /// Shown as `<blob n bytes>`, the bytes are kept so it can be written and compared as a blob.
+    Blob(Vec<u8>),
     Null,
 }
 impl DataItem {
@@ -29,6 +31,7 @@ impl DataItem {
             DataItem::Text(s) => s.len(),
             DataItem::Integer(x) => x.to_string().len(),
             DataItem::Float(x) => x.to_string().len(),
+            DataItem::Blob(_) => self.to_string().len(),
             DataItem::Null => 4,
         }
     }
@@ -45,6 +48,7 @@ impl<'a> From<DataItem> for Cell<'a> {
             DataItem::Text(text) => Cell::from(text.clone()),
             DataItem::Integer(int) => Cell::from(int.to_string()),
             DataItem::Float(float) => Cell::from(float.to_string()),
+            DataItem::Blob(_) => Cell::from(value.to_string()),
             DataItem::Null => Cell::from("NULL"),
         }
     }
@@ -56,6 +60,7 @@ impl From<DataItem> for String {
             DataItem::Text(text) => text,
             DataItem::Integer(num) => num.to_string(),
             DataItem::Float(num) => num.to_string(),
+            DataItem::Blob(bytes) => format!("<blob {} bytes>", bytes.len()),
             DataItem::Null => String::from(""),
         }
     }
@@ -68,7 +73,19 @@ impl<'a> From<ValueRef<'a>> for DataItem {
             ValueRef::Integer(n) => DataItem::Integer(n),
             ValueRef::Real(float) => DataItem::Float(float),
             ValueRef::Text(s) => DataItem::Text(String::from_utf8_lossy(s).to_string()),
-            ValueRef::Blob(_) => unimplemented!("blob"),
+            ValueRef::Blob(bytes) => DataItem::Blob(bytes.to_vec()),
         }
     }
 }
+
+impl ToSql for DataItem {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            DataItem::Text(text) => ToSqlOutput::from(text.as_str()),
+            DataItem::Integer(num) => ToSqlOutput::from(*num),
+            DataItem::Float(num) => ToSqlOutput::from(*num),
+            DataItem::Blob(bytes) => ToSqlOutput::from(bytes.as_slice()),
+            DataItem::Null => ToSqlOutput::from(rusqlite::types::Null),
+        })
+    }
+}
@@ -7,9 +7,46 @@ use ratatui::widgets::TableState;
 pub(crate) struct DatabaseSlice {
     pub(super) headers: Vec<String>,
     pub(super) data_rows: Vec<Vec<DataItem>>,
+    /// The rowid of each row in `data_rows`, so we can find the row under the cursor no matter the ordering.
+    pub(super) row_ids: Vec<i64>,
     pub(crate) table_state: TableState,
     pub(crate) row_offset: u32,
     pub(crate) is_unchanged: bool,
+    pub(super) page_keys: PageKeys,
+}
+
+/// The ordering values of the rows on the last fetched page, so the next page can be fetched with
+/// `WHERE (keys) > (last row)` instead of an OFFSET that has to step over every row before it.
+#[derive(Debug, Default)]
+pub(crate) struct PageKeys {
+    /// The table and ORDER BY clause the keys were read with, they are useless for any other.
+    pub(super) ordering: String,
+    /// The row number of the first row on the page.
+    pub(super) offset: u32,
+    /// One value per ordering column, ending with the rowid, for each row on the page.
+    pub(super) keys: Vec<Vec<DataItem>>,
+}
+
+impl PageKeys {
+    pub(super) fn row(&self, ordering: &str, row_number: u32) -> Option<&Vec<DataItem>> {
+        if self.ordering != ordering {
+            return None;
+        }
+        self.keys.get(row_number.checked_sub(self.offset)? as usize)
+    }
+
+    /// The row number and keys of the last row on the page.
+    pub(super) fn last(&self, ordering: &str) -> Option<(u32, &Vec<DataItem>)> {
+        if self.ordering != ordering {
+            return None;
+        }
+        let last = self.keys.last()?;
+        Some((self.offset + self.keys.len() as u32 - 1, last))
+    }
+
+    pub(super) fn clear(&mut self) {
+        *self = Self::default();
+    }
 }
 
 impl DatabaseSlice {
@@ -23,9 +60,11 @@ impl DatabaseSlice {
         Self {
             headers,
             data_rows,
+            row_ids: vec![],
             table_state,
             row_offset,
             is_unchanged: false,
+            page_keys: PageKeys::default(),
         }
     }
 
@@ -45,21 +84,11 @@ impl DatabaseSlice {
             if max_widths.iter().sum::<u16>() <= 92 {
                 break;
             }
-            let m: &mut u16 = max_widths
-                .iter_mut()
-                .fold(None, |acc, x| {
-                    if let Some(acc) = acc {
-                        if x > acc {
-                            Some(x)
-                        } else {
-                            Some(acc)
-                        }
-                    } else {
-                        Some(x)
-                    }
-                })
-                .unwrap();
-            *m -= 1;
+            // the first of the widest columns gives up a character
+            let Some(widest) = max_widths.iter_mut().rev().max() else {
+                break;
+            };
+            *widest -= 1;
         }
         max_widths
     }
@@ -69,6 +98,15 @@ impl DatabaseSlice {
         self.row_offset = row_offset;
         self.is_unchanged = false;
     }
+    /// The rowid of the selected row, if the slice is up to date.
+    pub(crate) fn selected_row_id(&self) -> Option<i64> {
+        if !self.is_unchanged {
+            return None;
+        }
+        let selected = self.table_state.selected()?;
+        self.row_ids.get(selected).copied()
+    }
+
     pub(crate) fn is_unchanged(&self) -> bool {
         self.is_unchanged
     }
@@ -0,0 +1,224 @@
+use std::io::Write;
+use std::path::Path;
+
+use rusqlite::Connection;
+
+use crate::error::AppResult;
+
+use super::compression;
+use super::convert::Exportable;
+use super::datarow::DataItem;
+use super::metadata::export_query;
+
+/// Text formats a table can be saved as, chosen by the extension of the save path.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextFormat {
+    /// An array with an object for each row.
+    Json,
+    /// An object for each row on its own line.
+    Ndjson,
+    /// A GitHub flavoured Markdown table.
+    Markdown,
+    Html,
+}
+
+impl TextFormat {
+    /// E.g. `Json` for `out.json` and `out.json.gz`.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match compression::strip_extension(path).extension()?.to_str()? {
+            "json" => Some(Self::Json),
+            "ndjson" | "jsonl" => Some(Self::Ndjson),
+            "md" | "markdown" => Some(Self::Markdown),
+            "html" | "htm" => Some(Self::Html),
+            _ => None,
+        }
+    }
+}
+
+/// A table that is saved in one of the text formats. Integers and floats stay numbers in JSON
+/// and NULL becomes `null`, in Markdown and HTML NULL is an empty cell.
+#[derive(Debug, Clone)]
+pub struct TextExport {
+    pub table_name: String,
+    pub format: TextFormat,
+}
+
+impl TextExport {
+    pub fn new(table_name: &str, format: TextFormat) -> Self {
+        Self {
+            table_name: table_name.to_string(),
+            format,
+        }
+    }
+
+    /// Writes the rows of the table, without the `id` column that we add on import.
+    pub fn write<W: Write>(&self, connection: &Connection, mut writer: W) -> AppResult<()> {
+        let mut stmt = connection.prepare(&export_query(connection, &self.table_name, "")?)?;
+        let headers: Vec<String> = stmt.column_names().iter().map(|s| s.to_string()).collect();
+        let headers: Vec<&str> = headers.iter().map(String::as_str).collect();
+        match self.format {
+            TextFormat::Json => writer.write_all(b"[")?,
+            TextFormat::Ndjson => {}
+            TextFormat::Markdown => {
+                let cells: Vec<String> = headers.iter().map(|h| markdown_cell(h)).collect();
+                writeln!(writer, "| {} |", cells.join(" | "))?;
+                writeln!(writer, "|{}", " --- |".repeat(headers.len()))?;
+            }
+            TextFormat::Html => {
+                writeln!(writer, "<table>\n  <thead>\n    <tr>")?;
+                for header in &headers {
+                    writeln!(writer, "      <th>{}</th>", html_escape(header))?;
+                }
+                writeln!(writer, "    </tr>\n  </thead>\n  <tbody>")?;
+            }
+        }
+        let mut rows = stmt.query([])?;
+        let mut first = true;
+        while let Some(row) = rows.next()? {
+            let items = (0..headers.len())
+                .map(|i| row.get_ref(i).map(DataItem::from))
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            match self.format {
+                TextFormat::Json => {
+                    let separator = if first { "\n  " } else { ",\n  " };
+                    write!(writer, "{separator}{}", json_object(&headers, &items)?)?;
+                }
+                TextFormat::Ndjson => writeln!(writer, "{}", json_object(&headers, &items)?)?,
+                TextFormat::Markdown => {
+                    let cells: Vec<String> = items
+                        .into_iter()
+                        .map(|item| markdown_cell(&String::from(item)))
+                        .collect();
+                    writeln!(writer, "| {} |", cells.join(" | "))?;
+                }
+                TextFormat::Html => {
+                    writeln!(writer, "    <tr>")?;
+                    for item in items {
+                        writeln!(
+                            writer,
+                            "      <td>{}</td>",
+                            html_escape(&String::from(item))
+                        )?;
+                    }
+                    writeln!(writer, "    </tr>")?;
+                }
+            }
+            first = false;
+        }
+        match self.format {
+            TextFormat::Json if first => writeln!(writer, "]")?,
+            TextFormat::Json => writeln!(writer, "\n]")?,
+            TextFormat::Ndjson | TextFormat::Markdown => {}
+            TextFormat::Html => writeln!(writer, "  </tbody>\n</table>")?,
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Exportable for TextExport {
+    /// Writes the file, compressed if its name ends in `.gz`, `.zst` or `.bz2`.
+    fn export_to_db(&self, path: &Path, connection: &Connection) -> AppResult<()> {
+        let mut writer = compression::create(path)?;
+        self.write(connection, &mut writer)?;
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+/// The keys are written in the order of the columns, a `serde_json::Map` would sort them.
+fn json_object(headers: &[&str], items: &[DataItem]) -> AppResult<String> {
+    let fields = headers
+        .iter()
+        .zip(items)
+        .map(|(header, item)| {
+            let value = match item {
+                DataItem::Text(text) => serde_json::Value::from(text.as_str()),
+                DataItem::Integer(n) => serde_json::Value::from(*n),
+                // NaN and infinity are not JSON, they become null
+                DataItem::Float(x) => serde_json::Value::from(*x),
+                DataItem::Blob(_) => serde_json::Value::from(item.to_string()),
+                DataItem::Null => serde_json::Value::Null,
+            };
+            Ok(format!(
+                "{}: {}",
+                serde_json::to_string(header)?,
+                serde_json::to_string(&value)?
+            ))
+        })
+        .collect::<serde_json::Result<Vec<_>>>()
+        .map_err(std::io::Error::from)?;
+    Ok(format!("{{{}}}", fields.join(", ")))
+}
+
+/// Pipes would end the cell and line breaks the row.
+fn markdown_cell(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace("\r\n", "<br>")
+        .replace('\n', "<br>")
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::metadata::set_key_column;
+
+    fn export(format: TextFormat) -> String {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                r#"CREATE TABLE people (id INTEGER PRIMARY KEY, name TEXT, age INTEGER, score REAL);
+                INSERT INTO people (name, age, score) VALUES
+                    ('anna "a|b"', 31, 2.5), ('<bo>', NULL, 1.0);"#,
+            )
+            .unwrap();
+        set_key_column(&connection, "people", "id").unwrap();
+        let mut out = vec![];
+        TextExport::new("people", format)
+            .write(&connection, &mut out)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn text_export_test() {
+        assert_eq!(
+            export(TextFormat::Json),
+            r#"[
+  {"name": "anna \"a|b\"", "age": 31, "score": 2.5},
+  {"name": "<bo>", "age": null, "score": 1.0}
+]
+"#
+        );
+        assert_eq!(
+            export(TextFormat::Ndjson),
+            r#"{"name": "anna \"a|b\"", "age": 31, "score": 2.5}
+{"name": "<bo>", "age": null, "score": 1.0}
+"#
+        );
+        assert_eq!(
+            export(TextFormat::Markdown),
+            r#"| name | age | score |
+| --- | --- | --- |
+| anna "a\|b" | 31 | 2.5 |
+| <bo> |  | 1 |
+"#
+        );
+        let html = export(TextFormat::Html);
+        assert!(html.starts_with("<table>\n  <thead>\n    <tr>\n      <th>name</th>"));
+        assert!(html.contains("      <td>&lt;bo&gt;</td>\n      <td></td>\n"));
+        assert!(html.ends_with("  </tbody>\n</table>\n"));
+        assert_eq!(
+            TextFormat::from_extension(Path::new("out.jsonl.gz")),
+            Some(TextFormat::Ndjson)
+        );
+    }
+}
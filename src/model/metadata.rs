@@ -1,28 +1,374 @@
+use std::collections::HashMap;
 use std::fmt;
+use std::path::{Path, PathBuf};
 
 use ratatui::text;
 
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OptionalExtension};
 
 use crate::error::AppResult;
 
+use super::collation::Collation;
+use super::csv_options::{CsvOptions, Encoding};
+
+/// Tables dataman creates for itself, these are not shown when moving between tables or exported.
+pub(crate) const METADATA_TABLES: [&str; 2] = ["table_of_tables", "column_collations"];
+
+/// E.g. `name NOT IN ('table_of_tables', 'column_collations')`, to be used in a WHERE clause on sqlite_master.
+pub(crate) fn not_metadata_table_condition() -> String {
+    let tables = METADATA_TABLES
+        .iter()
+        .map(|table| format!("'{table}'"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("name NOT IN ({tables})")
+}
+
+/// The columns of `table_of_tables`, a database from before one was added gets it added.
+const TABLE_OF_TABLES_COLUMNS: [(&str, &str); 13] = [
+    ("table_name", "TEXT PRIMARY KEY"),
+    ("row_count", "INTEGER"),
+    ("col_count", "INTEGER"),
+    ("text_col_count", "INTEGER"),
+    ("int_col_count", "INTEGER"),
+    ("source_path", "TEXT"),
+    ("source_format", "TEXT"),
+    ("source_sheet", "TEXT"),
+    ("csv_delimiter", "INTEGER"),
+    ("csv_encoding", "TEXT"),
+    ("csv_has_headers", "INTEGER"),
+    ("skip_rows", "INTEGER"),
+    ("key_column", "TEXT"),
+];
+
+/// Whether the connection has `table_name` in any schema, the main one or temp.
+fn has_table(conn: &Connection, table_name: &str) -> AppResult<bool> {
+    let has_table = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info(?1)",
+        [table_name],
+        |row| row.get(0),
+    )?;
+    Ok(has_table)
+}
+
+/// Creates the metadata tables in the temp schema, for a database the user opened. They are gone
+/// with the connection, so looking at a database doesn't change it. Metadata tables the database
+/// has already, because dataman saved it, are used instead.
+pub(crate) fn create_temp_metadata_tables(conn: &Connection) -> AppResult<()> {
+    create_table_of_tables_in(conn, "temp")?;
+    create_column_collations_in(conn, "temp")
+}
+
+/// Creates `table_of_tables` unless the database has it already, it keeps where the tables came
+/// from between sessions.
 pub(crate) fn create_table_of_tables(conn: &Connection) -> AppResult<()> {
-    conn.execute("DROP TABLE IF EXISTS table_of_tables;", [])?;
+    create_table_of_tables_in(conn, "main")
+}
+
+fn create_table_of_tables_in(conn: &Connection, schema: &str) -> AppResult<()> {
+    if !has_table(conn, "table_of_tables")? {
+        let columns = TABLE_OF_TABLES_COLUMNS
+            .iter()
+            .map(|(name, kind)| format!("{name} {kind}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        conn.execute(
+            &format!("CREATE TABLE {schema}.table_of_tables ({columns})"),
+            [],
+        )?;
+    }
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info('table_of_tables')")?;
+    let existing = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+    for (name, kind) in TABLE_OF_TABLES_COLUMNS {
+        if !existing.iter().any(|column| column == name) {
+            conn.execute(
+                &format!("ALTER TABLE table_of_tables ADD COLUMN {name} {kind}"),
+                [],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn create_column_collations(conn: &Connection) -> AppResult<()> {
+    create_column_collations_in(conn, "main")
+}
+
+fn create_column_collations_in(conn: &Connection, schema: &str) -> AppResult<()> {
+    if has_table(conn, "column_collations")? {
+        return Ok(());
+    }
     conn.execute(
-        "CREATE TABLE IF NOT EXISTS table_of_tables (
-            table_name TEXT PRIMARY KEY,
-            row_count INTEGER,
-            col_count INTEGER,
-            text_col_count INTEGER,
-            int_col_count INTEGER
-        )",
+        &format!(
+            "CREATE TABLE {schema}.column_collations (
+            table_name TEXT,
+            column_name TEXT,
+            collation TEXT,
+            PRIMARY KEY (table_name, column_name)
+        )"
+        ),
         [],
     )?;
     Ok(())
 }
 
-fn get_tables(conn: &Connection) -> AppResult<Vec<String>> {
-    let mut stmt = conn.prepare("SELECT name FROM sqlite_master WHERE type='table' AND name NOT LIKE 'sqlite_%' AND name != 'table_of_tables'")?;
+pub(crate) fn get_column_collations(
+    conn: &Connection,
+    table_name: &str,
+) -> AppResult<HashMap<String, Collation>> {
+    let mut stmt =
+        conn.prepare("SELECT column_name, collation FROM column_collations WHERE table_name = ?1")?;
+    let collations = stmt
+        .query_map([table_name], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?
+        .into_iter()
+        .filter_map(|(column, name)| Some((column, Collation::from_sql_name(&name)?)))
+        .collect();
+    Ok(collations)
+}
+
+pub(crate) fn set_column_collation(
+    conn: &Connection,
+    table_name: &str,
+    column_name: &str,
+    collation: Collation,
+) -> AppResult<()> {
+    if collation == Collation::Binary {
+        conn.execute(
+            "DELETE FROM column_collations WHERE table_name = ?1 AND column_name = ?2",
+            params![table_name, column_name],
+        )?;
+    } else {
+        conn.execute(
+            "REPLACE INTO column_collations (table_name, column_name, collation) VALUES (?1, ?2, ?3)",
+            params![table_name, column_name, collation.sql_name()],
+        )?;
+    }
+    Ok(())
+}
+
+pub(crate) fn rename_collation_column(
+    conn: &Connection,
+    table_name: &str,
+    column_name: &str,
+    new_column_name: &str,
+) -> AppResult<()> {
+    conn.execute(
+        "UPDATE column_collations SET column_name = ?3 WHERE table_name = ?1 AND column_name = ?2",
+        params![table_name, column_name, new_column_name],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn rename_collation_table(
+    conn: &Connection,
+    table_name: &str,
+    new_table_name: &str,
+) -> AppResult<()> {
+    conn.execute(
+        "UPDATE column_collations SET table_name = ?2 WHERE table_name = ?1",
+        params![table_name, new_table_name],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn delete_collations(
+    conn: &Connection,
+    table_name: &str,
+    column_name: Option<&str>,
+) -> AppResult<()> {
+    conn.execute(
+        "DELETE FROM column_collations WHERE table_name = ?1 AND (?2 IS NULL OR column_name = ?2)",
+        params![table_name, column_name],
+    )?;
+    Ok(())
+}
+
+/// The file a table was imported from, so it can be written back to it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TableSource {
+    pub(crate) path: PathBuf,
+    /// The extension without compression, e.g. `csv` for `data.csv.gz`.
+    pub(crate) format: String,
+    /// The worksheet of a spreadsheet the table came from.
+    pub(crate) sheet: Option<String>,
+    /// How a CSV file was read, it is written back with the same delimiter, encoding and header
+    /// row.
+    pub(crate) csv: CsvOptions,
+    /// The title rows above the headers of a worksheet, they are kept when it is written back.
+    pub(crate) skip_rows: usize,
+}
+
+pub(crate) fn set_table_source(
+    conn: &Connection,
+    table_name: &str,
+    source: &TableSource,
+) -> AppResult<()> {
+    conn.execute(
+        "INSERT INTO table_of_tables (table_name, source_path, source_format, source_sheet,
+            csv_delimiter, csv_encoding, csv_has_headers, skip_rows)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+        ON CONFLICT (table_name) DO UPDATE SET source_path = excluded.source_path,
+            source_format = excluded.source_format, source_sheet = excluded.source_sheet,
+            csv_delimiter = excluded.csv_delimiter, csv_encoding = excluded.csv_encoding,
+            csv_has_headers = excluded.csv_has_headers, skip_rows = excluded.skip_rows",
+        params![
+            table_name,
+            source.path.to_string_lossy(),
+            source.format,
+            source.sheet,
+            source.csv.delimiter,
+            source.csv.encoding.name(),
+            source.csv.has_headers,
+            source.skip_rows,
+        ],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn get_table_source(
+    conn: &Connection,
+    table_name: &str,
+) -> AppResult<Option<TableSource>> {
+    let source = conn
+        .query_row(
+            "SELECT source_path, source_format, source_sheet, csv_delimiter, csv_encoding,
+                csv_has_headers, skip_rows
+            FROM table_of_tables WHERE table_name = ?1 AND source_path IS NOT NULL",
+            [table_name],
+            |row| {
+                // a source from before the dialect was kept has NULLs, it was read as plain CSV
+                let default = CsvOptions::default();
+                let csv = CsvOptions {
+                    delimiter: row.get::<_, Option<u8>>(3)?.unwrap_or(default.delimiter),
+                    encoding: row
+                        .get::<_, Option<String>>(4)?
+                        .and_then(|name| Encoding::from_name(&name))
+                        .unwrap_or(default.encoding),
+                    has_headers: row
+                        .get::<_, Option<bool>>(5)?
+                        .unwrap_or(default.has_headers),
+                    ..default
+                };
+                Ok(TableSource {
+                    path: PathBuf::from(row.get::<_, String>(0)?),
+                    format: row.get(1)?,
+                    sheet: row.get(2)?,
+                    csv,
+                    skip_rows: row.get::<_, Option<usize>>(6)?.unwrap_or_default(),
+                })
+            },
+        )
+        .optional()?;
+    Ok(source)
+}
+
+/// The tables that came from the file at `path` with their worksheets, in the order they were
+/// imported.
+pub(crate) fn get_tables_from_source(
+    conn: &Connection,
+    path: &Path,
+) -> AppResult<Vec<(String, Option<String>)>> {
+    let mut stmt = conn.prepare(
+        "SELECT table_name, source_sheet FROM table_of_tables WHERE source_path = ?1 ORDER BY rowid",
+    )?;
+    let tables = stmt
+        .query_map([path.to_string_lossy()], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+    Ok(tables)
+}
+
+pub(crate) fn rename_table_source(
+    conn: &Connection,
+    table_name: &str,
+    new_table_name: &str,
+) -> AppResult<()> {
+    conn.execute(
+        "UPDATE table_of_tables SET table_name = ?2 WHERE table_name = ?1",
+        params![table_name, new_table_name],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn delete_table_source(conn: &Connection, table_name: &str) -> AppResult<()> {
+    conn.execute(
+        "DELETE FROM table_of_tables WHERE table_name = ?1",
+        [table_name],
+    )?;
+    Ok(())
+}
+
+/// Records that we added `column` to the table as its key, like the `id` of an imported file, so
+/// that it is left out when the table is exported. Imports run before `Database::new`, so
+/// `table_of_tables` is created if it isn't there yet.
+pub(crate) fn set_key_column(conn: &Connection, table_name: &str, column: &str) -> AppResult<()> {
+    create_table_of_tables(conn)?;
+    conn.execute(
+        "INSERT INTO table_of_tables (table_name, key_column) VALUES (?1, ?2)
+        ON CONFLICT (table_name) DO UPDATE SET key_column = excluded.key_column",
+        params![table_name, column],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn get_key_column(conn: &Connection, table_name: &str) -> AppResult<Option<String>> {
+    let column = conn
+        .query_row(
+            "SELECT key_column FROM table_of_tables WHERE table_name = ?1",
+            [table_name],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(column.flatten())
+}
+
+/// The columns that are written when the table is exported, with their declared types. That is
+/// every column but the key we added on import, a column of the user's that is called `id` stays.
+pub(crate) fn export_columns(
+    conn: &Connection,
+    table_name: &str,
+) -> AppResult<Vec<(String, String)>> {
+    let key_column = get_key_column(conn, table_name)?;
+    let mut stmt = conn.prepare(&format!(r#"PRAGMA table_info("{table_name}");"#))?;
+    let columns = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+        })?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?
+        .into_iter()
+        .filter(|(name, _)| key_column.as_ref() != Some(name))
+        .collect();
+    Ok(columns)
+}
+
+/// `SELECT` of the `export_columns` of the table, in the order of `ordering`, e.g. ` ORDER BY rowid`.
+pub(crate) fn export_query(
+    conn: &Connection,
+    table_name: &str,
+    ordering: &str,
+) -> AppResult<String> {
+    let columns = export_columns(conn, table_name)?
+        .iter()
+        .map(|(name, _)| format!(r#""{name}""#))
+        .collect::<Vec<_>>()
+        .join(", ");
+    Ok(format!(
+        r#"SELECT {columns} FROM "{table_name}"{ordering};"#
+    ))
+}
+
+/// The tables with user data, in the order they were created.
+pub(crate) fn get_tables(conn: &Connection) -> AppResult<Vec<String>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT name FROM sqlite_master WHERE type='table' AND name NOT LIKE 'sqlite_%' AND {} ORDER BY rowid",
+        not_metadata_table_condition()
+    ))?;
     let table_names = stmt
         .query_map([], |row| row.get(0))?
         .collect::<Result<Vec<String>, rusqlite::Error>>()?;
@@ -82,8 +428,11 @@ pub(crate) fn populate_table_of_tables(conn: &Connection) -> AppResult<()> {
         let int_col_count = get_table_kind_col_count(conn, &table, ColumnKind::Int)?;
         let text_col_count = get_table_kind_col_count(conn, &table, ColumnKind::Text)?;
 
+        // an upsert, REPLACE would delete where the table came from
         conn.execute(
-            r#"REPLACE INTO table_of_tables (table_name, row_count, col_count, text_col_count, int_col_count) VALUES (?1, ?2, ?3, ?4, ?5)"#,
+            r#"INSERT INTO table_of_tables (table_name, row_count, col_count, text_col_count, int_col_count) VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT (table_name) DO UPDATE SET row_count = excluded.row_count, col_count = excluded.col_count,
+                text_col_count = excluded.text_col_count, int_col_count = excluded.int_col_count"#,
             params![table, row_count, col_count,text_col_count, int_col_count],
         )?;
     }
@@ -145,13 +494,121 @@ mod tests {
         Ok(conn)
     }
     fn write_db_to_disk(conn: &Connection) {
-        let dst = Path::new("test.db");
+        let dst = std::env::temp_dir().join("dataman-test.db");
         let mut dst = Connection::open(dst).unwrap();
         let backup = backup::Backup::new(conn, &mut dst).unwrap();
         backup
             .run_to_completion(10, time::Duration::from_millis(250), None)
             .unwrap();
     }
+    #[test]
+    fn column_collations_test() {
+        let conn = setup_three_table_db().unwrap();
+        create_column_collations(&conn).unwrap();
+        set_column_collation(&conn, "t2", "c1", Collation::Natural).unwrap();
+        set_column_collation(&conn, "t2", "c2", Collation::Unicode).unwrap();
+        set_column_collation(&conn, "t3", "c1", Collation::NoCase).unwrap();
+        set_column_collation(&conn, "t2", "c2", Collation::Binary).unwrap();
+        rename_collation_column(&conn, "t2", "c1", "first").unwrap();
+        let collations = get_column_collations(&conn, "t2").unwrap();
+        assert_eq!(collations.len(), 1);
+        assert_eq!(collations.get("first"), Some(&Collation::Natural));
+
+        rename_collation_table(&conn, "t3", "t4").unwrap();
+        assert!(get_column_collations(&conn, "t3").unwrap().is_empty());
+        delete_collations(&conn, "t4", None).unwrap();
+        assert!(get_column_collations(&conn, "t4").unwrap().is_empty());
+        delete_collations(&conn, "t2", Some("first")).unwrap();
+        assert!(get_column_collations(&conn, "t2").unwrap().is_empty());
+    }
+
+    #[test]
+    fn table_source_test() {
+        let conn = setup_three_table_db().unwrap();
+        let source = TableSource {
+            path: PathBuf::from("report.xlsx"),
+            format: "xlsx".to_string(),
+            sheet: Some("sales".to_string()),
+            csv: CsvOptions::default(),
+            skip_rows: 2,
+        };
+        set_table_source(&conn, "t1", &source).unwrap();
+        set_table_source(
+            &conn,
+            "t3",
+            &TableSource {
+                sheet: None,
+                ..source.clone()
+            },
+        )
+        .unwrap();
+        populate_table_of_tables(&conn).unwrap();
+        assert_eq!(get_table_source(&conn, "t1").unwrap(), Some(source.clone()));
+        assert_eq!(get_table_source(&conn, "t2").unwrap(), None);
+
+        rename_table_source(&conn, "t1", "sales").unwrap();
+        delete_table_source(&conn, "t3").unwrap();
+        assert_eq!(
+            get_tables_from_source(&conn, Path::new("report.xlsx")).unwrap(),
+            vec![("sales".to_string(), Some("sales".to_string()))]
+        );
+    }
+
+    #[test]
+    fn create_table_of_tables_keeps_sources_test() {
+        let conn = Connection::open_in_memory().unwrap();
+        // as an older version created it
+        conn.execute_batch(
+            "CREATE TABLE table_of_tables (table_name TEXT PRIMARY KEY, row_count INTEGER,
+                col_count INTEGER, text_col_count INTEGER, int_col_count INTEGER,
+                source_path TEXT, source_format TEXT, source_sheet TEXT);
+            INSERT INTO table_of_tables (table_name, source_path, source_format)
+                VALUES ('people', 'people.csv', 'csv');",
+        )
+        .unwrap();
+        create_table_of_tables(&conn).unwrap();
+        let source = get_table_source(&conn, "people").unwrap().unwrap();
+        assert_eq!(source.csv, CsvOptions::default());
+
+        let source = TableSource {
+            csv: CsvOptions {
+                delimiter: b';',
+                encoding: Encoding::Utf16Le,
+                has_headers: false,
+                ..Default::default()
+            },
+            ..source
+        };
+        set_table_source(&conn, "people", &source).unwrap();
+        create_table_of_tables(&conn).unwrap();
+        assert_eq!(get_table_source(&conn, "people").unwrap(), Some(source));
+    }
+
+    #[test]
+    fn export_columns_test() {
+        let conn = setup_three_table_db().unwrap();
+        set_key_column(&conn, "t1", "id").unwrap();
+        let names = |table_name| -> Vec<String> {
+            export_columns(&conn, table_name)
+                .unwrap()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect()
+        };
+        assert_eq!(names("t1"), vec!["c1"]);
+        // t2 came with its own id column
+        assert_eq!(names("t2"), vec!["id", "c1", "c2", "c3"]);
+        rename_table_source(&conn, "t1", "renamed").unwrap();
+        assert_eq!(
+            get_key_column(&conn, "renamed").unwrap(),
+            Some("id".to_string())
+        );
+        assert_eq!(
+            export_query(&conn, "t2", " ORDER BY rowid").unwrap(),
+            r#"SELECT "id", "c1", "c2", "c3" FROM "t2" ORDER BY rowid;"#
+        );
+    }
+
     #[test]
     fn table_of_tables_num_count_test() {
         let conn = setup_three_table_db().unwrap();
@@ -1,8 +1,18 @@
+pub mod background;
+pub(crate) mod collation;
+pub(crate) mod compression;
+pub mod convert;
 pub mod converter;
+pub mod csv_options;
 pub mod database;
 pub mod datarow;
 pub mod db_slice;
+pub mod export;
 // pub(crate) mod embedded_engine;
 pub mod metadata;
+pub mod parquet_file;
 pub mod regexping;
+pub mod search;
+pub mod sort;
+pub mod sql_dump;
 pub mod sql_queries;
@@ -0,0 +1,452 @@
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use parquet::basic::{ConvertedType, LogicalType, Repetition, Type as PhysicalType};
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::FileReader;
+use parquet::file::serialized_reader::SerializedFileReader;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::record::Field;
+use parquet::schema::types::Type;
+use rusqlite::{params_from_iter, Connection};
+
+use crate::error::AppResult;
+
+use super::convert::{Exportable, Importable};
+use super::datarow::DataItem;
+use super::metadata::{export_columns, set_key_column};
+use super::sql_queries::key_column_name;
+
+/// Rows in each row group we write, readers can skip or parallelize over row groups.
+const ROW_GROUP_SIZE: usize = 65536;
+
+/// A table that is imported from or exported to a Parquet file.
+#[derive(Debug, Clone)]
+pub struct Parquet {
+    pub table_name: String,
+}
+
+impl Parquet {
+    pub fn new(table_name: &str) -> Self {
+        Self {
+            table_name: table_name.to_string(),
+        }
+    }
+}
+
+impl Importable for Parquet {
+    /// Creates the table with a column for each top level field. Integers and booleans become
+    /// INTEGER, floats and decimals REAL, strings TEXT, and dates and timestamps ISO 8601 TEXT.
+    fn import_to_db(&self, path: &Path, connection: &Connection) -> AppResult<()> {
+        let reader = SerializedFileReader::new(File::open(path)?)?;
+        let schema = reader.metadata().file_metadata().schema();
+        let fields = schema.get_fields();
+        let column_types = fields
+            .iter()
+            .map(|field| format!(r#""{}" {}"#, field.name(), column_affinity(field)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let columns = fields
+            .iter()
+            .map(|field| format!(r#""{}""#, field.name()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let params = (1..=fields.len())
+            .map(|i| format!("?{i}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let table_name = &self.table_name;
+        let names: Vec<&str> = fields.iter().map(|field| field.name()).collect();
+        let key_column = key_column_name(&names);
+        let transaction = connection.unchecked_transaction()?;
+        transaction.execute_batch(&format!(
+            r#"CREATE TABLE "{table_name}" ("{key_column}" INTEGER PRIMARY KEY, {column_types});"#
+        ))?;
+        set_key_column(&transaction, table_name, &key_column)?;
+        {
+            let mut stmt = transaction.prepare(&format!(
+                r#"INSERT INTO "{table_name}" ({columns}) VALUES ({params});"#
+            ))?;
+            for row in reader.get_row_iter(None)? {
+                let row = row?;
+                let items = row.get_column_iter().map(|(_, field)| parquet_item(field));
+                stmt.execute(params_from_iter(items))?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+impl Exportable for Parquet {
+    /// Writes the table with a schema from the column affinities: INTEGER columns are INT64, REAL
+    /// columns DOUBLE and the rest UTF8 strings. A column with values that don't fit its affinity,
+    /// e.g. text in an INTEGER column, is written as strings so nothing is lost. The `id` column
+    /// that we add on import is left out, see `export_columns`.
+    fn export_to_db(&self, path: &Path, connection: &Connection) -> AppResult<()> {
+        let table_name = &self.table_name;
+        let columns = export_columns(connection, table_name)?
+            .into_iter()
+            .map(|(name, declared_type)| {
+                let kind = export_kind(connection, table_name, &name, &declared_type)?;
+                Ok((name, kind))
+            })
+            .collect::<AppResult<Vec<(String, PhysicalType)>>>()?;
+
+        let fields = columns
+            .iter()
+            .map(|(name, kind)| {
+                let builder =
+                    Type::primitive_type_builder(name, *kind).with_repetition(Repetition::OPTIONAL);
+                let builder = match kind {
+                    PhysicalType::BYTE_ARRAY => {
+                        builder.with_logical_type(Some(LogicalType::String))
+                    }
+                    _ => builder,
+                };
+                builder.build().map(Arc::new)
+            })
+            .collect::<parquet::errors::Result<Vec<_>>>()?;
+        let schema = Type::group_type_builder("schema")
+            .with_fields(fields)
+            .build()?;
+        let properties = WriterProperties::builder()
+            .set_compression(parquet::basic::Compression::SNAPPY)
+            .build();
+        let mut writer =
+            SerializedFileWriter::new(File::create(path)?, Arc::new(schema), Arc::new(properties))?;
+
+        let column_list = columns
+            .iter()
+            .map(|(name, _)| format!(r#""{name}""#))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut stmt = connection.prepare(&format!(
+            r#"SELECT {column_list} FROM "{table_name}" ORDER BY rowid;"#
+        ))?;
+        let mut rows = stmt.query([])?;
+        let mut row_group: Vec<Vec<DataItem>> = Vec::with_capacity(ROW_GROUP_SIZE);
+        loop {
+            let row = rows.next()?;
+            if let Some(row) = row {
+                let items = (0..columns.len())
+                    .map(|i| row.get_ref(i).map(DataItem::from))
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                row_group.push(items);
+            }
+            if row_group.len() == ROW_GROUP_SIZE || (row.is_none() && !row_group.is_empty()) {
+                write_row_group(&mut writer, &columns, &row_group)?;
+                row_group.clear();
+            }
+            if row.is_none() {
+                break;
+            }
+        }
+        writer.close()?;
+        Ok(())
+    }
+}
+
+/// The SQLite type for a Parquet field, nested fields are shown as text.
+fn column_affinity(field: &Type) -> &'static str {
+    if !field.is_primitive() {
+        return "TEXT";
+    }
+    let info = field.get_basic_info();
+    match (
+        field.get_physical_type(),
+        info.logical_type(),
+        info.converted_type(),
+    ) {
+        (_, Some(LogicalType::Decimal { .. }), _) | (_, _, ConvertedType::DECIMAL) => "REAL",
+        (
+            _,
+            Some(LogicalType::Date | LogicalType::Time { .. } | LogicalType::Timestamp { .. }),
+            _,
+        ) => "TEXT",
+        (
+            _,
+            _,
+            ConvertedType::DATE
+            | ConvertedType::TIME_MILLIS
+            | ConvertedType::TIME_MICROS
+            | ConvertedType::TIMESTAMP_MILLIS
+            | ConvertedType::TIMESTAMP_MICROS,
+        ) => "TEXT",
+        (PhysicalType::BOOLEAN | PhysicalType::INT32 | PhysicalType::INT64, _, _) => "INTEGER",
+        (PhysicalType::FLOAT | PhysicalType::DOUBLE, _, _) => "REAL",
+        _ => "TEXT",
+    }
+}
+
+fn parquet_item(field: &Field) -> DataItem {
+    match field {
+        Field::Null => DataItem::Null,
+        Field::Bool(b) => DataItem::Integer(*b as i64),
+        Field::Byte(n) => DataItem::Integer(*n as i64),
+        Field::Short(n) => DataItem::Integer(*n as i64),
+        Field::Int(n) => DataItem::Integer(*n as i64),
+        Field::Long(n) => DataItem::Integer(*n),
+        Field::UByte(n) => DataItem::Integer(*n as i64),
+        Field::UShort(n) => DataItem::Integer(*n as i64),
+        Field::UInt(n) => DataItem::Integer(*n as i64),
+        Field::ULong(n) => match i64::try_from(*n) {
+            Ok(n) => DataItem::Integer(n),
+            Err(_) => DataItem::Float(*n as f64),
+        },
+        Field::Float16(x) => DataItem::Float(x.to_f64()),
+        Field::Float(x) => DataItem::Float(*x as f64),
+        Field::Double(x) => DataItem::Float(*x),
+        Field::Decimal(_) => match field.to_string().parse() {
+            Ok(x) => DataItem::Float(x),
+            Err(_) => DataItem::Text(field.to_string()),
+        },
+        Field::Str(s) => DataItem::Text(s.clone()),
+        Field::Date(days) => date_time(*days as i64 * 86_400_000)
+            .map(|date| DataItem::Text(date.format("%Y-%m-%d").to_string()))
+            .unwrap_or(DataItem::Integer(*days as i64)),
+        Field::TimestampMillis(millis) => timestamp_item(date_time(*millis), *millis),
+        Field::TimestampMicros(micros) => {
+            timestamp_item(chrono::DateTime::from_timestamp_micros(*micros), *micros)
+        }
+        Field::Bytes(_) | Field::Group(_) | Field::ListInternal(_) | Field::MapInternal(_) => {
+            DataItem::Text(field.to_string())
+        }
+    }
+}
+
+fn date_time(millis: i64) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::from_timestamp_millis(millis)
+}
+
+/// E.g. `2024-01-31 12:30:00`, in UTC, with the fraction of a second if there is one.
+fn timestamp_item(date_time: Option<chrono::DateTime<chrono::Utc>>, raw: i64) -> DataItem {
+    match date_time {
+        Some(date_time) => DataItem::Text(date_time.format("%Y-%m-%d %H:%M:%S%.f").to_string()),
+        None => DataItem::Integer(raw),
+    }
+}
+
+/// INT64 or DOUBLE when the declared type and every value agree, BYTE_ARRAY otherwise.
+fn export_kind(
+    connection: &Connection,
+    table_name: &str,
+    column: &str,
+    declared_type: &str,
+) -> AppResult<PhysicalType> {
+    let (kind, allowed) = match declared_type.to_uppercase().as_str() {
+        "INTEGER" | "INT" | "BIGINT" => (PhysicalType::INT64, "'integer'"),
+        "REAL" | "FLOAT" | "DOUBLE" => (PhysicalType::DOUBLE, "'real', 'integer'"),
+        _ => return Ok(PhysicalType::BYTE_ARRAY),
+    };
+    let misfits: i64 = connection.query_row(
+        &format!(
+            r#"SELECT COUNT(*) FROM "{table_name}" WHERE typeof("{column}") NOT IN ({allowed}, 'null');"#
+        ),
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(if misfits == 0 {
+        kind
+    } else {
+        PhysicalType::BYTE_ARRAY
+    })
+}
+
+fn write_row_group(
+    writer: &mut SerializedFileWriter<File>,
+    columns: &[(String, PhysicalType)],
+    rows: &[Vec<DataItem>],
+) -> AppResult<()> {
+    let mut row_group = writer.next_row_group()?;
+    for (i, (_, kind)) in columns.iter().enumerate() {
+        let Some(mut column) = row_group.next_column()? else {
+            break;
+        };
+        let items = rows.iter().map(|row| &row[i]);
+        let definition_levels: Vec<i16> = items
+            .clone()
+            .map(|item| (*item != DataItem::Null) as i16)
+            .collect();
+        match kind {
+            PhysicalType::INT64 => {
+                let values: Vec<i64> = items
+                    .filter_map(|item| match item {
+                        DataItem::Integer(n) => Some(*n),
+                        _ => None,
+                    })
+                    .collect();
+                column
+                    .typed::<Int64Type>()
+                    .write_batch(&values, Some(&definition_levels), None)?;
+            }
+            PhysicalType::DOUBLE => {
+                let values: Vec<f64> = items
+                    .filter_map(|item| match item {
+                        DataItem::Integer(n) => Some(*n as f64),
+                        DataItem::Float(x) => Some(*x),
+                        _ => None,
+                    })
+                    .collect();
+                column.typed::<DoubleType>().write_batch(
+                    &values,
+                    Some(&definition_levels),
+                    None,
+                )?;
+            }
+            _ => {
+                let values: Vec<ByteArray> = items
+                    .filter(|item| **item != DataItem::Null)
+                    .map(|item| ByteArray::from(String::from(item.clone()).into_bytes()))
+                    .collect();
+                column.typed::<ByteArrayType>().write_batch(
+                    &values,
+                    Some(&definition_levels),
+                    None,
+                )?;
+            }
+        }
+        column.close()?;
+    }
+    row_group.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(connection: &Connection, table_name: &str) -> Vec<Vec<DataItem>> {
+        let mut stmt = connection
+            .prepare(&format!(r#"SELECT * FROM "{table_name}" ORDER BY id;"#))
+            .unwrap();
+        let columns = stmt.column_count();
+        stmt.query_map([], |row| {
+            (0..columns)
+                .map(|i| row.get_ref(i).map(DataItem::from))
+                .collect()
+        })
+        .unwrap()
+        .collect::<rusqlite::Result<_>>()
+        .unwrap()
+    }
+
+    #[test]
+    fn parquet_round_trip_test() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE people (id INTEGER PRIMARY KEY, name TEXT, age INTEGER, score REAL, note INTEGER);
+                INSERT INTO people (name, age, score, note) VALUES
+                    ('anna', 31, 2.5, 1), ('bo', NULL, 1, 'not a number'), (NULL, 7, NULL, NULL);",
+            )
+            .unwrap();
+        set_key_column(&connection, "people", "id").unwrap();
+        let path = std::env::temp_dir().join("dataman_round_trip.parquet");
+        Parquet::new("people")
+            .export_to_db(&path, &connection)
+            .unwrap();
+        Parquet::new("copy")
+            .import_to_db(&path, &connection)
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let declared: Vec<String> = connection
+            .prepare(r#"SELECT type FROM pragma_table_info('copy');"#)
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        // note has text in an INTEGER column, so it is exported as strings
+        assert_eq!(declared, vec!["INTEGER", "TEXT", "INTEGER", "REAL", "TEXT"]);
+        let text = |s: &str| DataItem::Text(s.to_string());
+        assert_eq!(
+            rows(&connection, "copy"),
+            vec![
+                vec![
+                    DataItem::Integer(1),
+                    text("anna"),
+                    DataItem::Integer(31),
+                    DataItem::Float(2.5),
+                    text("1")
+                ],
+                vec![
+                    DataItem::Integer(2),
+                    text("bo"),
+                    DataItem::Null,
+                    DataItem::Float(1.0),
+                    text("not a number")
+                ],
+                vec![
+                    DataItem::Integer(3),
+                    DataItem::Null,
+                    DataItem::Integer(7),
+                    DataItem::Null,
+                    DataItem::Null
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn parquet_dates_and_timestamps_test() {
+        use parquet::basic::TimeUnit;
+        use parquet::data_type::Int32Type;
+
+        let path = std::env::temp_dir().join("dataman_dates.parquet");
+        let day = Type::primitive_type_builder("day", PhysicalType::INT32)
+            .with_logical_type(Some(LogicalType::Date))
+            .with_repetition(Repetition::REQUIRED)
+            .build()
+            .unwrap();
+        let at = Type::primitive_type_builder("at", PhysicalType::INT64)
+            .with_logical_type(Some(LogicalType::Timestamp {
+                is_adjusted_to_u_t_c: true,
+                unit: TimeUnit::MILLIS(Default::default()),
+            }))
+            .with_repetition(Repetition::REQUIRED)
+            .build()
+            .unwrap();
+        let schema = Type::group_type_builder("schema")
+            .with_fields(vec![Arc::new(day), Arc::new(at)])
+            .build()
+            .unwrap();
+        let mut writer = SerializedFileWriter::new(
+            File::create(&path).unwrap(),
+            Arc::new(schema),
+            Default::default(),
+        )
+        .unwrap();
+        let mut row_group = writer.next_row_group().unwrap();
+        let mut column = row_group.next_column().unwrap().unwrap();
+        column
+            .typed::<Int32Type>()
+            .write_batch(&[19_753], None, None)
+            .unwrap();
+        column.close().unwrap();
+        let mut column = row_group.next_column().unwrap().unwrap();
+        column
+            .typed::<Int64Type>()
+            .write_batch(&[1_706_704_200_500], None, None)
+            .unwrap();
+        column.close().unwrap();
+        row_group.close().unwrap();
+        writer.close().unwrap();
+
+        let connection = Connection::open_in_memory().unwrap();
+        Parquet::new("events")
+            .import_to_db(&path, &connection)
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(
+            rows(&connection, "events")[0][1..],
+            [
+                DataItem::Text("2024-01-31".to_string()),
+                DataItem::Text("2024-01-31 12:30:00.500".to_string())
+            ]
+        );
+    }
+}
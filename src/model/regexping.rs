@@ -51,35 +51,53 @@ pub mod custom_functions {
     use core::hash;
     use std::{
         collections::{hash_map, HashMap},
-        sync::{Arc, Mutex, RwLock},
+        sync::{Arc, Mutex, PoisonError, RwLock},
     };
 
     use regex::Regex;
     use rusqlite::{functions::FunctionFlags, types::ValueRef, Connection};
 
+    use crate::model::collation::add_collations;
     use crate::model::database::Database;
+    use crate::model::datarow::DataItem;
+
+    /// The compiled `pattern`, it is only compiled again when the pattern changes. A pattern that
+    /// doesn't compile fails the query instead of the app.
+    fn cached_regex(cache: &Mutex<Option<Regex>>, pattern: &str) -> rusqlite::Result<Regex> {
+        let mut cached = cache.lock().unwrap_or_else(PoisonError::into_inner);
+        match cached.as_ref() {
+            Some(regex) if regex.as_str() == pattern => Ok(regex.clone()),
+            _ => {
+                let regex = Regex::new(pattern)
+                    .map_err(|err| rusqlite::Error::UserFunctionError(Box::new(err)))?;
+                *cached = Some(regex.clone());
+                Ok(regex)
+            }
+        }
+    }
 
     pub fn add_custom_functions(conn: &Connection) -> rusqlite::Result<()> {
+        add_collations(conn)?;
         let hash_map: HashMap<String, Regex> = HashMap::new();
         let regex_cache: Arc<Mutex<HashMap<String, Regex>>> = Arc::new(Mutex::new(HashMap::new()));
 
-        let cached_filter_regex = Arc::new(Mutex::new(Regex::new("").unwrap()));
+        let cached_filter_regex = Arc::new(Mutex::new(None));
         conn.create_scalar_function(
             "regexp",
             2,
             FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
             move |ctx| {
                 let regex_str = ctx.get::<String>(0)?;
-                let text = ctx.get::<String>(1)?;
-                let mut cached_filter_regex = cached_filter_regex.lock().unwrap();
-                if cached_filter_regex.as_str() != regex_str {
-                    *cached_filter_regex = Regex::new(&regex_str).unwrap();
-                }
-
-                Ok(cached_filter_regex.is_match(&text))
+                // numbers are matched against how they are displayed, NULL never matches
+                let text = match ctx.get_raw(1) {
+                    ValueRef::Null => return Ok(false),
+                    value => DataItem::from(value).to_string(),
+                };
+                let regex = cached_regex(&cached_filter_regex, &regex_str)?;
+                Ok(regex.is_match(&text))
             },
         )?;
-        let cached_with_capture_regex = Arc::new(Mutex::new(Regex::new("").unwrap()));
+        let cached_with_capture_regex = Arc::new(Mutex::new(None));
         conn.create_scalar_function(
             // this one is used to filter, to create new tables
             "regexp_transform_with_capture_group",
@@ -89,22 +107,17 @@ pub mod custom_functions {
                 let regex_str = ctx.get::<String>(0)?;
                 let text = ctx.get::<String>(1)?;
                 let substitution_pattern = ctx.get::<String>(2)?;
-                let mut cached_with_capture_regex = cached_with_capture_regex.lock().unwrap();
-                if cached_with_capture_regex.as_str() != regex_str {
-                    *cached_with_capture_regex = Regex::new(&regex_str).unwrap();
-                }
-                let is_match = cached_with_capture_regex.is_match(&text);
+                let regex = cached_regex(&cached_with_capture_regex, &regex_str)?;
+                let is_match = regex.is_match(&text);
                 if is_match {
-                    let val = cached_with_capture_regex
-                        .replace(&text, &substitution_pattern)
-                        .to_string();
+                    let val = regex.replace(&text, &substitution_pattern).to_string();
                     Ok(Some(val))
                 } else {
                     Ok(None)
                 }
             },
         )?;
-        let cached_no_capture_regex = Arc::new(Mutex::new(Regex::new("").unwrap()));
+        let cached_no_capture_regex = Arc::new(Mutex::new(None));
         conn.create_scalar_function(
             // this is used to derive a new column
             "regexp_transform_no_capture_group",
@@ -113,11 +126,8 @@ pub mod custom_functions {
             move |ctx| {
                 let regex_str = ctx.get::<String>(0)?;
                 let text = ctx.get::<String>(1)?;
-                let mut cached_no_capture_regex = cached_no_capture_regex.lock().unwrap();
-                if cached_no_capture_regex.as_str() != regex_str {
-                    *cached_no_capture_regex = Regex::new(&regex_str).unwrap();
-                }
-                let result = cached_no_capture_regex.captures(&text);
+                let regex = cached_regex(&cached_no_capture_regex, &regex_str)?;
+                let result = regex.captures(&text);
                 let val = result
                     .and_then(|c| c.get(0))
                     .map(|v| v.as_str().to_string());
@@ -146,4 +156,20 @@ mod tests {
 
         assert_eq!(query, expected_query);
     }
+
+    #[test]
+    fn invalid_regex_fails_the_query_test() {
+        let connection = rusqlite::Connection::open_in_memory().unwrap();
+        custom_functions::add_custom_functions(&connection).unwrap();
+        let is_match: bool = connection
+            .query_row("SELECT 'abc' REGEXP '^a';", [], |row| row.get(0))
+            .unwrap();
+        assert!(is_match);
+        let result =
+            connection.query_row("SELECT 'abc' REGEXP '(';", [], |row| row.get::<_, bool>(0));
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("regex parse error"));
+    }
 }
@@ -0,0 +1,152 @@
+use regex::Regex;
+
+use crate::error::AppResult;
+
+use super::datarow::DataItem;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum SearchKind {
+    Exact,
+    Regex,
+    /// Case insensitive subsequence match, "hnrk" matches "Henrik".
+    Fuzzy,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SearchOptions {
+    pub(crate) ignore_case: bool,
+    pub(crate) all_columns: bool,
+}
+
+/// The last search the user made. Every kind of search is turned into a regex, so the same
+/// pattern can be sent to sqlite's REGEXP function to find matching rows and be used by the TUI
+/// to highlight matching cells.
+#[derive(Debug, Clone)]
+pub(crate) struct Search {
+    pub(crate) kind: SearchKind,
+    pub(crate) pattern: String,
+    /// The column we search in, `None` means we search in all columns.
+    pub(crate) column: Option<String>,
+    pub(crate) regex: Regex,
+    /// The zero indexed row number of the match the cursor is on, in the current ordering.
+    pub(crate) current: Option<u32>,
+    /// How many rows match, counted once when the search is made and again after the data
+    /// changed.
+    pub(crate) count: Option<u32>,
+}
+
+impl Search {
+    pub(crate) fn new(
+        kind: SearchKind,
+        pattern: &str,
+        column: Option<String>,
+        options: SearchOptions,
+    ) -> AppResult<Self> {
+        let body = match kind {
+            SearchKind::Exact => format!("^{}$", regex::escape(pattern)),
+            SearchKind::Regex => pattern.to_string(),
+            SearchKind::Fuzzy => pattern
+                .chars()
+                .map(|c| regex::escape(&c.to_string()))
+                .collect::<Vec<_>>()
+                .join(".*?"),
+        };
+        let flags = if options.ignore_case || kind == SearchKind::Fuzzy {
+            "(?i)"
+        } else {
+            ""
+        };
+        let regex = Regex::new(&format!("{flags}{body}"))?;
+        Ok(Self {
+            kind,
+            pattern: pattern.to_string(),
+            column,
+            regex,
+            current: None,
+            count: None,
+        })
+    }
+
+    pub(crate) fn is_match(&self, header: &str, item: &DataItem) -> bool {
+        if self
+            .column
+            .as_ref()
+            .is_some_and(|column| column.as_str() != header)
+        {
+            return false;
+        }
+        match item {
+            DataItem::Null => false,
+            item => self.regex.is_match(&item.to_string()),
+        }
+    }
+
+    pub(crate) fn status(&self) -> String {
+        match (self.current, self.count) {
+            (Some(row), Some(1)) => format!("match in row {}, the only one", row + 1),
+            (Some(row), Some(count)) => format!("match in row {}, {count} matches", row + 1),
+            (Some(row), None) => format!("match in row {}", row + 1),
+            (None, _) => format!("no match for '{}'", self.pattern),
+        }
+    }
+}
+
+/// Where the cursor was, and which search was active, before an incremental search started.
+#[derive(Debug, Clone)]
+pub(crate) struct SearchOrigin {
+    pub(crate) selected: usize,
+    pub(crate) row_offset: u32,
+    pub(crate) search: Option<Search>,
+    /// The ordering keys of the row the cursor was on, the preview scans forward from them.
+    pub(crate) key: Option<Vec<DataItem>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_search_escapes_pattern() {
+        let search = Search::new(SearchKind::Exact, "a.c", None, SearchOptions::default()).unwrap();
+        assert!(search.is_match("h", &DataItem::Text("a.c".to_string())));
+        assert!(!search.is_match("h", &DataItem::Text("abc".to_string())));
+        assert!(!search.is_match("h", &DataItem::Text("a.cd".to_string())));
+    }
+
+    #[test]
+    fn ignore_case_and_column_scope() {
+        let options = SearchOptions {
+            ignore_case: true,
+            all_columns: false,
+        };
+        let search =
+            Search::new(SearchKind::Regex, "^hen", Some("name".to_string()), options).unwrap();
+        assert!(search.is_match("name", &DataItem::Text("Henrik".to_string())));
+        assert!(!search.is_match("other", &DataItem::Text("Henrik".to_string())));
+        assert!(!search.is_match("name", &DataItem::Null));
+    }
+
+    #[test]
+    fn fuzzy_search_matches_subsequence() {
+        let search =
+            Search::new(SearchKind::Fuzzy, "hnr.k", None, SearchOptions::default()).unwrap();
+        assert!(search.is_match("h", &DataItem::Text("Hen r. Kowalski".to_string())));
+        assert!(!search.is_match("h", &DataItem::Text("henrik".to_string())));
+        let search = Search::new(SearchKind::Fuzzy, "jsn", None, SearchOptions::default()).unwrap();
+        assert!(search.is_match("h", &DataItem::Text("Johansson".to_string())));
+        assert!(!search.is_match("h", &DataItem::Text("Jonas".to_string())));
+    }
+
+    #[test]
+    fn status_test() {
+        let mut search =
+            Search::new(SearchKind::Exact, "x", None, SearchOptions::default()).unwrap();
+        assert_eq!(search.status(), "no match for 'x'");
+        search.current = Some(4);
+        assert_eq!(search.status(), "match in row 5");
+        search.count = Some(1);
+        assert_eq!(search.status(), "match in row 5, the only one");
+        search.count = Some(3);
+        assert_eq!(search.status(), "match in row 5, 3 matches");
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+
+use super::collation::Collation;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SortKey {
+    pub(crate) column: String,
+    pub(crate) is_asc: bool,
+}
+
+impl SortKey {
+    pub(crate) fn new(column: &str) -> Self {
+        Self {
+            column: column.to_string(),
+            is_asc: true,
+        }
+    }
+
+    fn indicator(&self) -> &str {
+        if self.is_asc {
+            "▲"
+        } else {
+            "▼"
+        }
+    }
+}
+
+/// A column in an ORDER BY clause, with the collation it is compared with.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct OrderKey {
+    pub(crate) column: String,
+    pub(crate) is_asc: bool,
+    pub(crate) collation: Collation,
+}
+
+impl OrderKey {
+    /// The last key of every ordering, so that rows with equal values always come in the same order.
+    pub(crate) fn rowid() -> Self {
+        Self {
+            column: "rowid".to_string(),
+            is_asc: true,
+            collation: Collation::Binary,
+        }
+    }
+
+    /// The column, with its collation if it has one, e.g. `"name" COLLATE "NATURAL"`.
+    pub(crate) fn expression(&self) -> String {
+        match self.collation {
+            Collation::Binary => format!(r#""{}""#, self.column),
+            collation => format!(r#""{}" COLLATE "{}""#, self.column, collation.sql_name()),
+        }
+    }
+
+    pub(crate) fn reversed(&self) -> Self {
+        Self {
+            is_asc: !self.is_asc,
+            ..self.clone()
+        }
+    }
+}
+
+pub(crate) fn order_by_clause(keys: &[OrderKey]) -> String {
+    if keys.is_empty() {
+        return "".to_string();
+    }
+    let keys = keys
+        .iter()
+        .map(|key| {
+            let direction = if key.is_asc { "ASC" } else { "DESC" };
+            format!("{} {}", key.expression(), direction)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(" ORDER BY {keys} ")
+}
+
+/// The columns the current table is sorted by, the first key has the highest priority.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct SortStack {
+    pub(crate) keys: Vec<SortKey>,
+}
+
+impl SortStack {
+    /// Sort by this column only. If we already only sort by this column the direction is toggled.
+    pub(crate) fn sort_by(&mut self, column: &str) {
+        match self.keys.as_mut_slice() {
+            [key] if key.column == column => key.is_asc = !key.is_asc,
+            _ => self.keys = vec![SortKey::new(column)],
+        }
+    }
+
+    /// Add this column as the lowest priority key. If it is already a key the direction is toggled.
+    pub(crate) fn add(&mut self, column: &str) {
+        match self.keys.iter_mut().find(|key| key.column == column) {
+            Some(key) => key.is_asc = !key.is_asc,
+            None => self.keys.push(SortKey::new(column)),
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.keys.clear();
+    }
+
+    pub(crate) fn remove(&mut self, column: &str) {
+        self.keys.retain(|key| key.column != column);
+    }
+
+    pub(crate) fn rename(&mut self, column: &str, new_column: &str) {
+        for key in self.keys.iter_mut().filter(|key| key.column == column) {
+            key.column = new_column.to_string();
+        }
+    }
+
+    pub(crate) fn order_keys(&self, collations: &HashMap<String, Collation>) -> Vec<OrderKey> {
+        self.keys
+            .iter()
+            .map(|key| OrderKey {
+                column: key.column.clone(),
+                is_asc: key.is_asc,
+                collation: collations.get(&key.column).copied().unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    /// The ORDER BY clause, columns without a collation in `collations` use sqlite's default.
+    pub(crate) fn order_by(&self, collations: &HashMap<String, Collation>) -> String {
+        order_by_clause(&self.order_keys(collations))
+    }
+
+    /// E.g. "▲1" if the column is the first sort key in ascending order.
+    pub(crate) fn indicator(&self, column: &str) -> Option<String> {
+        self.keys
+            .iter()
+            .position(|key| key.column == column)
+            .map(|i| format!("{}{}", self.keys[i].indicator(), i + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort_by_toggles_single_key() {
+        let mut stack = SortStack::default();
+        assert_eq!(stack.order_by(&HashMap::new()), "");
+        stack.sort_by("a");
+        assert_eq!(stack.order_by(&HashMap::new()), r#" ORDER BY "a" ASC "#);
+        stack.sort_by("a");
+        assert_eq!(stack.order_by(&HashMap::new()), r#" ORDER BY "a" DESC "#);
+        stack.sort_by("b");
+        assert_eq!(stack.order_by(&HashMap::new()), r#" ORDER BY "b" ASC "#);
+    }
+
+    #[test]
+    fn add_keys_with_own_direction() {
+        let mut stack = SortStack::default();
+        stack.sort_by("a");
+        stack.add("b");
+        stack.add("b");
+        stack.add("c");
+        assert_eq!(
+            stack.order_by(&HashMap::new()),
+            r#" ORDER BY "a" ASC, "b" DESC, "c" ASC "#
+        );
+        assert_eq!(stack.indicator("b"), Some("▼2".to_string()));
+        assert_eq!(stack.indicator("d"), None);
+
+        // with several keys sort_by starts over with only the current column
+        stack.sort_by("c");
+        assert_eq!(stack.order_by(&HashMap::new()), r#" ORDER BY "c" ASC "#);
+    }
+
+    #[test]
+    fn order_by_with_collation() {
+        let mut stack = SortStack::default();
+        stack.sort_by("a");
+        stack.add("b");
+        let collations = HashMap::from([
+            ("a".to_string(), Collation::Natural),
+            ("b".to_string(), Collation::Binary),
+        ]);
+        assert_eq!(
+            stack.order_by(&collations),
+            r#" ORDER BY "a" COLLATE "NATURAL" ASC, "b" ASC "#
+        );
+    }
+
+    #[test]
+    fn remove_rename_and_clear() {
+        let mut stack = SortStack::default();
+        stack.sort_by("a");
+        stack.add("b");
+        stack.rename("a", "x");
+        stack.remove("b");
+        assert_eq!(stack.order_by(&HashMap::new()), r#" ORDER BY "x" ASC "#);
+        stack.clear();
+        assert_eq!(stack.order_by(&HashMap::new()), "");
+    }
+}
@@ -0,0 +1,235 @@
+use std::io::Write;
+use std::path::Path;
+
+use rusqlite::Connection;
+
+use crate::error::AppResult;
+
+use super::compression;
+use super::convert::Exportable;
+use super::datarow::DataItem;
+use super::metadata::export_columns;
+
+/// Rows in each INSERT statement, big enough to load fast and small enough for the default
+/// packet size of MySQL.
+const INSERT_BATCH_SIZE: usize = 500;
+
+/// Tables that are saved as SQL: a CREATE TABLE with the column types followed by batched
+/// INSERTs, in a transaction. The statements are plain enough for SQLite, Postgres and MySQL in
+/// ANSI_QUOTES mode. The `id` column that we add on import is left out.
+#[derive(Debug, Clone)]
+pub struct SqlDump {
+    pub table_names: Vec<String>,
+}
+
+impl SqlDump {
+    pub fn new(table_names: Vec<String>) -> Self {
+        Self { table_names }
+    }
+
+    pub fn write<W: Write>(&self, connection: &Connection, mut writer: W) -> AppResult<()> {
+        writeln!(writer, "BEGIN;")?;
+        for table_name in &self.table_names {
+            let columns = export_columns(connection, table_name)?;
+            // a table needs at least one column
+            if columns.is_empty() {
+                continue;
+            }
+            let column_types = columns
+                .iter()
+                .map(|(name, declared_type)| {
+                    format!(
+                        r#"  {} {}"#,
+                        quote_identifier(name),
+                        column_type(declared_type)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(",\n");
+            let column_list = columns
+                .iter()
+                .map(|(name, _)| quote_identifier(name))
+                .collect::<Vec<_>>()
+                .join(", ");
+            // numbers in a TEXT column are quoted, Postgres won't insert them otherwise
+            let is_text: Vec<bool> = columns
+                .iter()
+                .map(|(_, declared_type)| column_type(declared_type) == "TEXT")
+                .collect();
+            let table = quote_identifier(table_name);
+            writeln!(writer)?;
+            writeln!(writer, "DROP TABLE IF EXISTS {table};")?;
+            writeln!(writer, "CREATE TABLE {table} (\n{column_types}\n);")?;
+
+            let mut stmt = connection.prepare(&format!(
+                "SELECT {column_list} FROM {table} ORDER BY rowid;"
+            ))?;
+            let mut rows = stmt.query([])?;
+            let mut batch = Vec::with_capacity(INSERT_BATCH_SIZE);
+            loop {
+                let row = rows.next()?;
+                if let Some(row) = row {
+                    let values = (0..columns.len())
+                        .map(|i| {
+                            let item = DataItem::from(row.get_ref(i)?);
+                            Ok(match item {
+                                DataItem::Integer(_) | DataItem::Float(_) if is_text[i] => {
+                                    sql_literal(&DataItem::Text(item.into()))
+                                }
+                                _ => sql_literal(&item),
+                            })
+                        })
+                        .collect::<rusqlite::Result<Vec<_>>>()?;
+                    batch.push(format!("({})", values.join(", ")));
+                }
+                if batch.len() == INSERT_BATCH_SIZE || (row.is_none() && !batch.is_empty()) {
+                    writeln!(
+                        writer,
+                        "INSERT INTO {table} ({column_list}) VALUES\n{};",
+                        batch.join(",\n")
+                    )?;
+                    batch.clear();
+                }
+                if row.is_none() {
+                    break;
+                }
+            }
+        }
+        writeln!(writer, "\nCOMMIT;")?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Exportable for SqlDump {
+    /// Writes the dump, compressed if the name ends in `.gz`, `.zst` or `.bz2`.
+    fn export_to_db(&self, path: &Path, connection: &Connection) -> AppResult<()> {
+        let mut writer = compression::create(path)?;
+        self.write(connection, &mut writer)?;
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!(r#""{}""#, name.replace('"', r#""""#))
+}
+
+/// A column without a declared type, e.g. a mixed column from a spreadsheet, becomes TEXT since
+/// other databases need a type. REAL is a 4 byte float in Postgres, SQLite's REAL has 8 bytes.
+fn column_type(declared_type: &str) -> &str {
+    match declared_type.to_uppercase().as_str() {
+        "" | "TEXT" => "TEXT",
+        "REAL" => "DOUBLE PRECISION",
+        _ => declared_type,
+    }
+}
+
+/// Infinite REALs are written as `'Infinity'`, which Postgres reads as a double, a bare `inf`
+/// would not parse anywhere.
+fn sql_literal(item: &DataItem) -> String {
+    match item {
+        DataItem::Text(text) => format!("'{}'", text.replace('\'', "''")),
+        DataItem::Integer(n) => n.to_string(),
+        DataItem::Float(x) if x.is_nan() => "'NaN'".to_string(),
+        DataItem::Float(x) if x.is_infinite() => {
+            format!("'{}Infinity'", if x.is_sign_negative() { "-" } else { "" })
+        }
+        DataItem::Float(x) => format!("{x:?}"),
+        DataItem::Blob(bytes) => {
+            let hex: String = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+            format!("X'{hex}'")
+        }
+        DataItem::Null => "NULL".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::metadata::set_key_column;
+
+    #[test]
+    fn sql_dump_round_trip_test() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                r#"CREATE TABLE "my people" (id INTEGER PRIMARY KEY, name TEXT, age INTEGER, score REAL, misc);
+                INSERT INTO "my people" (name, age, score, misc) VALUES
+                    ('o''neil', 31, 2.5, 'x'), (NULL, NULL, 1.0, 3);
+                CREATE TABLE empty (id INTEGER PRIMARY KEY, note TEXT);"#,
+            )
+            .unwrap();
+        set_key_column(&connection, "my people", "id").unwrap();
+        set_key_column(&connection, "empty", "id").unwrap();
+        let mut out = vec![];
+        SqlDump::new(vec!["my people".to_string(), "empty".to_string()])
+            .write(&connection, &mut out)
+            .unwrap();
+        let dump = String::from_utf8(out).unwrap();
+        assert!(dump.contains(
+            "CREATE TABLE \"my people\" (\n  \"name\" TEXT,\n  \"age\" INTEGER,\n  \"score\" DOUBLE PRECISION,\n  \"misc\" TEXT\n);"
+        ));
+        assert!(dump.contains("('o''neil', 31, 2.5, 'x'),\n(NULL, NULL, 1.0, '3');"));
+
+        let copy = Connection::open_in_memory().unwrap();
+        copy.execute_batch(&dump).unwrap();
+        let rows: Vec<(Option<String>, Option<i64>, f64)> = copy
+            .prepare(r#"SELECT name, age, score FROM "my people" ORDER BY rowid;"#)
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (Some("o'neil".to_string()), Some(31), 2.5),
+                (None, None, 1.0)
+            ]
+        );
+        let empty: i64 = copy
+            .query_row("SELECT COUNT(*) FROM empty;", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(empty, 0);
+    }
+
+    #[test]
+    fn sql_literal_test() {
+        assert_eq!(sql_literal(&DataItem::Float(2.0)), "2.0");
+        assert_eq!(sql_literal(&DataItem::Float(f64::INFINITY)), "'Infinity'");
+        assert_eq!(
+            sql_literal(&DataItem::Float(f64::NEG_INFINITY)),
+            "'-Infinity'"
+        );
+        assert_eq!(sql_literal(&DataItem::Float(f64::NAN)), "'NaN'");
+
+        let blob = Connection::open_in_memory()
+            .unwrap()
+            .query_row("SELECT X'00FF';", [], |row| {
+                Ok(DataItem::from(row.get_ref(0)?))
+            })
+            .unwrap();
+        assert_eq!(blob.to_string(), "<blob 2 bytes>");
+        assert_eq!(sql_literal(&blob), "X'00FF'");
+
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE t (id INTEGER PRIMARY KEY, x REAL);
+                INSERT INTO t (x) VALUES (1e999), (-1e999);",
+            )
+            .unwrap();
+        set_key_column(&connection, "t", "id").unwrap();
+        let mut out = vec![];
+        SqlDump::new(vec!["t".to_string()])
+            .write(&connection, &mut out)
+            .unwrap();
+        let dump = String::from_utf8(out).unwrap();
+        assert!(dump.contains("('Infinity'),\n('-Infinity');"));
+        Connection::open_in_memory()
+            .unwrap()
+            .execute_batch(&dump)
+            .unwrap();
+    }
+}
@@ -1,18 +1,140 @@
-use crate::error::AppResult;
+use crate::error::{AppError, AppResult};
+
+/// Checks that a table or column name can be used in our queries, where names are put between
+/// double quotes.
+pub(crate) fn validate_identifier(name: &str) -> AppResult<()> {
+    let reason = if name.trim().is_empty() {
+        "a name can't be empty"
+    } else if name.contains('"') {
+        "a name can't contain a double quote"
+    } else if name.to_lowercase().starts_with("sqlite_") {
+        "names that start with sqlite_ are reserved by SQLite"
+    } else {
+        return Ok(());
+    };
+    Err(AppError::InvalidIdentifier {
+        name: name.to_string(),
+        reason: reason.to_string(),
+    })
+}
+
+/// The name of the key column we add to an imported table, `id` unless the file has a column
+/// with that name already. SQLite compares column names without case.
+pub(crate) fn key_column_name<S: AsRef<str>>(headers: &[S]) -> String {
+    let mut name = "id".to_string();
+    while headers
+        .iter()
+        .any(|header| header.as_ref().eq_ignore_ascii_case(&name))
+    {
+        name.insert(0, '_');
+    }
+    name
+}
 
 pub(super) mod build {
-    pub fn exact_search_query(
-        ordering: &str,
-        search_column: &str,
-        current_row: u32,
-        table_name: &str,
+    use crate::model::datarow::DataItem;
+    use crate::model::sort::OrderKey;
+
+    /// Rows where any of the columns matches the regex bound as ?1.
+    pub(crate) fn search_condition(search_columns: &[String]) -> String {
+        search_columns
+            .iter()
+            .map(|column| format!(r#"regexp(?1, "{column}")"#))
+            .collect::<Vec<_>>()
+            .join(" OR ")
+    }
+
+    /// The rows that come after the anchor row when ordered by `keys`. The anchor's value for each
+    /// key is bound as ?1, ?2, ... in the same order as `keys`. The last key has to be unique and
+    /// never NULL, e.g. the rowid, so that no two rows are equal.
+    pub(crate) fn keyset_condition(keys: &[OrderKey], anchor: &[DataItem]) -> String {
+        keyset_condition_from(keys, anchor, 1, true)
+    }
+
+    /// Whether the rows after the anchor end with all the rows where the first key is NULL, which
+    /// is when the first key descends from a value. `keyset_condition` then needs an OR that keeps
+    /// sqlite from using an index on the first key.
+    pub(crate) fn nulls_come_last(keys: &[OrderKey], anchor: &[DataItem]) -> bool {
+        match (keys.first(), anchor.first()) {
+            (Some(key), Some(value)) => !key.is_asc && *value != DataItem::Null,
+            _ => false,
+        }
+    }
+
+    /// Like `keyset_condition`, without the rows where the first key is NULL.
+    pub(crate) fn keyset_condition_not_null(keys: &[OrderKey], anchor: &[DataItem]) -> String {
+        keyset_condition_from(keys, anchor, 1, false)
+    }
+
+    /// The anchor row and the rows after it, with the anchor bound from ?`first_param` on. A
+    /// NULL comparison counts as false, so the condition can be negated.
+    pub(crate) fn at_or_after_condition(
+        keys: &[OrderKey],
+        anchor: &[DataItem],
+        first_param: usize,
     ) -> String {
-        let query = format!(
-            r#"SELECT rownum FROM
-			(SELECT ROW_NUMBER() OVER ({ordering}) AS rownum, "{search_column}" FROM "{table_name}")
-			WHERE "{search_column}" = ? AND rownum > {current_row} LIMIT 1;"#
-        );
-        query
+        let rowid_param = first_param + keys.len() - 1;
+        format!(
+            "(rowid = ?{rowid_param} OR COALESCE(({}), 0))",
+            keyset_condition_from(keys, anchor, first_param, true)
+        )
+    }
+
+    /// Like `keyset_condition`, with the anchor bound as ?`first_param`, ?`first_param + 1`, ...
+    /// Without `first_nulls` the rows where the first key is NULL are left out.
+    fn keyset_condition_from(
+        keys: &[OrderKey],
+        anchor: &[DataItem],
+        first_param: usize,
+        first_nulls: bool,
+    ) -> String {
+        let mut alternatives = vec![];
+        for (i, (key, value)) in keys.iter().zip(anchor).enumerate() {
+            let param = first_param + i;
+            // sqlite puts NULL first when ascending and last when descending
+            let after = match (value, key.is_asc) {
+                (DataItem::Null, true) => format!(r#""{}" IS NOT NULL"#, key.column),
+                (DataItem::Null, false) => continue,
+                (_, true) => format!("{} > ?{param}", key.expression()),
+                (_, false) if i == 0 && !first_nulls => {
+                    format!("{} < ?{param}", key.expression())
+                }
+                (_, false) => format!(
+                    r#"({} < ?{param} OR "{}" IS NULL)"#,
+                    key.expression(),
+                    key.column
+                ),
+            };
+            let mut terms: Vec<String> = keys[..i]
+                .iter()
+                .enumerate()
+                .map(|(j, key)| format!(r#""{}" IS ?{}"#, key.column, first_param + j))
+                .collect();
+            terms.push(after);
+            alternatives.push(format!("({})", terms.join(" AND ")));
+        }
+        let alternatives = alternatives.join(" OR ");
+        // the same condition, but with a range on the first key that an index can be used for
+        let bound = match (keys.first(), anchor.first()) {
+            (Some(key), Some(value)) if keys.len() > 1 => match (value, key.is_asc) {
+                (DataItem::Null, true) => None,
+                (DataItem::Null, false) => Some(format!(r#""{}" IS NULL"#, key.column)),
+                (_, true) => Some(format!("{} >= ?{first_param}", key.expression())),
+                (_, false) if !first_nulls => {
+                    Some(format!("{} <= ?{first_param}", key.expression()))
+                }
+                (_, false) => Some(format!(
+                    r#"({} <= ?{first_param} OR "{}" IS NULL)"#,
+                    key.expression(),
+                    key.column
+                )),
+            },
+            _ => None,
+        };
+        match bound {
+            Some(bound) => format!("{bound} AND ({alternatives})"),
+            None => alternatives,
+        }
     }
 
     pub(crate) fn text_to_int(table_name: &str, column: &str) -> String {
@@ -60,10 +182,42 @@ pub(super) mod build {
         format!(r#"ALTER TABLE "{table_name}" DROP COLUMN "{column}";"#)
     }
 
+    pub(crate) fn delete_row_query(table_name: &str) -> String {
+        format!(r#"DELETE FROM "{table_name}" WHERE rowid = ?;"#)
+    }
+
     pub(crate) fn delete_table_query(table_name: &str) -> String {
         format!(r#"DROP TABLE "{table_name}";"#)
     }
 
+    /// Copies a virtual table into a real one with the same name, empty fields become NULL like
+    /// they do when a CSV file is imported.
+    pub(crate) fn materialize_query(table_name: &str, headers: &[String]) -> String {
+        let temp_table_name = "__dataman_materialize";
+        let key_column = super::key_column_name(headers);
+        let column_types = headers
+            .iter()
+            .map(|header| format!(r#""{header}" TEXT"#))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let columns = headers
+            .iter()
+            .map(|header| format!(r#""{header}""#))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let values = headers
+            .iter()
+            .map(|header| format!(r#"NULLIF("{header}", '')"#))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            r#"CREATE TABLE "{temp_table_name}" ("{key_column}" INTEGER PRIMARY KEY, {column_types});
+INSERT INTO "{temp_table_name}" ({columns}) SELECT {values} FROM "{table_name}" ORDER BY rowid;
+DROP TABLE "{table_name}";
+ALTER TABLE "{temp_table_name}" RENAME TO "{table_name}";"#
+        )
+    }
+
     pub(crate) fn rename_column_query(table_name: &str, column: &str, new_column: &str) -> String {
         format!(r#"ALTER TABLE "{table_name}" RENAME COLUMN "{column}" TO "{new_column}";"#)
     }
@@ -77,4 +231,69 @@ pub(super) mod build {
     pub fn histogram_query(column: &str, table_name: &str) -> String {
         String::new()
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::model::collation::Collation;
+
+        fn key(column: &str, is_asc: bool, collation: Collation) -> OrderKey {
+            OrderKey {
+                column: column.to_string(),
+                is_asc,
+                collation,
+            }
+        }
+
+        #[test]
+        fn keyset_condition_test() {
+            let keys = vec![
+                key("name", true, Collation::Natural),
+                key("age", false, Collation::Binary),
+                OrderKey::rowid(),
+            ];
+            let anchor = vec![
+                DataItem::Text("john".to_string()),
+                DataItem::Integer(35),
+                DataItem::Integer(2),
+            ];
+            assert_eq!(
+                keyset_condition(&keys, &anchor),
+                r#""name" COLLATE "NATURAL" >= ?1 AND (("name" COLLATE "NATURAL" > ?1) OR ("name" IS ?1 AND ("age" < ?2 OR "age" IS NULL)) OR ("name" IS ?1 AND "age" IS ?2 AND "rowid" > ?3))"#
+            );
+            assert_eq!(
+                keyset_condition(&[OrderKey::rowid()], &[DataItem::Integer(2)]),
+                r#"("rowid" > ?1)"#
+            );
+        }
+
+        #[test]
+        fn keyset_condition_not_null_test() {
+            let keys = vec![key("name", false, Collation::Binary), OrderKey::rowid()];
+            let anchor = vec![DataItem::Text("john".to_string()), DataItem::Integer(2)];
+            assert!(nulls_come_last(&keys, &anchor));
+            assert_eq!(
+                keyset_condition_not_null(&keys, &anchor),
+                r#""name" <= ?1 AND (("name" < ?1) OR ("name" IS ?1 AND "rowid" > ?2))"#
+            );
+            assert!(!nulls_come_last(
+                &keys,
+                &[DataItem::Null, DataItem::Integer(2)]
+            ));
+        }
+
+        #[test]
+        fn keyset_condition_with_null_anchor_test() {
+            let keys = vec![
+                key("a", true, Collation::Binary),
+                key("b", false, Collation::Binary),
+                OrderKey::rowid(),
+            ];
+            let anchor = vec![DataItem::Null, DataItem::Null, DataItem::Integer(7)];
+            assert_eq!(
+                keyset_condition(&keys, &anchor),
+                r#"("a" IS NOT NULL) OR ("a" IS ?1 AND "b" IS ?2 AND "rowid" > ?3)"#
+            );
+        }
+    }
 }
@@ -1,6 +1,7 @@
 use std::{
     fmt::{Debug, Display},
-    io::{stdout, Read, Stdout, Write},
+    fs::OpenOptions,
+    io::{stdout, IsTerminal, Read, Stdout, Write},
     iter::Sum,
     panic,
 };
@@ -13,10 +14,10 @@ use crossterm::{
 };
 use log::info;
 use ratatui::{
-    prelude::{Backend, Constraint, CrosstermBackend, Layout},
+    prelude::{Backend, Constraint, CrosstermBackend, Layout, Rect},
     style::{Color, Modifier, Style, Stylize},
     text::{Line, Span},
-    widgets::{Block, Borders, Cell, Paragraph, Row, Table},
+    widgets::{Block, Borders, Cell, Clear, Paragraph, Row, Table, Wrap},
     Frame, Terminal,
 };
 
@@ -24,26 +25,42 @@ use crate::{
     app_error_other,
     controller::{self, controller_impl::Controller},
 };
-use crate::{controller::input::InputMode, model::datarow::DataTable};
+use crate::{
+    controller::input::InputMode, controller::message::Severity, model::datarow::DataTable,
+};
 use crate::{
     error::{AppError, AppResult},
-    model::database::Database,
+    model::{csv_options::CsvPreview, database::Database},
 };
 
+/// How many rows we fetch for the table, more than fit on any screen.
+pub(crate) const PAGE_SIZE: u32 = 100;
+
 pub struct TUI {
-    terminal: Terminal<CrosstermBackend<Stdout>>,
+    terminal: Terminal<CrosstermBackend<Box<dyn Write>>>,
 }
 
 impl TUI {
     pub fn new() -> Self {
-        let stdout = std::io::stdout();
-        let backend = CrosstermBackend::new(stdout);
+        let backend = CrosstermBackend::new(Self::output());
         let terminal = Terminal::new(backend).unwrap();
         enable_raw_mode().unwrap();
         Self { terminal }
     }
+    /// Stdout, or the terminal itself when stdout is piped to another program.
+    fn output() -> Box<dyn Write> {
+        if stdout().is_terminal() {
+            return Box::new(stdout());
+        }
+        match OpenOptions::new().write(true).open("/dev/tty") {
+            Ok(tty) => Box::new(tty),
+            Err(_) => Box::new(stdout()),
+        }
+    }
+
     pub fn get_table_height() -> AppResult<u32> {
-        let height = (crossterm::terminal::size()?.1 - 4) as u32;
+        // the borders and header of the table, the status line and the message line
+        let height = (crossterm::terminal::size()?.1 - 5) as u32;
         Ok(height)
     }
 
@@ -52,7 +69,8 @@ impl TUI {
             self.terminal.backend_mut(),
             terminal::Clear(terminal::ClearType::All),
         )?;
-        print!("\x1B[2J\x1B[1;1H");
+        write!(self.terminal.backend_mut(), "\x1B[2J\x1B[1;1H")?;
+        Write::flush(self.terminal.backend_mut())?;
         terminal::disable_raw_mode()?;
         Ok(())
     }
@@ -70,6 +88,76 @@ impl TUI {
         Ok(())
     }
 
+    /// The import preview, the rows as they would be imported with the options above them. The
+    /// selected column is the one `t` changes the type of.
+    pub fn draw_import_preview(&mut self, preview: &CsvPreview, column: usize) -> AppResult<()> {
+        self.terminal.draw(|f| {
+            let rects = Layout::default()
+                .direction(ratatui::prelude::Direction::Vertical)
+                .constraints([
+                    Constraint::Length(3),
+                    Constraint::Max(1000),
+                    Constraint::Length(1),
+                ])
+                .split(f.size());
+            let options = &preview.options;
+            let yes_no = |yes: bool| if yes { "yes" } else { "no" };
+            let settings = Line::from(format!(
+                "delimiter: {} (d)  header row: {} (h)  encoding: {} (e)  lenient: {} (l)",
+                options.delimiter_name(),
+                yes_no(options.has_headers),
+                options.encoding.name(),
+                yes_no(options.lenient),
+            ));
+            let title = format!("Import {}", preview.path.display());
+            let paragraph =
+                Paragraph::new(settings).block(Block::default().borders(Borders::ALL).title(title));
+            f.render_widget(paragraph, rects[0]);
+
+            let selected = Style::default().add_modifier(Modifier::BOLD).fg(Color::Red);
+            let header = Row::new(preview.headers.iter().enumerate().map(|(i, name)| {
+                let text = format!("{name}\n{}", options.column_type(i).as_sql());
+                if i == column {
+                    Cell::from(text).style(selected)
+                } else {
+                    Cell::from(text)
+                }
+            }))
+            .height(2);
+            let rows = preview
+                .rows
+                .iter()
+                .map(|row| Row::new(row.iter().map(|value| Cell::from(value.as_str()))));
+            let widths = preview.headers.iter().enumerate().map(|(i, name)| {
+                let longest = preview
+                    .rows
+                    .iter()
+                    .filter_map(|row| row.get(i))
+                    .map(|value| value.chars().count())
+                    .chain([name.chars().count(), 7])
+                    .max()
+                    .unwrap_or_default();
+                Constraint::Min(longest.min(40) as u16)
+            });
+            let table = Table::new(rows, widths).header(header).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!("First {} rows", preview.rows.len())),
+            );
+            f.render_widget(table, rects[1]);
+
+            let help = match &preview.error {
+                Some(error) => Line::from(Span::styled(error.as_str(), severity_style(Severity::Error))),
+                None => Line::from(
+                    "left/right to pick a column, t to change its type, enter to import, esc to quit"
+                        .dark_gray(),
+                ),
+            };
+            f.render_widget(Paragraph::new(help), rects[2]);
+        })?;
+        Ok(())
+    }
+
     pub fn get_input() -> AppResult<controller::command::Command> {
         if let Event::Key(key) = event::read()? {
             let command = controller::command::Command::from(key);
@@ -109,11 +197,16 @@ impl TUI {
                 vec![
                     Constraint::Max(1000),
                     Constraint::Length(1),
+                    Constraint::Length(1),
                     Constraint::Length(3),
                 ]
             }
             InputMode::Normal | InputMode::Abort | InputMode::Finish => {
-                vec![Constraint::Max(1000), Constraint::Length(1)]
+                vec![
+                    Constraint::Max(1000),
+                    Constraint::Length(1),
+                    Constraint::Length(1),
+                ]
             }
             InputMode::ExternalEditor => todo!(),
         };
@@ -122,10 +215,13 @@ impl TUI {
             .constraints(constraints.as_slice())
             .split(f.size());
 
-        let table_name = database.get_current_table_name()?;
-
+        // while a job runs on the worker thread we show what we have instead of asking the database
+        let table_name = match &database.job {
+            Some(job) => job.table_name.clone(),
+            None => database.get_current_table_name()?,
+        };
         let (headers, rows): DataTable =
-            database.get(100, database.slice.row_offset, table_name)?;
+            database.get(PAGE_SIZE, database.slice.row_offset, table_name.clone())?;
         let id_space: u16 = rows.iter().fold(0, |acc, row| {
             let id = row.first().unwrap().to_string().len() as u16;
             if id > acc {
@@ -145,30 +241,49 @@ impl TUI {
         // mark current header
 
         let header = Row::new(headers.iter().enumerate().map(|(i, h)| {
+            let h = match database.sort_stack.indicator(h) {
+                Some(indicator) => format!("{h} {indicator}"),
+                None => h.clone(),
+            };
             if current_header == i as u16 {
                 Cell::from(Span::styled(
-                    h.clone(),
+                    h,
                     Style::default().add_modifier(Modifier::BOLD).fg(Color::Red),
                 ))
             } else {
-                Cell::from(h.clone())
+                Cell::from(h)
             }
         }))
         .height(1);
 
         // draw border under header
+        let search = database.search.as_ref();
+        let match_style = Style::default().fg(Color::Black).bg(Color::Yellow);
         let tui_rows = rows.iter().map(|data_row| {
             let data_row = data_row
                 .iter()
-                .map(|item| Cell::from(item.clone()))
+                .zip(headers.iter())
+                .map(|(item, header)| {
+                    let cell = Cell::from(item.clone());
+                    if search.is_some_and(|search| search.is_match(header, item)) {
+                        cell.style(match_style)
+                    } else {
+                        cell
+                    }
+                })
                 .collect::<Vec<_>>();
             Row::new(data_row).height(1)
         });
         let selected_style = Style::default().add_modifier(Modifier::UNDERLINED);
-        let table_name = database.get_current_table_name()?;
+        // like vim, [+] means there are changes that haven't been saved
+        let title = if database.modified.contains(&table_name) {
+            format!("{table_name} [+]")
+        } else {
+            table_name
+        };
         let t = Table::new(tui_rows, constraints)
             .header(header)
-            .block(Block::default().borders(Borders::ALL).title(table_name))
+            .block(Block::default().borders(Borders::ALL).title(title))
             .highlight_style(selected_style)
             // .highlight_symbol(">> ")
             .bg(Color::Black);
@@ -176,22 +291,75 @@ impl TUI {
 
         let a = database.header_idx;
         let row = database.slice.table_state.selected().unwrap_or(0);
-        let total_rows = database.count_rows().unwrap_or(0);
+        let total_rows = if database.is_busy() {
+            database.cached_row_count()
+        } else {
+            database.count_rows()
+        }
+        .unwrap_or(0);
         let rowid = rows
             .get(row)
             .map(|el| el.first().unwrap().to_string())
             .unwrap_or("xxx".to_owned());
         let offset = database.slice.table_state.offset();
-        let last_command = database.last_command.command.to_string();
+        let last_command = &database.last_command.command;
         let table_height = rects[0].height;
+        let search_status = database
+            .search
+            .as_ref()
+            .map(|search| format!(", {}", search.status()))
+            .unwrap_or_default();
+        let job_status = database
+            .job
+            .as_ref()
+            .map(|job| format!(", {}", job.status()))
+            .unwrap_or_default();
+        let import_status = database
+            .import
+            .as_ref()
+            .map(|import| format!(", {}", import.status()))
+            .unwrap_or_default();
         let text = vec![Line::from(vec![Span::raw(format!(
             // "last command: {last_command} current header: {a} selected: {b} offset: {offset} "
-            "row: {row}, total rows: {total_rows},  last command: {last_command}, height: {table_height}",
+            "row: {row}, total rows: {total_rows},  last command: {last_command}, height: {table_height}{search_status}{job_status}{import_status}",
         ))])];
         let paragraph = Paragraph::new(text);
 
         f.render_widget(paragraph, rects[1]);
 
+        let message = database.last_command.message.as_deref().unwrap_or_default();
+        let style = severity_style(database.last_command.severity);
+        let message_line = Line::from(vec![
+            Span::styled(message, style),
+            Span::raw(format!(
+                "  ({} messages, H to read them)",
+                database.messages.len()
+            ))
+            .dark_gray(),
+        ]);
+        f.render_widget(Paragraph::new(message_line), rects[2]);
+
+        if database.messages.open {
+            TUI::draw_message_history(f, database);
+        }
+
+        if let Some(confirmation) = &database.confirmation {
+            let text = vec![
+                Line::from(confirmation.question.as_str()),
+                Line::from(""),
+                Line::from("y to confirm, any other key to cancel".dark_gray()),
+            ];
+            let paragraph = Paragraph::new(text).wrap(Wrap { trim: true }).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(Color::Red))
+                    .title(confirmation.command.to_string()),
+            );
+            let area = centered_rect(60, 5, f.size());
+            f.render_widget(Clear, area);
+            f.render_widget(paragraph, area);
+        }
+
         if database.input_mode_state_machine.get_state() == InputMode::Editing {
             let title = database.last_command.command.to_string();
             let paragraph = Paragraph::new(database.input.as_str())
@@ -203,10 +371,10 @@ impl TUI {
                 );
 
             f.set_cursor(
-                rects[2].x + database.character_index as u16 + 1,
-                rects[2].y + 1,
+                rects[3].x + database.character_index as u16 + 1,
+                rects[3].y + 1,
             );
-            f.render_widget(paragraph, rects[2]);
+            f.render_widget(paragraph, rects[3]);
             // match app.input_mode {
             //     InputMode::Normal =>
             //         // Hide the cursor. `Frame` does this by default, so we don't need to do anything here
@@ -229,16 +397,65 @@ impl TUI {
         Ok(())
     }
 
+    /// The newest messages at the bottom, `messages.scroll` of them are scrolled out of view.
+    fn draw_message_history(f: &mut Frame, database: &Database) {
+        let messages = &database.messages;
+        let area = centered_rect(80, f.size().height * 3 / 5, f.size());
+        let visible = area.height.saturating_sub(2) as usize;
+        let mut lines: Vec<Line> = messages
+            .messages()
+            .rev()
+            .skip(messages.scroll)
+            .take(visible)
+            .map(|message| {
+                let style = severity_style(message.severity);
+                Line::from(vec![
+                    Span::raw(format!("{} ", message.time.format("%H:%M:%S"))).dark_gray(),
+                    Span::styled(message.text.as_str(), style),
+                ])
+            })
+            .collect();
+        lines.reverse();
+        let title = format!(
+            "Messages {}/{} (up/down to scroll, esc to close)",
+            messages.len() - messages.scroll.min(messages.len()),
+            messages.len()
+        );
+        let paragraph =
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title));
+        f.render_widget(Clear, area);
+        f.render_widget(paragraph, area);
+    }
+
     pub fn install_panic_hook() {
         let original_hook = panic::take_hook();
         panic::set_hook(Box::new(move |panic_info| {
-            stdout().execute(LeaveAlternateScreen).unwrap();
+            Self::output().execute(LeaveAlternateScreen).unwrap();
             disable_raw_mode().unwrap();
             original_hook(panic_info);
         }));
     }
 }
 
+fn severity_style(severity: Severity) -> Style {
+    match severity {
+        Severity::Info => Style::default(),
+        Severity::Warning => Style::default().fg(Color::Yellow),
+        Severity::Error => Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+    }
+}
+
+/// A rect in the middle of `area`, `percent_x` of its width and `height` rows high.
+fn centered_rect(percent_x: u16, height: u16, area: Rect) -> Rect {
+    let width = area.width * percent_x / 100;
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + area.height.saturating_sub(height) / 2,
+        width,
+        height: height.min(area.height),
+    }
+}
+
 impl Default for TUI {
     fn default() -> Self {
         Self::new()